@@ -0,0 +1,211 @@
+use crate::options::TraceMode;
+
+/// Pair of textures which frames are averaged into, each frame reads the
+/// running average from one texture and writes the updated average to the other
+pub struct Accumulation {
+    pub textures: [wgpu::Texture; 2],
+    pub views: [wgpu::TextureView; 2],
+    pub layout: wgpu::BindGroupLayout,
+    pub bind_groups: [wgpu::BindGroup; 2],
+    /// Layout for compute tracing, which also writes the other texture as
+    /// storage. Only created when tracing with compute, as WebGL has no
+    /// storage textures
+    pub compute_layout: Option<wgpu::BindGroupLayout>,
+    pub compute_bind_groups: Option<[wgpu::BindGroup; 2]>,
+    /// Index of the texture holding the current running average
+    pub current: usize,
+    /// Number of frames averaged so far
+    pub frame: u32,
+}
+
+impl Accumulation {
+    /// Format of the accumulation textures, full precision to average many frames
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+    /// Create a new Accumulation with textures of the given size, for tracing
+    /// with the given mode
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, trace: TraceMode) -> Self {
+        // Create layout from entries
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+            label: Some("accumulation_binding"),
+        });
+
+        // Create compute layout, reading the running average and writing the next
+        let compute_layout = (trace == TraceMode::Compute).then(|| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: Accumulation::FORMAT,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("accumulation_compute_binding"),
+            })
+        });
+
+        let (textures, views, bind_groups, compute_bind_groups) =
+            Accumulation::create_textures(device, &layout, compute_layout.as_ref(), width, height);
+
+        Self {
+            textures,
+            views,
+            layout,
+            bind_groups,
+            compute_layout,
+            compute_bind_groups,
+            current: 0,
+            frame: 0,
+        }
+    }
+
+    /// Create both textures along with their views and bind groups, and the
+    /// compute bind groups if there is a compute layout
+    #[allow(clippy::type_complexity)]
+    fn create_textures(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        compute_layout: Option<&wgpu::BindGroupLayout>,
+        width: u32,
+        height: u32,
+    ) -> (
+        [wgpu::Texture; 2],
+        [wgpu::TextureView; 2],
+        [wgpu::BindGroup; 2],
+        Option<[wgpu::BindGroup; 2]>,
+    ) {
+        let mut usage =
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+        if compute_layout.is_some() {
+            usage |= wgpu::TextureUsages::STORAGE_BINDING;
+        }
+        let textures = [0, 1].map(|i| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(&format!("accumulation_texture_{i}")),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Accumulation::FORMAT,
+                usage,
+                view_formats: &[],
+            })
+        });
+
+        let views =
+            [0, 1].map(|i| textures[i].create_view(&wgpu::TextureViewDescriptor::default()));
+
+        // Create a bind group reading from each texture
+        let bind_groups = [0, 1].map(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&views[i]),
+                }],
+                label: Some(&format!("accumulation_group_{i}")),
+            })
+        });
+
+        // Create a compute bind group reading from each texture and writing the other
+        let compute_bind_groups = compute_layout.map(|compute_layout| {
+            [0, 1].map(|i| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: compute_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&views[i]),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&views[1 - i]),
+                        },
+                    ],
+                    label: Some(&format!("accumulation_compute_group_{i}")),
+                })
+            })
+        });
+
+        (textures, views, bind_groups, compute_bind_groups)
+    }
+
+    /// Recreate the textures at a new size, discarding the running average
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (
+            self.textures,
+            self.views,
+            self.bind_groups,
+            self.compute_bind_groups,
+        ) = Accumulation::create_textures(
+            device,
+            &self.layout,
+            self.compute_layout.as_ref(),
+            width,
+            height,
+        );
+        self.current = 0;
+        self.reset();
+    }
+
+    /// Restart averaging from the next frame
+    pub fn reset(&mut self) {
+        self.frame = 0;
+    }
+
+    /// Bind group reading the current running average
+    pub fn read_group(&self) -> &wgpu::BindGroup {
+        &self.bind_groups[self.current]
+    }
+
+    /// Bind group reading the texture the next frame is written to
+    pub fn write_group(&self) -> &wgpu::BindGroup {
+        &self.bind_groups[1 - self.current]
+    }
+
+    /// Bind group for compute tracing, reading the current running average and
+    /// writing the texture the next frame is written to
+    pub fn compute_group(&self) -> Option<&wgpu::BindGroup> {
+        Some(&self.compute_bind_groups.as_ref()?[self.current])
+    }
+
+    /// View of the texture the next frame is written to
+    pub fn write_view(&self) -> &wgpu::TextureView {
+        &self.views[1 - self.current]
+    }
+
+    /// Swap the textures once a frame has been written
+    pub fn swap(&mut self) {
+        self.current = 1 - self.current;
+        self.frame += 1;
+    }
+}
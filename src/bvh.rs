@@ -0,0 +1,300 @@
+use std::ops::Range;
+
+use cgmath::Vector3;
+
+use crate::geometry::Geometry;
+
+/// Axis aligned box around primitives
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Bounds {
+    /// Bounds containing nothing, growing it by anything gives that thing
+    pub fn empty() -> Self {
+        Self {
+            min: Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    /// Smallest bounds containing all the points
+    pub fn around(points: impl IntoIterator<Item = Vector3<f32>>) -> Self {
+        points.into_iter().fold(Bounds::empty(), |bounds, point| {
+            bounds.union(Bounds {
+                min: point,
+                max: point,
+            })
+        })
+    }
+
+    pub fn union(self, other: Bounds) -> Self {
+        let min = |a: f32, b: f32| a.min(b);
+        let max = |a: f32, b: f32| a.max(b);
+        Self {
+            min: self.min.zip(other.min, min),
+            max: self.max.zip(other.max, max),
+        }
+    }
+
+    pub fn contains(&self, other: &Bounds) -> bool {
+        (0..3).all(|axis| self.min[axis] <= other.min[axis] && other.max[axis] <= self.max[axis])
+    }
+
+    pub fn centroid(&self) -> Vector3<f32> {
+        (self.min + self.max) / 2.0
+    }
+
+    /// Area of the surface, which is proportional to the chance a random ray
+    /// through a larger box also hits this one
+    pub fn surface_area(&self) -> f32 {
+        let size = self.max - self.min;
+        if size.x < 0.0 {
+            return 0.0;
+        }
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+}
+
+/// Node of the hierarchy in the layout used by the GPU, nodes are stored
+/// depth first so the first child of an interior node comes right after it
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BvhNode {
+    pub min: [f32; 3],
+    /// Index of the first primitive of a leaf, or of the second child of an
+    /// interior node
+    pub start: u32,
+    pub max: [f32; 3],
+    /// Number of primitives in a leaf, zero for interior nodes
+    pub count: u32,
+}
+
+impl BvhNode {
+    pub fn bounds(&self) -> Bounds {
+        Bounds {
+            min: self.min.into(),
+            max: self.max.into(),
+        }
+    }
+}
+
+/// Primitive in a leaf of the hierarchy
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BvhPrimitive {
+    /// Kind of primitive, a Geometry constant
+    pub kind: u32,
+    /// Index of the primitive within its kind
+    pub index: u32,
+}
+
+/// Primitive being sorted into the hierarchy
+#[derive(Copy, Clone, Debug)]
+struct BuildPrimitive {
+    primitive: BvhPrimitive,
+    bounds: Bounds,
+    centroid: Vector3<f32>,
+}
+
+/// Bounding volume hierarchy over every bounded primitive, so rays only test
+/// the primitives in boxes they pass through. Planes are infinite and left out.
+/// The top level, starting at the first node, has the primitives placed
+/// directly in the world and the instances as leaves, each instanced model
+/// has its own hierarchy after it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    /// Primitives of each leaf, contiguous
+    pub primitives: Vec<BvhPrimitive>,
+    /// First node of each model's hierarchy, zero for models nothing places
+    pub roots: Vec<u32>,
+}
+
+impl Bvh {
+    /// Most levels a hierarchy can have. Traversal keeps at most one node for
+    /// each level on its stack, so this is also the size of that stack
+    pub const MAX_DEPTH: usize = 32;
+    /// Number of candidate splits along each axis
+    const BINS: usize = 16;
+    /// Leaves with more primitives are split even when the surface area
+    /// heuristic says not to
+    const MAX_LEAF_SIZE: usize = 4;
+    /// Cost of visiting a node relative to intersecting a primitive
+    const TRAVERSAL_COST: f32 = 1.0;
+
+    /// Build the hierarchies with binned splits chosen by the surface area
+    /// heuristic
+    pub fn new(geometry: &Geometry) -> Self {
+        let kinds = [
+            Geometry::SPHERE,
+            Geometry::DISC,
+            Geometry::BOX,
+            Geometry::QUAD,
+            Geometry::TRIANGLE,
+        ];
+        let build_primitive = |kind: u32, index: usize, bounds: Bounds| BuildPrimitive {
+            primitive: BvhPrimitive {
+                kind,
+                index: index as u32,
+            },
+            bounds,
+            centroid: bounds.centroid(),
+        };
+        let primitives = |ranges: [Range<usize>; Geometry::PRIMITIVE_KINDS]| {
+            kinds
+                .iter()
+                .flat_map(|&kind| {
+                    ranges[kind as usize].clone().map(move |index| {
+                        build_primitive(kind, index, geometry.primitive_bounds(kind, index))
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut models: Vec<Vec<BuildPrimitive>> = geometry
+            .models
+            .iter()
+            .map(|model| primitives(model.ranges.clone()))
+            .collect();
+        let mut top_level = primitives(std::array::from_fn(|kind| {
+            0..geometry.world_count(kind as u32)
+        }));
+        for (index, instance) in geometry.instances.iter().enumerate() {
+            // Instances of empty models can never be hit
+            let model = &models[instance.model as usize];
+            if !model.is_empty() {
+                let bounds = model
+                    .iter()
+                    .fold(Bounds::empty(), |bounds, p| bounds.union(p.bounds));
+                top_level.push(build_primitive(
+                    Geometry::INSTANCE,
+                    index,
+                    instance.bounds(bounds),
+                ));
+            }
+        }
+
+        let mut bvh = Bvh {
+            roots: vec![0; models.len()],
+            ..Default::default()
+        };
+        if top_level.is_empty() {
+            return bvh;
+        }
+        bvh.build(&mut top_level, 0);
+        for (index, model) in models.iter_mut().enumerate() {
+            let placed = top_level.iter().any(|p| {
+                p.primitive.kind == Geometry::INSTANCE
+                    && geometry.instances[p.primitive.index as usize].model == index as u32
+            });
+            if placed {
+                bvh.roots[index] = bvh.nodes.len() as u32;
+                bvh.build(model, 0);
+            }
+        }
+        bvh
+    }
+
+    /// Add a node for the primitives at depth, counted from zero at the root,
+    /// then, unless it is a leaf, its children
+    fn build(&mut self, primitives: &mut [BuildPrimitive], depth: usize) {
+        let bounds = primitives
+            .iter()
+            .fold(Bounds::empty(), |bounds, p| bounds.union(p.bounds));
+        let index = self.nodes.len();
+        self.nodes.push(BvhNode {
+            min: bounds.min.into(),
+            start: 0,
+            max: bounds.max.into(),
+            count: 0,
+        });
+
+        let split = match depth + 1 < Bvh::MAX_DEPTH {
+            true => Bvh::split(primitives, &bounds),
+            false => None,
+        };
+        let Some(middle) = split else {
+            self.nodes[index].start = self.primitives.len() as u32;
+            self.nodes[index].count = primitives.len() as u32;
+            self.primitives
+                .extend(primitives.iter().map(|p| p.primitive));
+            return;
+        };
+
+        let (first, second) = primitives.split_at_mut(middle);
+        self.build(first, depth + 1);
+        self.nodes[index].start = self.nodes.len() as u32;
+        self.build(second, depth + 1);
+    }
+
+    /// Partition the primitives at the cheapest split by centroid, returning
+    /// where the second half starts, or None if they are cheaper as a leaf
+    fn split(primitives: &mut [BuildPrimitive], bounds: &Bounds) -> Option<usize> {
+        let count = primitives.len();
+        if count == 1 {
+            return None;
+        }
+
+        let centroids = Bounds::around(primitives.iter().map(|p| p.centroid));
+        let extent = centroids.max - centroids.min;
+        let bin = |p: &BuildPrimitive, axis: usize| {
+            let offset = (p.centroid[axis] - centroids.min[axis]) / extent[axis];
+            ((offset * Bvh::BINS as f32) as usize).min(Bvh::BINS - 1)
+        };
+
+        // Cost of each split between bins is the chance of hitting each side
+        // times the primitives there
+        let mut best: Option<(f32, usize, usize)> = None;
+        for axis in (0..3).filter(|&axis| extent[axis] > 0.0) {
+            let mut bins = [(0, Bounds::empty()); Bvh::BINS];
+            for p in primitives.iter() {
+                let (bin_count, bin_bounds) = &mut bins[bin(p, axis)];
+                *bin_count += 1;
+                *bin_bounds = bin_bounds.union(p.bounds);
+            }
+
+            let mut below = [(0, Bounds::empty()); Bvh::BINS];
+            let mut total = (0, Bounds::empty());
+            for (i, &(bin_count, bin_bounds)) in bins.iter().enumerate() {
+                total = (total.0 + bin_count, total.1.union(bin_bounds));
+                below[i] = total;
+            }
+            let mut above = (0, Bounds::empty());
+            for i in (1..Bvh::BINS).rev() {
+                above = (above.0 + bins[i].0, above.1.union(bins[i].1));
+                let (below_count, below_bounds) = below[i - 1];
+                if below_count == 0 || above.0 == 0 {
+                    continue;
+                }
+                let cost = Bvh::TRAVERSAL_COST
+                    + (below_count as f32 * below_bounds.surface_area()
+                        + above.0 as f32 * above.1.surface_area())
+                        / bounds.surface_area();
+                if best.is_none_or(|(best_cost, ..)| cost < best_cost) {
+                    best = Some((cost, axis, i));
+                }
+            }
+        }
+
+        let too_large = count > Bvh::MAX_LEAF_SIZE;
+        let (axis, split_bin) = match best {
+            Some((cost, axis, split_bin)) if cost < count as f32 || too_large => (axis, split_bin),
+            Some(_) => return None,
+            // Primitives all centred in one place can't be told apart, large
+            // leaves of them are halved anyway
+            None => return too_large.then_some(count / 2),
+        };
+
+        let mut middle = 0;
+        for i in 0..count {
+            if bin(&primitives[i], axis) < split_bin {
+                primitives.swap(i, middle);
+                middle += 1;
+            }
+        }
+        Some(middle)
+    }
+}
@@ -1,3 +1,4 @@
+use cgmath::{InnerSpace, Vector3};
 use wgpu::util::DeviceExt;
 
 #[derive(Debug)]
@@ -9,32 +10,103 @@ pub struct CameraWithBuffers {
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Camera {
     pub screen_dimensions: [f32; 2],
+    /// Distance from the eye to the image plane
     pub focal: f32,
-    pub viewport_height : f32,
+    /// Vertical field of view in degrees
+    pub vfov: f32,
+    /// Position of the eye
     pub pos: [f32; 3],
     pub max_depth : i32,
-    _pad: [f32; 0],
+    /// Point the camera looks at
+    pub target: [f32; 3],
+    pub samples: u32,
+    /// Direction considered up, need not be perpendicular to the view
+    pub up: [f32; 3],
+    pub frame: u32,
+    /// Orthonormal basis pointing right, up and backwards from the view,
+    /// computed from pos, target and up by update_basis
+    pub u: [f32; 3],
+    /// Diameter of the lens, zero for a pinhole camera with no defocus blur
+    pub aperture: f32,
+    pub v: [f32; 3],
+    /// Distance from the eye to the plane which is in perfect focus
+    pub focus_dist: f32,
+    pub w: [f32; 3],
+    /// How samples are placed within each pixel, a Jitter as u32
+    pub jitter: u32,
+    /// Source of samples, a Sampler as u32
+    pub sampler: u32,
+    _pad: [f32; 3],
+}
+
+/// How samples are placed within each pixel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[repr(u32)]
+pub enum Jitter {
+    /// One random sample in each cell of a grid covering the pixel
+    #[default]
+    Stratified = 0,
+    /// Random samples anywhere in the pixel
+    Random = 1,
+}
+
+/// Source of the samples used for pixel jitter, lens and bounce directions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[repr(u32)]
+pub enum Sampler {
+    /// Independent random numbers
+    #[default]
+    Random = 0,
+    /// Scrambled Sobol sequence, which covers each pixel more evenly and
+    /// converges with less noise
+    Sobol = 1,
 }
 
 impl Camera {
-    pub fn new(device: &wgpu::Device, dimensions: [f32; 2]) -> CameraWithBuffers {
-        let camera = Camera {
+    /// Create a Camera with default settings for the given screen dimensions
+    pub fn with_dimensions(dimensions: [f32; 2]) -> Self {
+        Camera {
             screen_dimensions: dimensions,
             focal: 1.0,
-            viewport_height: 2.0,
+            vfov: 90.0,
             pos: [0.0, 0.0, 0.0],
             max_depth: 10,
+            target: [0.0, 0.0, -1.0],
+            samples: 4,
+            up: [0.0, 1.0, 0.0],
+            frame: 0,
+            u: [1.0, 0.0, 0.0],
+            aperture: 0.0,
+            v: [0.0, 1.0, 0.0],
+            focus_dist: 1.0,
+            w: [0.0, 0.0, 1.0],
+            jitter: Jitter::default() as u32,
+            sampler: Sampler::default() as u32,
             _pad: Default::default(),
-        };
+        }
+    }
+
+    /// Recompute the basis vectors after pos, target or up change
+    pub fn update_basis(&mut self) {
+        let w = (Vector3::from(self.pos) - Vector3::from(self.target)).normalize();
+        let u = Vector3::from(self.up).cross(w).normalize();
+        let v = w.cross(u);
+
+        self.u = u.into();
+        self.v = v.into();
+        self.w = w.into();
+    }
+
+    pub fn new(device: &wgpu::Device, camera: Camera) -> CameraWithBuffers {
 
         // Create layout entrys
         let entries = (0..=6)
             .map(|i| wgpu::BindGroupLayoutEntry {
                 binding: i,
-                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT | wgpu::ShaderStages::COMPUTE,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
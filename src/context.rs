@@ -1,17 +1,8 @@
-use std::{iter, mem};
+use std::iter;
 
 use anyhow::{Ok, Result};
-use futures::SinkExt;
-use instant::Instant;
-use wgpu::{util::DeviceExt, CommandEncoder, TextureView};
-
-use crate::{
-    camera::{Camera, CameraWithBuffers},
-    pipeline::Pipeline,
-    sphere::{self, Sphere, Spheres, SpheresWithBuffers},
-    thread_context::ThreadContext,
-    vertex::Vertex,
-};
+
+use crate::{options::Options, renderer::Renderer, scene::Scene, thread_context::ThreadContext};
 
 use super::window::Window;
 
@@ -20,46 +11,25 @@ pub struct GraphicsContext {
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
-    pub buffers: (wgpu::Buffer, wgpu::Buffer),
     pub thread: ThreadContext,
 
-    pub pipeline: Pipeline,
-    pub camera: CameraWithBuffers,
-    pub spheres: SpheresWithBuffers,
+    pub renderer: Renderer,
 }
 
 impl GraphicsContext {
-    /// Vertexes spanning screenspace
-    const VERTICES: &'static [Vertex] = &[
-        Vertex::xyz(1.0, 1.0, 0.0),
-        Vertex::xyz(1.0, -1.0, 0.0),
-        Vertex::xyz(-1.0, -1.0, 0.0),
-        Vertex::xyz(-1.0, 1.0, 0.0),
-    ];
-
-    /// Indices for vertexes
-    const INDICES: &'static [u16] = &[0, 3, 1, 1, 3, 2];
-
-    /// Create a new GraphicsContext
-    pub async fn new(window: &Window) -> Self {
+    /// Create a new GraphicsContext rendering a scene
+    pub async fn new(window: &Window, scene: &Scene, options: &Options) -> Self {
         let size = window.raw.inner_size();
 
         // Create a new backend instance
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            ..Default::default()
-        });
+        let instance = wgpu::Instance::new(options.instance_descriptor());
 
         // Create a new surface to render to
         let surface = unsafe { instance.create_surface(&window.raw) }.unwrap();
 
         // Create a new device adapter
         let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
+            .request_adapter(&options.adapter_options(Some(&surface)))
             .await
             .unwrap();
 
@@ -71,7 +41,7 @@ impl GraphicsContext {
                     #[cfg(target_arch = "wasm32")]
                     limits: wgpu::Limits::downlevel_webgl2_defaults(),
                     #[cfg(not(target_arch = "wasm32"))]
-                    limits: wgpu::Limits::default(),
+                    limits: Renderer::limits(),
                     label: None,
                 },
                 None,
@@ -100,73 +70,30 @@ impl GraphicsContext {
         };
         surface.configure(&device, &config);
 
-        let buffers = GraphicsContext::create_buffers(&device);
-        let camera = Camera::new(
+        let renderer = Renderer::new(
             &device,
+            &queue,
+            config.format,
             [
                 window.raw.inner_size().width as f32,
                 window.raw.inner_size().height as f32,
             ],
-        );
-
-        let spheres = Spheres {
-            spheres: vec![
-                Sphere {
-                    pos: [-0.4, 0.0, -2.0],
-                    radius: 0.4,
-                    colour: [1.0, 0.0, 0.0],
-                    reflection: 0.1,
-                },
-                Sphere {
-                    pos: [0.4, 0.0, -2.0],
-                    radius: 0.25,
-                    colour: [0.0, 1.0, 0.0],
-                    reflection: 0.2,
-                },
-                Sphere {
-                    pos: [0.0, -6.0, -4.0],
-                    radius: 5.0,
-                    colour: [0.1, 0.1, 0.1],
-                    reflection: 0.1,
-                },
-            ],
-        };
-
-        let spheres = Sphere::new_sphere_buffers(spheres, &device);
-
-        let pipeline = Pipeline::new(&device, &camera.layout, &spheres.layout).await;
+            scene,
+            options.trace,
+        )
+        .await;
 
         Self {
             surface,
             device,
             queue,
             config,
-            buffers,
             thread: ThreadContext::default(),
 
-            pipeline,
-            camera,
-            spheres,
+            renderer,
         }
     }
 
-    /// Create vertex and index buffers
-    pub fn create_buffers(device: &wgpu::Device) -> (wgpu::Buffer, wgpu::Buffer) {
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("vertex_buf"),
-            contents: bytemuck::cast_slice(GraphicsContext::VERTICES),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("index_buf"),
-            contents: bytemuck::cast_slice(GraphicsContext::INDICES),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-
-        (vertex_buffer, index_buffer)
-    }
-
     /// Create the sampler used for all textures
     pub fn create_sampler(device: &wgpu::Device) -> wgpu::Sampler {
         device.create_sampler(&wgpu::SamplerDescriptor {
@@ -180,6 +107,19 @@ impl GraphicsContext {
         })
     }
 
+    /// Replace the scene being rendered, starting accumulation over
+    pub async fn set_scene(&mut self, scene: &Scene, options: &Options) {
+        self.renderer = Renderer::new(
+            &self.device,
+            &self.queue,
+            self.config.format,
+            [self.config.width as f32, self.config.height as f32],
+            scene,
+            options.trace,
+        )
+        .await;
+    }
+
     /// Resize window callback
     pub fn resize(&mut self, width: u32, height: u32) {
         if width > 0 && height > 0 {
@@ -187,20 +127,12 @@ impl GraphicsContext {
             self.config.height = height;
             self.surface.configure(&self.device, &self.config);
 
-            let dims = &mut self.camera.camera.screen_dimensions;
-            dims[0] = width as f32;
-            dims[1] = height as f32;
+            self.renderer.resize(&self.device, width, height);
         }
     }
 
     /// Perform all render tasks per frame
     pub fn render(&mut self) -> Result<()> {
-        self.queue.write_buffer(
-            &self.camera.buffer,
-            0,
-            bytemuck::bytes_of(&self.camera.camera),
-        );
-
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -213,29 +145,7 @@ impl GraphicsContext {
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &output_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
-
-            render_pass.set_pipeline(&self.pipeline.pipeline);
-            render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
-            render_pass.set_bind_group(1, &self.spheres.bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.buffers.0.slice(..));
-            render_pass.set_index_buffer(self.buffers.1.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(0..GraphicsContext::INDICES.len() as u32, 0, 0..1);
-        }
+        self.renderer.render(&self.queue, &mut encoder, &output_view);
 
         self.queue.submit(iter::once(encoder.finish()));
         output.present();
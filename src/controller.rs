@@ -0,0 +1,153 @@
+use std::f32::consts::FRAC_PI_2;
+
+use cgmath::{Basis3, InnerSpace, Rad, Rotation, Rotation3, Vector3};
+use winit::event::{
+    DeviceEvent, ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent,
+};
+
+use crate::camera::Camera;
+
+/// Free-fly camera controls, WASD moves, QE moves down and up and dragging
+/// with the left mouse button looks around, minus and plus halve and double
+/// the samples per pixel
+#[derive(Debug, Default)]
+pub struct CameraController {
+    /// Movement speed in units per second
+    pub speed: f32,
+    /// Rotation in radians per pixel of mouse movement
+    pub sensitivity: f32,
+
+    forward: bool,
+    backward: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+    looking: bool,
+    mouse_delta: (f32, f32),
+    /// Number of times to double the samples, negative to halve
+    sample_steps: i32,
+}
+
+impl CameraController {
+    /// Pitch is kept just short of the camera's up or its opposite
+    const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+    /// Upper bound on samples per pixel per frame
+    const MAX_SAMPLES: u32 = 1024;
+
+    /// Create a new CameraController
+    pub fn new(speed: f32, sensitivity: f32) -> Self {
+        Self {
+            speed,
+            sensitivity,
+            ..Default::default()
+        }
+    }
+
+    /// Handle key presses and mouse buttons, returns true if the event was used
+    pub fn process_window_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => {
+                let pressed = *state == ElementState::Pressed;
+                match key {
+                    VirtualKeyCode::Equals | VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd => {
+                        self.sample_steps += pressed as i32
+                    }
+                    VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => {
+                        self.sample_steps -= pressed as i32
+                    }
+                    VirtualKeyCode::W => self.forward = pressed,
+                    VirtualKeyCode::S => self.backward = pressed,
+                    VirtualKeyCode::A => self.left = pressed,
+                    VirtualKeyCode::D => self.right = pressed,
+                    VirtualKeyCode::E => self.up = pressed,
+                    VirtualKeyCode::Q => self.down = pressed,
+                    _ => return false,
+                }
+                true
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.looking = *state == ElementState::Pressed;
+                true
+            }
+            // Stop moving if focus is lost, key releases would be missed
+            WindowEvent::Focused(false) => {
+                *self = CameraController::new(self.speed, self.sensitivity);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Handle raw mouse movement while looking around
+    pub fn process_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            if self.looking {
+                self.mouse_delta.0 += delta.0 as f32;
+                self.mouse_delta.1 += delta.1 as f32;
+            }
+        }
+    }
+
+    /// Move and rotate the camera by the input gathered over dt seconds
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) {
+        if self.sample_steps != 0 {
+            let samples = if self.sample_steps > 0 {
+                camera.samples << self.sample_steps.min(10)
+            } else {
+                camera.samples >> (-self.sample_steps).min(10)
+            };
+            camera.samples = samples.clamp(1, CameraController::MAX_SAMPLES);
+            self.sample_steps = 0;
+            log::info!("Samples per pixel: {}", camera.samples);
+        }
+
+        // Leave the camera untouched without input so accumulation continues
+        let moving =
+            self.forward || self.backward || self.left || self.right || self.up || self.down;
+        if !moving && self.mouse_delta == (0.0, 0.0) {
+            return;
+        }
+
+        let pos = Vector3::from(camera.pos);
+        let offset = Vector3::from(camera.target) - pos;
+        let direction = offset.normalize();
+
+        // Look around, moving the mouse right turns right about up and moving
+        // it up looks up, never quite as far as up itself
+        let up = Vector3::from(camera.up).normalize();
+        let turn = Basis3::from_axis_angle(up, Rad(-self.mouse_delta.0 * self.sensitivity));
+        let level = turn.rotate_vector((direction - up * direction.dot(up)).normalize());
+        let pitch = (direction.dot(up).clamp(-1.0, 1.0).asin()
+            - self.mouse_delta.1 * self.sensitivity)
+            .clamp(-CameraController::MAX_PITCH, CameraController::MAX_PITCH);
+        self.mouse_delta = (0.0, 0.0);
+
+        let direction = level * pitch.cos() + up * pitch.sin();
+
+        // Move relative to the level facing direction
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let forward = axis(self.forward, self.backward) * level;
+        let right = axis(self.right, self.left) * level.cross(up);
+        let up = axis(self.up, self.down) * up;
+
+        let pos = pos + (forward + right + up) * self.speed * dt;
+
+        // Keep the target the same distance away
+        camera.pos = pos.into();
+        camera.target = (pos + direction * offset.magnitude()).into();
+    }
+}
@@ -0,0 +1,1512 @@
+use std::{
+    f32::consts::{PI, TAU},
+    ops::Range,
+    thread,
+};
+
+use cgmath::{vec2, vec3, ElementWise, InnerSpace, Matrix, Matrix4, Vector2, Vector3, Zero};
+
+use crate::{
+    bvh::{Bvh, BvhNode, BvhPrimitive},
+    camera::{Camera, Jitter, Sampler},
+    environment::Environment,
+    geometry::Geometry,
+    instance::Instance,
+    light::Light,
+    material::Material,
+    primitive::{Cuboid, Disc, MeshVertex, Plane, Quad, Triangle},
+    shading::Shading,
+    sky::{PhysicalSky, Sky},
+    sphere::Sphere,
+    texture::Texture,
+};
+
+const EPSILON: f32 = 0.0001;
+
+/// RayHit light when a primitive rather than a light was hit
+pub const NO_LIGHT: u32 = u32::MAX;
+
+/// RayHit instance when a primitive placed directly in the world was hit
+pub const NO_INSTANCE: u32 = u32::MAX;
+
+/// Equivalent of the storage buffers bound to the shader
+#[derive(Copy, Clone, Debug)]
+pub struct Bindings<'a> {
+    pub geometry: &'a Geometry,
+    pub shading: &'a Shading,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub pos: Vector3<f32>,
+    pub dir: Vector3<f32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RayHit {
+    pub hit: bool,
+    pub distance: f32,
+    pub pos: Vector3<f32>,
+    /// Faces against the ray, front_face is set if that is the outside
+    pub normal: Vector3<f32>,
+    pub front_face: bool,
+    pub material: u32,
+    /// Kind of primitive hit, a Geometry constant, only meaningful when
+    /// light is NO_LIGHT
+    pub primitive: u32,
+    /// Index of the primitive hit within its kind
+    pub index: u32,
+    /// Index of the spherical light hit, or NO_LIGHT
+    pub light: u32,
+    /// Index of the instance the primitive was hit through, or NO_INSTANCE
+    pub instance: u32,
+    /// Texture coordinates, interpolated across triangles and zero elsewhere
+    pub uv: Vector2<f32>,
+    /// Vertex colour, interpolated across triangles and white elsewhere
+    pub colour: Vector3<f32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Scatter {
+    pub scattered: bool,
+    pub ray: Ray,
+    pub attenuation: Vector3<f32>,
+}
+
+impl RayHit {
+    /// Zero initialised hit, equivalent to an unassigned `var` in WGSL
+    pub fn miss() -> Self {
+        Self {
+            hit: false,
+            distance: 0.0,
+            pos: Vector3::zero(),
+            normal: Vector3::zero(),
+            front_face: false,
+            material: 0,
+            primitive: 0,
+            index: 0,
+            light: 0,
+            instance: 0,
+            uv: Vector2::zero(),
+            colour: Vector3::zero(),
+        }
+    }
+}
+
+/// PCG hash, see "Hash Functions for GPU Rendering", Jarzynski and Olano 2020
+pub fn pcg(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Seed state so every pixel, frame and sample has an independent sequence
+pub fn init_rng(pixel: [u32; 2], frame: u32, sample: u32) -> u32 {
+    pcg(pixel[0].wrapping_add(pcg(
+        pixel[1].wrapping_add(pcg(frame.wrapping_add(pcg(sample))))
+    )))
+}
+
+/// Next 32 random bits, advancing state by the same LCG step pcg permutes
+pub fn rand_u32(state: &mut u32) -> u32 {
+    let old = *state;
+    *state = old.wrapping_mul(747796405).wrapping_add(2891336453);
+    pcg(old)
+}
+
+/// Uniform in [0, 1), 24 bits so every value is exact in f32
+pub fn rand(state: &mut u32) -> f32 {
+    (rand_u32(state) >> 8) as f32 / 16777216.0
+}
+
+pub fn rand2(state: &mut u32) -> Vector2<f32> {
+    let x = rand(state);
+    vec2(x, rand(state))
+}
+
+/// Source of every sample taken while tracing a camera sample, set by init_sample_state
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SampleState {
+    /// Random number generator state
+    pub rng: u32,
+    /// Index into the low discrepancy sequence
+    pub index: u32,
+    /// Scrambles the sequence differently for each pixel
+    pub seed: u32,
+    /// Pair of dimensions of the sequence the next sample uses
+    pub dimension: u32,
+    pub sampler: u32,
+}
+
+/// Sample i of a pixel continues the pixel's sequence from previous frames
+pub fn init_sample_state(camera: &Camera, pixel: [u32; 2], sample: u32) -> SampleState {
+    SampleState {
+        rng: init_rng(pixel, camera.frame, sample),
+        index: camera
+            .frame
+            .wrapping_mul(camera.samples)
+            .wrapping_add(sample),
+        seed: init_rng(pixel, 0, 0),
+        dimension: 0,
+        sampler: camera.sampler,
+    }
+}
+
+/// Owen scrambling by hashing, see "Practical Hash-based Owen Scrambling", Burley 2020
+pub fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut v = x.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x05526c56);
+    v ^= v.wrapping_mul(0x53a22864);
+    v.reverse_bits()
+}
+
+/// Second dimension of the Sobol sequence, the first is index.reverse_bits()
+pub fn sobol_second(index: u32) -> u32 {
+    let mut result = 0;
+    let mut v = 1 << 31;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            result ^= v;
+        }
+        v ^= v >> 1;
+        i >>= 1;
+    }
+    result
+}
+
+/// Point of the Sobol sequence, with the index shuffled and each dimension scrambled
+pub fn sobol_2d(index: u32, seed: u32) -> Vector2<f32> {
+    let i = nested_uniform_scramble(index, seed);
+    let x = nested_uniform_scramble(i.reverse_bits(), pcg(seed));
+    let y = nested_uniform_scramble(sobol_second(i), pcg(seed.wrapping_add(1)));
+    vec2((x >> 8) as f32, (y >> 8) as f32) / 16777216.0
+}
+
+/// Next pair of dimensions of the sample, each in [0, 1)
+pub fn sample_2d(state: &mut SampleState) -> Vector2<f32> {
+    if state.sampler == Sampler::Sobol as u32 {
+        let seed = pcg(state.seed.wrapping_add(state.dimension));
+        state.dimension += 1;
+        return sobol_2d(state.index, seed);
+    }
+    rand2(&mut state.rng)
+}
+
+pub fn sample_1d(state: &mut SampleState) -> f32 {
+    sample_2d(state).x
+}
+
+pub fn random_unit_vector(state: &mut SampleState) -> Vector3<f32> {
+    let h = sample_2d(state).mul_element_wise(vec2(2.0, TAU)) - vec2(1.0, 0.0);
+    let phi = h.y;
+    let xy = (1.0 - h.x * h.x).sqrt() * vec2(phi.sin(), phi.cos());
+    vec3(xy.x, xy.y, h.x)
+}
+
+pub fn random_in_unit_sphere(state: &mut SampleState) -> Vector3<f32> {
+    let direction = random_unit_vector(state);
+    sample_1d(state).powf(1.0 / 3.0) * direction
+}
+
+pub fn random_in_unit_disk(state: &mut SampleState) -> Vector2<f32> {
+    let h = sample_2d(state);
+    let r = h.x.sqrt();
+    let theta = TAU * h.y;
+    r * vec2(theta.cos(), theta.sin())
+}
+
+pub fn near_zero(v: Vector3<f32>) -> bool {
+    v.x.abs() < EPSILON && v.y.abs() < EPSILON && v.z.abs() < EPSILON
+}
+
+pub fn hit_sphere(sphere: &Sphere, ray: &Ray) -> RayHit {
+    let dif = ray.pos - Vector3::from(sphere.pos);
+    let x = dif.dot(ray.dir);
+    let y = dif.dot(dif) - (sphere.radius * sphere.radius);
+
+    let d = x * x - y;
+
+    if d > 0.0 {
+        let xy = d.sqrt();
+        // Nearest root in front of the ray, ignoring the surface it starts on
+        let mut root = -x - xy;
+        if root < EPSILON {
+            root = -x + xy;
+        }
+        if root >= EPSILON {
+            let pos = ray.pos + root * ray.dir;
+            let outward = (pos - Vector3::from(sphere.pos)) / sphere.radius;
+            let front_face = ray.dir.dot(outward) < 0.0;
+            return RayHit {
+                hit: true,
+                distance: root,
+                pos,
+                normal: if front_face { outward } else { -outward },
+                front_face,
+                material: sphere.material,
+                primitive: Geometry::SPHERE,
+                index: 0,
+                light: NO_LIGHT,
+                instance: NO_INSTANCE,
+                uv: Vector2::zero(),
+                colour: vec3(1.0, 1.0, 1.0),
+            };
+        }
+    }
+    RayHit::miss()
+}
+
+/// Hit at distance along the ray on a surface with the given outward normal,
+/// or a miss if that is behind the ray or too close to its start
+fn surface_hit(ray: &Ray, distance: f32, outward: Vector3<f32>, material: u32) -> RayHit {
+    if distance < EPSILON {
+        return RayHit::miss();
+    }
+    let front_face = ray.dir.dot(outward) < 0.0;
+    RayHit {
+        hit: true,
+        distance,
+        pos: ray.pos + distance * ray.dir,
+        normal: if front_face { outward } else { -outward },
+        front_face,
+        material,
+        primitive: 0,
+        index: 0,
+        light: NO_LIGHT,
+        instance: NO_INSTANCE,
+        uv: Vector2::zero(),
+        colour: vec3(1.0, 1.0, 1.0),
+    }
+}
+
+/// Distance along the ray to the plane through pos with the given normal,
+/// negative if the ray is parallel to it
+fn plane_distance(pos: Vector3<f32>, normal: Vector3<f32>, ray: &Ray) -> f32 {
+    let denominator = ray.dir.dot(normal);
+    if denominator.abs() < 1e-8 {
+        return -1.0;
+    }
+    (pos - ray.pos).dot(normal) / denominator
+}
+
+pub fn hit_plane(plane: &Plane, ray: &Ray) -> RayHit {
+    let normal = Vector3::from(plane.normal);
+    let distance = plane_distance(plane.pos.into(), normal, ray);
+    let mut out = surface_hit(ray, distance, normal, plane.material);
+    out.primitive = Geometry::PLANE;
+    out
+}
+
+pub fn hit_disc(disc: &Disc, ray: &Ray) -> RayHit {
+    let normal = Vector3::from(disc.normal);
+    let distance = plane_distance(disc.pos.into(), normal, ray);
+    let mut out = surface_hit(ray, distance, normal, disc.material);
+    let offset = out.pos - Vector3::from(disc.pos);
+    if offset.dot(offset) > disc.radius * disc.radius {
+        return RayHit::miss();
+    }
+    out.primitive = Geometry::DISC;
+    out
+}
+
+/// Slab test in the box's own frame, from inside the box this finds the way out
+pub fn hit_box(cuboid: &Cuboid, ray: &Ray) -> RayHit {
+    let axes = cuboid.axes.map(|[x, y, z, _]| vec3(x, y, z));
+    let to_local = |v: Vector3<f32>| vec3(axes[0].dot(v), axes[1].dot(v), axes[2].dot(v));
+    let pos = to_local(ray.pos - Vector3::from(cuboid.pos));
+    let dir = to_local(ray.dir);
+    let half_size = Vector3::from(cuboid.half_size);
+
+    // Directions parallel to a slab give huge distances of the right sign
+    // rather than dividing by zero
+    let safe = |d: f32| if d.abs() < 1e-20 { 1e-20 } else { d };
+    let inverse = vec3(1.0 / safe(dir.x), 1.0 / safe(dir.y), 1.0 / safe(dir.z));
+    let t0 = (-half_size - pos).mul_element_wise(inverse);
+    let t1 = (half_size - pos).mul_element_wise(inverse);
+    let near = t0.x.min(t1.x).max(t0.y.min(t1.y)).max(t0.z.min(t1.z));
+    let far = t0.x.max(t1.x).min(t0.y.max(t1.y)).min(t0.z.max(t1.z));
+    if near > far {
+        return RayHit::miss();
+    }
+    let distance = if near >= EPSILON { near } else { far };
+
+    // The face hit is the one the hit point is relatively furthest out on
+    let local = (pos + distance * dir).div_element_wise(half_size);
+    let axis = if local.x.abs() >= local.y.abs() && local.x.abs() >= local.z.abs() {
+        0
+    } else if local.y.abs() >= local.z.abs() {
+        1
+    } else {
+        2
+    };
+    let outward = axes[axis] * local[axis].signum();
+    let mut out = surface_hit(ray, distance, outward, cuboid.material);
+    out.primitive = Geometry::BOX;
+    out
+}
+
+pub fn hit_quad(quad: &Quad, ray: &Ray) -> RayHit {
+    let (u, v) = (Vector3::from(quad.u), Vector3::from(quad.v));
+    let n = u.cross(v);
+    let distance = plane_distance(quad.pos.into(), n, ray);
+    let mut out = surface_hit(ray, distance, n.normalize(), quad.material);
+
+    // Coordinates of the hit along each edge, inside for 0 to 1 on both
+    let w = n / n.dot(n);
+    let offset = out.pos - Vector3::from(quad.pos);
+    let alpha = w.dot(offset.cross(v));
+    let beta = w.dot(u.cross(offset));
+    if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+        return RayHit::miss();
+    }
+    out.primitive = Geometry::QUAD;
+    out
+}
+
+/// Equirectangular coordinates of a direction in the environment map, with -z
+/// in the middle and +y at the top
+pub fn environment_uv(rotation: f32, dir: Vector3<f32>) -> Vector2<f32> {
+    let (s, c) = rotation.sin_cos();
+    let d = vec3(c * dir.x - s * dir.z, dir.y, s * dir.x + c * dir.z);
+    vec2(
+        0.5 + d.x.atan2(-d.z) / TAU,
+        d.y.clamp(-1.0, 1.0).acos() / PI,
+    )
+}
+
+/// Equivalent of sampling the environment map with the nearest, clamped sampler
+pub fn environment_colour(environment: &Environment, dir: Vector3<f32>) -> Vector3<f32> {
+    let uv = environment_uv(environment.rotation, dir);
+    let x = ((uv.x * environment.width as f32) as u32).min(environment.width - 1);
+    let y = ((uv.y * environment.height as f32) as u32).min(environment.height - 1);
+    let [r, g, b, _] = environment.pixels[(y * environment.width + x) as usize];
+    environment.intensity * vec3(r, g, b)
+}
+
+/// White to blue gradient multiplied by sky, for a direction with the given height
+pub fn sky_gradient(sky: [f32; 3], y: f32) -> Vector3<f32> {
+    let a = 0.5 * (y + 1.0);
+    Vector3::from(sky).mul_element_wise((1.0 - a) * vec3(1.0, 1.0, 1.0) + a * vec3(0.5, 0.7, 1.0))
+}
+
+/// Direction with the given equirectangular coordinates, inverse of environment_uv
+pub fn environment_direction(rotation: f32, uv: Vector2<f32>) -> Vector3<f32> {
+    let theta = uv.y * PI;
+    let phi = (uv.x - 0.5) * TAU;
+    let d = vec3(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    );
+    let (s, c) = rotation.sin_cos();
+    vec3(c * d.x + s * d.z, d.y, -s * d.x + c * d.z)
+}
+
+/// Equivalent of `lights.rotation`, zero without an environment map
+fn sky_rotation(bindings: &Bindings) -> f32 {
+    match &bindings.shading.sky {
+        Sky::Environment(environment) => environment.rotation,
+        _ => 0.0,
+    }
+}
+
+/// Perez function of Y, x and y for the cosine of the angle from the zenith
+/// and the angle from the sun
+pub fn perez(sky: &PhysicalSky, cos_theta: f32, gamma: f32) -> Vector3<f32> {
+    let [a, b, c, d, e] = sky.coefficients.map(|[y, x, yc, _]| vec3(y, x, yc));
+    let exp = |v: Vector3<f32>| vec3(v.x.exp(), v.y.exp(), v.z.exp());
+    let one = vec3(1.0, 1.0, 1.0);
+    let cos_gamma = gamma.cos();
+    (one + a.mul_element_wise(exp(b / cos_theta)))
+        .mul_element_wise(one + c.mul_element_wise(exp(d * gamma)) + e * cos_gamma * cos_gamma)
+}
+
+/// Linear sRGB radiance of the physical sky, which continues the horizon below
+/// it so curved ground doesn't leave a black band
+pub fn physical_sky_colour(sky: &PhysicalSky, dir: Vector3<f32>) -> Vector3<f32> {
+    let gamma = dir.dot(sky.sun_direction.into()).clamp(-1.0, 1.0).acos();
+    let cos_theta = dir.y.max(PhysicalSky::HORIZON);
+    let yxy = Vector3::from(sky.zenith).mul_element_wise(perez(sky, cos_theta, gamma));
+    let xyz = vec3(
+        yxy.y / yxy.z * yxy.x,
+        yxy.x,
+        (1.0 - yxy.y - yxy.z) / yxy.z * yxy.x,
+    );
+    let rgb = vec3(
+        vec3(3.2406, -1.5372, -0.4986).dot(xyz),
+        vec3(-0.9689, 1.8758, 0.0415).dot(xyz),
+        vec3(0.0557, -0.2040, 1.0570).dot(xyz),
+    );
+    sky.intensity * vec3(rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0))
+}
+
+/// Interval of the count + 1 values of the CDF starting at offset which contains u
+pub fn find_interval(cdf: &[f32], offset: u32, count: u32, u: f32) -> u32 {
+    let mut low = 0;
+    let mut high = count;
+    while high - low > 1 {
+        let middle = (low + high) / 2;
+        if cdf[(offset + middle) as usize] <= u {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    low
+}
+
+/// Direction sampled from the sky and its solid angle density, zero when no
+/// direction could be sampled
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SkySample {
+    pub dir: Vector3<f32>,
+    pub pdf: f32,
+}
+
+/// Sample a direction with density proportional to the tabulated sky radiance
+pub fn sample_sky(bindings: &Bindings, u: Vector2<f32>) -> SkySample {
+    let mut out = SkySample {
+        dir: Vector3::zero(),
+        pdf: 0.0,
+    };
+    let distribution = &bindings.shading.sky_distribution;
+    if distribution.total == 0.0 {
+        return out;
+    }
+
+    let cdf = &distribution.cdf;
+    let (width, height) = (distribution.width, distribution.height);
+    let row = find_interval(cdf, 0, height, u.y);
+    let row_start = cdf[row as usize];
+    let row_size = cdf[row as usize + 1] - row_start;
+    let v = (row as f32 + (u.y - row_start) / row_size) / height as f32;
+
+    let offset = height + 1 + row * (width + 1);
+    let column = find_interval(cdf, offset, width, u.x);
+    let column_start = cdf[(offset + column) as usize];
+    let column_size = cdf[(offset + column) as usize + 1] - column_start;
+    let uv = vec2(
+        (column as f32 + (u.x - column_start) / column_size) / width as f32,
+        v,
+    );
+
+    // Density over uv, divided by the area the cell covers on the sphere
+    let sin_theta = (v * PI).sin();
+    if sin_theta > 0.0 {
+        out.dir = environment_direction(sky_rotation(bindings), uv);
+        out.pdf =
+            row_size * height as f32 * column_size * width as f32 / (2.0 * PI * PI * sin_theta);
+    }
+    out
+}
+
+/// Solid angle density of sample_sky producing a direction
+pub fn sky_pdf(bindings: &Bindings, dir: Vector3<f32>) -> f32 {
+    let distribution = &bindings.shading.sky_distribution;
+    let sin_theta = (1.0 - dir.y * dir.y).max(0.0).sqrt();
+    if distribution.total == 0.0 || sin_theta == 0.0 {
+        return 0.0;
+    }
+
+    let cdf = &distribution.cdf;
+    let (width, height) = (distribution.width, distribution.height);
+    let uv = environment_uv(sky_rotation(bindings), dir);
+    let row = ((uv.y * height as f32) as u32).min(height - 1);
+    let column = ((uv.x * width as f32) as u32).min(width - 1);
+    let offset = (height + 1 + row * (width + 1)) as usize;
+    let row_size = cdf[row as usize + 1] - cdf[row as usize];
+    let column_size = cdf[offset + column as usize + 1] - cdf[offset + column as usize];
+    row_size * height as f32 * column_size * width as f32 / (2.0 * PI * PI * sin_theta)
+}
+
+pub fn sky_colour(bindings: &Bindings, ray: &Ray) -> Vector3<f32> {
+    match &bindings.shading.sky {
+        Sky::Gradient(colour) => sky_gradient(*colour, ray.dir.normalize().y),
+        Sky::Environment(environment) => environment_colour(environment, ray.dir.normalize()),
+        Sky::Physical(physical) => physical_sky_colour(physical, ray.dir.normalize()),
+    }
+}
+
+/// Whether nothing is hit along the ray before max_distance
+pub fn visible(bindings: &Bindings, ray: &Ray, max_distance: f32) -> bool {
+    let hit = cast_ray(bindings, ray);
+    !hit.hit || hit.distance >= max_distance
+}
+
+/// Irradiance at the hit from point and directional lights, which paths can
+/// never hit so have to be sampled explicitly
+pub fn delta_light_irradiance(bindings: &Bindings, hit: &RayHit) -> Vector3<f32> {
+    let mut irradiance = Vector3::zero();
+    for light in &bindings.shading.lights {
+        let (to_light, light_distance, incident) = match light.kind {
+            Light::POINT => {
+                let to_light = Vector3::from(light.pos) - hit.pos;
+                let light_distance = to_light.magnitude();
+                let incident = Vector3::from(light.emission) / (light_distance * light_distance);
+                (to_light / light_distance, light_distance, incident)
+            }
+            Light::DIRECTIONAL => (
+                -Vector3::from(light.direction),
+                f32::MAX,
+                Vector3::from(light.emission),
+            ),
+            _ => continue,
+        };
+
+        let cos_theta = hit.normal.dot(to_light);
+        if cos_theta <= 0.0 {
+            continue;
+        }
+
+        let shadow_ray = Ray {
+            pos: hit.pos + hit.normal * EPSILON,
+            dir: to_light,
+        };
+        if visible(bindings, &shadow_ray, light_distance) {
+            irradiance += cos_theta * incident;
+        }
+    }
+    irradiance
+}
+
+/// Orthonormal basis with n as the last vector, see "Building an Orthonormal
+/// Basis, Revisited", Duff et al. 2017
+pub fn orthonormal_basis(n: Vector3<f32>) -> [Vector3<f32>; 3] {
+    let sign = if n.z >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    [
+        vec3(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        vec3(b, sign + n.y * n.y * a, -n.y),
+        n,
+    ]
+}
+
+/// 1 - cos of the half angle of the cone a sphere subtends from a point, written
+/// to stay accurate for small spheres, or zero from inside the sphere
+pub fn sphere_cone(centre: Vector3<f32>, radius: f32, origin: Vector3<f32>) -> f32 {
+    let to_centre = centre - origin;
+    let sin2_max = radius * radius / to_centre.dot(to_centre);
+    if sin2_max >= 1.0 {
+        return 0.0;
+    }
+    sin2_max / (1.0 + (1.0 - sin2_max).sqrt())
+}
+
+/// Solid angle density of sampling a direction towards a sphere from a point,
+/// zero from inside it where it isn't sampled
+pub fn sphere_pdf(centre: Vector3<f32>, radius: f32, origin: Vector3<f32>) -> f32 {
+    let cone = sphere_cone(centre, radius, origin);
+    if cone == 0.0 {
+        return 0.0;
+    }
+    1.0 / (TAU * cone)
+}
+
+/// Uniformly sample a direction within a cone around axis, given 1 - cos of
+/// its half angle
+pub fn sample_cone(axis: Vector3<f32>, cone: f32, u: Vector2<f32>) -> Vector3<f32> {
+    // sin^2 = (1 - cos)(1 + cos), which stays accurate for narrow cones
+    let one_minus_cos = u.x * cone;
+    let cos_theta = 1.0 - one_minus_cos;
+    let sin_theta = (one_minus_cos * (2.0 - one_minus_cos)).max(0.0).sqrt();
+    let phi = TAU * u.y;
+    let [x, y, z] = orthonormal_basis(axis);
+    x * (phi.cos() * sin_theta) + y * (phi.sin() * sin_theta) + z * cos_theta
+}
+
+/// Uniformly sample a direction within the cone a sphere subtends from a point
+pub fn sample_sphere(
+    centre: Vector3<f32>,
+    radius: f32,
+    origin: Vector3<f32>,
+    u: Vector2<f32>,
+) -> Vector3<f32> {
+    sample_cone(
+        (centre - origin).normalize(),
+        sphere_cone(centre, radius, origin),
+        u,
+    )
+}
+
+/// 1 - cos of the angular radius of the sun
+pub fn sun_cone(light: &Light) -> f32 {
+    let s = (0.5 * light.radius).sin();
+    2.0 * s * s
+}
+
+/// Multiple importance sampling weight of a sample taken with density a, when
+/// it could also have been taken with density b
+pub fn power_heuristic(a: f32, b: f32) -> f32 {
+    a * a / (a * a + b * b)
+}
+
+/// Weight of emission found by a diffuse bounce with the given density, which
+/// sampling the emitter directly from the bounce could also have found
+pub fn bsdf_weight(bsdf_pdf: f32, centre: Vector3<f32>, radius: f32, origin: Vector3<f32>) -> f32 {
+    if bsdf_pdf == 0.0 {
+        return 1.0;
+    }
+    power_heuristic(bsdf_pdf, sphere_pdf(centre, radius, origin))
+}
+
+/// Irradiance at a diffuse hit from directly sampling one spherical emitter,
+/// either sphere or light, with a shadow ray checking that emitter is hit
+#[allow(clippy::too_many_arguments)]
+pub fn sample_emitter(
+    bindings: &Bindings,
+    hit: &RayHit,
+    centre: Vector3<f32>,
+    radius: f32,
+    emission: Vector3<f32>,
+    sphere: u32,
+    light: u32,
+    state: &mut SampleState,
+) -> Vector3<f32> {
+    // Always drawn so later dimensions of the sample don't depend on the hit
+    let u = sample_2d(state);
+    let light_pdf = sphere_pdf(centre, radius, hit.pos);
+    if light_pdf == 0.0 {
+        return Vector3::zero();
+    }
+
+    let dir = sample_sphere(centre, radius, hit.pos, u);
+    let cos_theta = hit.normal.dot(dir);
+    if cos_theta <= 0.0 {
+        return Vector3::zero();
+    }
+
+    let shadow_ray = Ray {
+        pos: hit.pos + hit.normal * EPSILON,
+        dir,
+    };
+    let shadow = cast_ray(bindings, &shadow_ray);
+    let other_sphere = shadow.primitive != Geometry::SPHERE
+        || shadow.index != sphere
+        || shadow.instance != NO_INSTANCE;
+    if !shadow.hit || shadow.light != light || (light == NO_LIGHT && other_sphere) {
+        return Vector3::zero();
+    }
+
+    let bsdf_pdf = cos_theta / PI;
+    emission * cos_theta / light_pdf * power_heuristic(light_pdf, bsdf_pdf)
+}
+
+/// Irradiance at a diffuse hit from directly sampling the disk of the sun,
+/// which is visible when the shadow ray escapes
+pub fn sample_sun(
+    bindings: &Bindings,
+    hit: &RayHit,
+    light: &Light,
+    state: &mut SampleState,
+) -> Vector3<f32> {
+    // Always drawn so later dimensions of the sample don't depend on the hit
+    let u = sample_2d(state);
+    let cone = sun_cone(light);
+    let dir = sample_cone(-Vector3::from(light.direction), cone, u);
+    let cos_theta = hit.normal.dot(dir);
+    if cos_theta <= 0.0 {
+        return Vector3::zero();
+    }
+
+    let shadow_ray = Ray {
+        pos: hit.pos + hit.normal * EPSILON,
+        dir,
+    };
+    if cast_ray(bindings, &shadow_ray).hit {
+        return Vector3::zero();
+    }
+
+    let light_pdf = 1.0 / (TAU * cone);
+    let bsdf_pdf = cos_theta / PI;
+    Vector3::from(light.emission) * cos_theta / light_pdf * power_heuristic(light_pdf, bsdf_pdf)
+}
+
+/// Radiance of any sun seen along an escaping ray, weighted against sampling
+/// the sun directly when the ray comes from a diffuse bounce
+pub fn sun_radiance(bindings: &Bindings, dir: Vector3<f32>, bsdf_pdf: f32) -> Vector3<f32> {
+    let mut radiance = Vector3::zero();
+    for light in &bindings.shading.lights {
+        if light.kind != Light::SUN {
+            continue;
+        }
+
+        let cone = sun_cone(light);
+        if dir.normalize().dot(-Vector3::from(light.direction)) < 1.0 - cone {
+            continue;
+        }
+        let mut weight = 1.0;
+        if bsdf_pdf > 0.0 {
+            weight = power_heuristic(bsdf_pdf, 1.0 / (TAU * cone));
+        }
+        radiance += weight * Vector3::from(light.emission);
+    }
+    radiance
+}
+
+/// Irradiance at a diffuse hit from sampling every emissive sphere, spherical
+/// light and sun, small bright emitters are rarely found by bouncing
+pub fn emitter_irradiance(
+    bindings: &Bindings,
+    hit: &RayHit,
+    state: &mut SampleState,
+) -> Vector3<f32> {
+    let mut irradiance = Vector3::zero();
+    // Spheres of models are only lit by bouncing
+    let geometry = bindings.geometry;
+    let spheres = &geometry.spheres[..geometry.world_count(Geometry::SPHERE)];
+    for (i, sphere) in spheres.iter().enumerate() {
+        let material = bindings.shading.materials[sphere.material as usize];
+        if material.kind == Material::EMISSIVE {
+            irradiance += sample_emitter(
+                bindings,
+                hit,
+                sphere.pos.into(),
+                sphere.radius.abs(),
+                material.emission.into(),
+                i as u32,
+                NO_LIGHT,
+                state,
+            );
+        }
+    }
+
+    for (i, light) in bindings.shading.lights.iter().enumerate() {
+        if light.kind == Light::SPHERICAL {
+            irradiance += sample_emitter(
+                bindings,
+                hit,
+                light.pos.into(),
+                light.radius,
+                light.emission.into(),
+                0,
+                i as u32,
+                state,
+            );
+        } else if light.kind == Light::SUN {
+            irradiance += sample_sun(bindings, hit, light, state);
+        }
+    }
+    irradiance
+}
+
+/// Irradiance at a diffuse hit from importance sampling the sky, weighted
+/// against the chance of a diffuse bounce escaping the same way
+pub fn sky_irradiance(bindings: &Bindings, hit: &RayHit, state: &mut SampleState) -> Vector3<f32> {
+    // Always drawn so later dimensions of the sample don't depend on the hit
+    let sky_sample = sample_sky(bindings, sample_2d(state));
+    if sky_sample.pdf == 0.0 {
+        return Vector3::zero();
+    }
+
+    let cos_theta = hit.normal.dot(sky_sample.dir);
+    if cos_theta <= 0.0 {
+        return Vector3::zero();
+    }
+
+    let shadow_ray = Ray {
+        pos: hit.pos + hit.normal * EPSILON,
+        dir: sky_sample.dir,
+    };
+    if cast_ray(bindings, &shadow_ray).hit {
+        return Vector3::zero();
+    }
+
+    let bsdf_pdf = cos_theta / PI;
+    let weight = power_heuristic(sky_sample.pdf, bsdf_pdf);
+    sky_colour(bindings, &shadow_ray) * cos_theta / sky_sample.pdf * weight
+}
+
+/// Schlick's approximation of the reflectance of a dielectric
+pub fn reflectance(cosine: f32, ratio: f32) -> f32 {
+    let r0 = (1.0 - ratio) / (1.0 + ratio);
+    let r0 = r0 * r0;
+    r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
+}
+
+/// Equivalent of WGSL `reflect`
+pub fn reflect(e1: Vector3<f32>, e2: Vector3<f32>) -> Vector3<f32> {
+    e1 - 2.0 * e2.dot(e1) * e2
+}
+
+/// Equivalent of WGSL `refract`
+pub fn refract(e1: Vector3<f32>, e2: Vector3<f32>, e3: f32) -> Vector3<f32> {
+    let k = 1.0 - e3 * e3 * (1.0 - e2.dot(e1) * e2.dot(e1));
+    if k < 0.0 {
+        Vector3::zero()
+    } else {
+        e3 * e1 - (e3 * e2.dot(e1) + k.sqrt()) * e2
+    }
+}
+
+/// Linear value of an sRGB encoded one
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Equivalent of reading the nearest texel from the textures buffer, the
+/// coordinates wrap around so textures repeat
+pub fn texture_colour(texture: &Texture, uv: Vector2<f32>) -> Vector3<f32> {
+    let wrapped = uv - vec2(uv.x.floor(), uv.y.floor());
+    let x = ((wrapped.x * texture.width as f32) as u32).min(texture.width - 1);
+    let y = ((wrapped.y * texture.height as f32) as u32).min(texture.height - 1);
+    let [r, g, b, _] = texture.texels[(y * texture.width + x) as usize].to_le_bytes();
+    [r, g, b].map(|c| srgb_to_linear(c as f32 / 255.0)).into()
+}
+
+/// Material of the hit, with its albedo multiplied by its vertex colour and by
+/// its texture if it has one
+pub fn hit_material(bindings: &Bindings, hit: &RayHit) -> Material {
+    let mut material = bindings.shading.materials[hit.material as usize];
+    material.albedo = Vector3::from(material.albedo)
+        .mul_element_wise(hit.colour)
+        .into();
+    if material.texture != Material::NO_TEXTURE {
+        let texture = &bindings.shading.textures[material.texture as usize];
+        let colour = texture_colour(texture, hit.uv);
+        material.albedo = Vector3::from(material.albedo)
+            .mul_element_wise(colour)
+            .into();
+    }
+    material
+}
+
+pub fn scatter(bindings: &Bindings, ray: &Ray, hit: &RayHit, state: &mut SampleState) -> Scatter {
+    let material = hit_material(bindings, hit);
+
+    let mut out = Scatter {
+        scattered: true,
+        ray: Ray {
+            pos: hit.pos,
+            dir: Vector3::zero(),
+        },
+        attenuation: material.albedo.into(),
+    };
+
+    match material.kind {
+        Material::METAL => {
+            let reflected = reflect(ray.dir, hit.normal);
+            out.ray.dir = reflected.normalize() + material.fuzz * random_in_unit_sphere(state);
+            out.scattered = out.ray.dir.dot(hit.normal) > 0.0;
+        }
+        Material::DIELECTRIC => {
+            let ratio = if hit.front_face {
+                1.0 / material.ior
+            } else {
+                material.ior
+            };
+            let cos_theta = (-ray.dir).dot(hit.normal).min(1.0);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+            if ratio * sin_theta > 1.0 || reflectance(cos_theta, ratio) > sample_1d(state) {
+                out.ray.dir = reflect(ray.dir, hit.normal);
+            } else {
+                out.ray.dir = refract(ray.dir, hit.normal, ratio);
+            }
+        }
+        Material::EMISSIVE => {
+            out.scattered = false;
+        }
+        _ => {
+            out.ray.dir = hit.normal + random_unit_vector(state);
+
+            if near_zero(out.ray.dir) {
+                out.ray.dir = hit.normal;
+            }
+        }
+    }
+
+    // Matches WGSL normalize, which leaves a zero vector as NaN rather than panicking
+    out.ray.dir = out.ray.dir.normalize();
+
+    // Start just off the surface on the side the ray leaves through, so rounding
+    // can't leave it behind the surface and hit it again
+    let side = if out.ray.dir.dot(hit.normal) > 0.0 {
+        hit.normal
+    } else {
+        -hit.normal
+    };
+    out.ray.pos = hit.pos + side * EPSILON;
+    out
+}
+
+/// Path trace from the given ray, emission along the path is weighted by the
+/// throughput, the product of the attenuation of every bounce so far. Diffuse
+/// bounces also sample emitters and the sky directly, emission found by the
+/// next bounce is weighted against that
+pub fn iterative_ray_colour(
+    camera: &Camera,
+    bindings: &Bindings,
+    ray: &Ray,
+    state: &mut SampleState,
+) -> Vector3<f32> {
+    let mut radiance = Vector3::zero();
+    let mut throughput = vec3(1.0, 1.0, 1.0);
+
+    let mut current_ray = *ray;
+    // Density of the last bounce direction if it was diffuse, zero otherwise
+    let mut bsdf_pdf = 0.0;
+    let mut bounce_pos = Vector3::zero();
+
+    for _ in 0..camera.max_depth {
+        let hit_out = cast_ray(bindings, &current_ray);
+        if !hit_out.hit {
+            let mut weight = 1.0;
+            if bsdf_pdf > 0.0 {
+                weight = power_heuristic(bsdf_pdf, sky_pdf(bindings, current_ray.dir));
+            }
+            let escaped = weight * sky_colour(bindings, &current_ray)
+                + sun_radiance(bindings, current_ray.dir, bsdf_pdf);
+            radiance += throughput.mul_element_wise(escaped);
+            break;
+        }
+
+        if hit_out.light != NO_LIGHT {
+            let light = bindings.shading.lights[hit_out.light as usize];
+            let weight = bsdf_weight(bsdf_pdf, light.pos.into(), light.radius, bounce_pos);
+            radiance += throughput.mul_element_wise(weight * Vector3::from(light.emission));
+            break;
+        }
+
+        // Only emissive spheres placed directly in the world are also sampled
+        // directly
+        let material = hit_material(bindings, &hit_out);
+        let world_sphere = hit_out.primitive == Geometry::SPHERE && hit_out.instance == NO_INSTANCE;
+        if material.kind == Material::EMISSIVE && world_sphere {
+            let sphere = bindings.geometry.spheres[hit_out.index as usize];
+            let weight = bsdf_weight(bsdf_pdf, sphere.pos.into(), sphere.radius.abs(), bounce_pos);
+            radiance += throughput.mul_element_wise(weight * Vector3::from(material.emission));
+        } else {
+            radiance += throughput.mul_element_wise(Vector3::from(material.emission));
+        }
+
+        // Diffuse surfaces reflect albedo / pi of the irradiance from lights
+        if material.kind == Material::LAMBERTIAN {
+            let reflected = Vector3::from(material.albedo) / PI;
+            let irradiance = delta_light_irradiance(bindings, &hit_out)
+                + emitter_irradiance(bindings, &hit_out, state)
+                + sky_irradiance(bindings, &hit_out, state);
+            radiance += throughput.mul_element_wise(reflected.mul_element_wise(irradiance));
+        }
+
+        let scattered = scatter(bindings, &current_ray, &hit_out, state);
+        if !scattered.scattered {
+            break;
+        }
+        throughput.mul_assign_element_wise(scattered.attenuation);
+        current_ray = scattered.ray;
+
+        bsdf_pdf = 0.0;
+        if material.kind == Material::LAMBERTIAN {
+            bsdf_pdf = current_ray.dir.dot(hit_out.normal).max(0.0) / PI;
+            bounce_pos = hit_out.pos;
+        }
+    }
+    radiance
+}
+
+/// Möller–Trumbore intersection, smooth shaded when the vertices have normals
+pub fn hit_triangle(triangle: &Triangle, vertices: &[MeshVertex], ray: &Ray) -> RayHit {
+    let [a, b, c] = triangle.vertices.map(|i| vertices[i as usize]);
+    let v0 = Vector3::from(a.pos);
+    let edge1 = Vector3::from(b.pos) - v0;
+    let edge2 = Vector3::from(c.pos) - v0;
+
+    let p = ray.dir.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < 1e-8 {
+        return RayHit::miss();
+    }
+    let inverse = 1.0 / determinant;
+    let offset = ray.pos - v0;
+    let u = offset.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return RayHit::miss();
+    }
+    let q = offset.cross(edge1);
+    let v = ray.dir.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return RayHit::miss();
+    }
+
+    let distance = edge2.dot(q) * inverse;
+    let mut out = surface_hit(
+        ray,
+        distance,
+        edge1.cross(edge2).normalize(),
+        triangle.material,
+    );
+    if a.normal != [0.0; 3] {
+        let normal = ((1.0 - u - v) * Vector3::from(a.normal)
+            + u * Vector3::from(b.normal)
+            + v * Vector3::from(c.normal))
+        .normalize();
+        out.normal = if out.front_face { normal } else { -normal };
+    }
+    out.uv = (1.0 - u - v) * vec2(a.u, a.v) + u * vec2(b.u, b.v) + v * vec2(c.u, c.v);
+    out.colour = (1.0 - u - v) * Vector3::from(a.colour)
+        + u * Vector3::from(b.colour)
+        + v * Vector3::from(c.colour);
+    out.primitive = Geometry::TRIANGLE;
+    out
+}
+
+/// Keep ray_hit as the closest if it is nearer, recording its index
+fn closest_hit(closest: &mut RayHit, ray_hit: RayHit, index: usize) {
+    if ray_hit.hit && (!closest.hit || closest.distance >= ray_hit.distance) {
+        *closest = ray_hit;
+        closest.index = index as u32;
+    }
+}
+
+/// Distance along the ray to where it enters the node's bounds, or -1 if it
+/// misses them or only reaches them beyond max_distance
+pub fn bounds_distance(node: &BvhNode, ray: &Ray, inverse: Vector3<f32>, max_distance: f32) -> f32 {
+    let t0 = (Vector3::from(node.min) - ray.pos).mul_element_wise(inverse);
+    let t1 = (Vector3::from(node.max) - ray.pos).mul_element_wise(inverse);
+    let (low, high) = (t0.zip(t1, f32::min), t0.zip(t1, f32::max));
+    let near = low.x.max(low.y).max(low.z).max(0.0);
+    let far = high.x.min(high.y).min(high.z).min(max_distance);
+    if near > far {
+        return -1.0;
+    }
+    near
+}
+
+/// Intersect the primitive a BVH leaf refers to
+pub fn hit_primitive(geometry: &Geometry, primitive: BvhPrimitive, ray: &Ray) -> RayHit {
+    let i = primitive.index as usize;
+    match primitive.kind {
+        Geometry::SPHERE => hit_sphere(&geometry.spheres[i], ray),
+        Geometry::DISC => hit_disc(&geometry.discs[i], ray),
+        Geometry::BOX => hit_box(&geometry.boxes[i], ray),
+        Geometry::QUAD => hit_quad(&geometry.quads[i], ray),
+        _ => hit_triangle(&geometry.triangles[i], &geometry.vertices, ray),
+    }
+}
+
+/// Keep a spherical light as the closest if one is nearer, they are hit like
+/// spheres
+fn closest_light(bindings: &Bindings, ray: &Ray, mut closest: RayHit) -> RayHit {
+    let mut hit = closest.hit;
+    for (i, light) in bindings.shading.lights.iter().enumerate() {
+        if light.kind != Light::SPHERICAL {
+            continue;
+        }
+
+        let ray_hit = hit_sphere(&Sphere::new(light.pos, light.radius, 0), ray);
+        if ray_hit.hit && (!hit || closest.distance >= ray_hit.distance) {
+            closest = ray_hit;
+            closest.light = i as u32;
+            hit = true;
+        }
+    }
+    closest
+}
+
+/// Reciprocal of each component of the direction, those parallel to an axis
+/// give huge values of the right sign rather than dividing by zero
+fn inverse_direction(dir: Vector3<f32>) -> Vector3<f32> {
+    let safe = |d: f32| if d.abs() < 1e-20 { 1e-20 } else { d };
+    vec3(1.0 / safe(dir.x), 1.0 / safe(dir.y), 1.0 / safe(dir.z))
+}
+
+/// Push the children of an interior node the ray reaches onto the stack, the
+/// further first so the nearer is visited next
+fn push_children(
+    nodes: &[BvhNode],
+    index: usize,
+    ray: &Ray,
+    inverse: Vector3<f32>,
+    max_distance: f32,
+    stack: &mut [usize; Bvh::MAX_DEPTH],
+    stack_size: &mut usize,
+) {
+    let mut first = index + 1;
+    let mut second = nodes[index].start as usize;
+    let mut first_distance = bounds_distance(&nodes[first], ray, inverse, max_distance);
+    let mut second_distance = bounds_distance(&nodes[second], ray, inverse, max_distance);
+    if first_distance > second_distance {
+        (first, second) = (second, first);
+        (first_distance, second_distance) = (second_distance, first_distance);
+    }
+    if second_distance >= 0.0 {
+        stack[*stack_size] = second;
+        *stack_size += 1;
+    }
+    if first_distance >= 0.0 {
+        stack[*stack_size] = first;
+        *stack_size += 1;
+    }
+}
+
+/// Closest hit within max_distance in a model's hierarchy, starting at root
+pub fn hit_model(geometry: &Geometry, root: usize, ray: &Ray, max_distance: f32) -> RayHit {
+    let mut closest = RayHit::miss();
+    let nodes = &geometry.bvh.nodes;
+    let inverse = inverse_direction(ray.dir);
+    let mut stack = [0; Bvh::MAX_DEPTH];
+    stack[0] = root;
+    let mut stack_size = 1;
+    while stack_size > 0 {
+        stack_size -= 1;
+        let index = stack[stack_size];
+        let node = nodes[index];
+        let max_distance = if closest.hit {
+            closest.distance
+        } else {
+            max_distance
+        };
+        if bounds_distance(&node, ray, inverse, max_distance) < 0.0 {
+            continue;
+        }
+
+        if node.count == 0 {
+            push_children(
+                nodes,
+                index,
+                ray,
+                inverse,
+                max_distance,
+                &mut stack,
+                &mut stack_size,
+            );
+            continue;
+        }
+        let start = node.start as usize;
+        for &primitive in &geometry.bvh.primitives[start..start + node.count as usize] {
+            let ray_hit = hit_primitive(geometry, primitive, ray);
+            closest_hit(&mut closest, ray_hit, primitive.index as usize);
+        }
+    }
+    closest
+}
+
+/// Ray in the space of the instance's model, and how much longer distances
+/// along it are
+fn instance_ray(instance: &Instance, ray: &Ray) -> (Ray, f32) {
+    let inverse = Matrix4::from(instance.inverse);
+    let dir = (inverse * ray.dir.extend(0.0)).truncate();
+    let scale = dir.magnitude();
+    let local = Ray {
+        pos: (inverse * ray.pos.extend(1.0)).truncate(),
+        dir: dir / scale,
+    };
+    (local, scale)
+}
+
+/// Move a hit in the space of the instance's model back into the world, with
+/// the instance's material if it overrides the model's
+fn instance_hit(instance: &Instance, index: usize, ray: &Ray, local: RayHit, scale: f32) -> RayHit {
+    if !local.hit {
+        return local;
+    }
+    let mut out = local;
+    out.distance = local.distance / scale;
+    out.pos = ray.pos + out.distance * ray.dir;
+    // Normals are transformed by the inverse transpose to stay perpendicular
+    let inverse = Matrix4::from(instance.inverse);
+    out.normal = (inverse.transpose() * local.normal.extend(0.0))
+        .truncate()
+        .normalize();
+    if instance.material != Instance::NO_MATERIAL {
+        out.material = instance.material;
+    }
+    out.instance = index as u32;
+    out
+}
+
+/// Closest hit within max_distance on the model an instance places, found by
+/// walking the model's hierarchy with the ray moved into its space
+pub fn hit_instance(geometry: &Geometry, index: usize, ray: &Ray, max_distance: f32) -> RayHit {
+    let instance = &geometry.instances[index];
+    let (local, scale) = instance_ray(instance, ray);
+    let hit = hit_model(
+        geometry,
+        instance.root as usize,
+        &local,
+        max_distance * scale,
+    );
+    instance_hit(instance, index, ray, hit, scale)
+}
+
+/// Closest hit, planes are tested one by one and everything else by walking
+/// the BVH nearest node first, skipping nodes beyond the closest hit so far.
+/// Leaves of the top level can be instances, whose models are walked in turn
+pub fn cast_ray(bindings: &Bindings, ray: &Ray) -> RayHit {
+    let mut closest = RayHit::miss();
+    let geometry = bindings.geometry;
+    for (i, plane) in geometry.planes.iter().enumerate() {
+        closest_hit(&mut closest, hit_plane(plane, ray), i);
+    }
+
+    let nodes = &geometry.bvh.nodes;
+    let inverse = inverse_direction(ray.dir);
+    let mut stack = [0; Bvh::MAX_DEPTH];
+    let mut stack_size = usize::from(!nodes.is_empty());
+    while stack_size > 0 {
+        stack_size -= 1;
+        let index = stack[stack_size];
+        let node = nodes[index];
+        let max_distance = if closest.hit {
+            closest.distance
+        } else {
+            f32::MAX
+        };
+        if bounds_distance(&node, ray, inverse, max_distance) < 0.0 {
+            continue;
+        }
+
+        if node.count == 0 {
+            push_children(
+                nodes,
+                index,
+                ray,
+                inverse,
+                max_distance,
+                &mut stack,
+                &mut stack_size,
+            );
+            continue;
+        }
+        let start = node.start as usize;
+        for &primitive in &geometry.bvh.primitives[start..start + node.count as usize] {
+            let i = primitive.index as usize;
+            if primitive.kind != Geometry::INSTANCE {
+                closest_hit(&mut closest, hit_primitive(geometry, primitive, ray), i);
+                continue;
+            }
+            let max_distance = if closest.hit {
+                closest.distance
+            } else {
+                f32::MAX
+            };
+            let ray_hit = hit_instance(geometry, i, ray, max_distance);
+            closest_hit(&mut closest, ray_hit, ray_hit.index as usize);
+        }
+    }
+    closest_light(bindings, ray, closest)
+}
+
+/// Closest hit testing every primitive in the range of each kind
+fn hit_ranges_brute_force(
+    geometry: &Geometry,
+    ranges: &[Range<usize>; Geometry::PRIMITIVE_KINDS],
+    ray: &Ray,
+) -> RayHit {
+    let mut closest = RayHit::miss();
+    for (kind, range) in ranges.iter().enumerate() {
+        for i in range.clone() {
+            let primitive = BvhPrimitive {
+                kind: kind as u32,
+                index: i as u32,
+            };
+            let ray_hit = match primitive.kind {
+                Geometry::PLANE => hit_plane(&geometry.planes[i], ray),
+                _ => hit_primitive(geometry, primitive, ray),
+            };
+            closest_hit(&mut closest, ray_hit, i);
+        }
+    }
+    closest
+}
+
+/// Closest hit testing every primitive, and every primitive of the model of
+/// every instance, the reference the BVH traversal in cast_ray is tested
+/// against, it has no equivalent in the shader
+pub fn cast_ray_brute_force(bindings: &Bindings, ray: &Ray) -> RayHit {
+    let geometry = bindings.geometry;
+    let world = std::array::from_fn(|kind| 0..geometry.world_count(kind as u32));
+    let mut closest = hit_ranges_brute_force(geometry, &world, ray);
+    for (i, instance) in geometry.instances.iter().enumerate() {
+        let model = &geometry.models[instance.model as usize];
+        let (local, scale) = instance_ray(instance, ray);
+        let local_hit = hit_ranges_brute_force(geometry, &model.ranges, &local);
+        let ray_hit = instance_hit(instance, i, ray, local_hit, scale);
+        closest_hit(&mut closest, ray_hit, ray_hit.index as usize);
+    }
+    closest_light(bindings, ray, closest)
+}
+
+pub fn calc_ray(camera: &Camera, screen_pos: Vector2<f32>, state: &mut SampleState) -> Ray {
+    let dimensions = camera.screen_dimensions;
+    let pos = Vector3::from(camera.pos);
+    let viewport_height = 2.0 * (camera.vfov.to_radians() / 2.0).tan() * camera.focal;
+    let viewport_width = viewport_height * (dimensions[0] / dimensions[1]);
+
+    let viewport_u = viewport_width * Vector3::from(camera.u);
+    let viewport_v = viewport_height * -Vector3::from(camera.v);
+
+    let pixel_delta_u = viewport_u / dimensions[0];
+    let pixel_delta_v = viewport_v / dimensions[1];
+
+    let viewport_upper_left =
+        pos - camera.focal * Vector3::from(camera.w) - viewport_u / 2.0 - viewport_v / 2.0;
+    let pixel00_loc = viewport_upper_left + 0.5 * (pixel_delta_u + pixel_delta_v);
+
+    let pixel_center =
+        pixel00_loc + (screen_pos.x * pixel_delta_u) + (screen_pos.y * pixel_delta_v);
+    let ray_direction = (pixel_center - pos).normalize();
+
+    // Thin lens, start from a point on the lens and aim at the focus plane
+    if camera.aperture > 0.0 {
+        let w = Vector3::from(camera.w);
+        let focus_point = pos + ray_direction * (camera.focus_dist / ray_direction.dot(-w));
+        let lens = 0.5 * camera.aperture * random_in_unit_disk(state);
+        let pos = pos + lens.x * Vector3::from(camera.u) + lens.y * Vector3::from(camera.v);
+        return Ray {
+            pos,
+            dir: (focus_point - pos).normalize(),
+        };
+    }
+
+    Ray {
+        pos,
+        dir: ray_direction,
+    }
+}
+
+pub fn gamma_correction(v: f32) -> f32 {
+    if v > 0.0 {
+        v.sqrt()
+    } else {
+        0.0
+    }
+}
+
+pub fn gamma_correction_vec(v: Vector3<f32>) -> Vector3<f32> {
+    vec3(
+        gamma_correction(v.x),
+        gamma_correction(v.y),
+        gamma_correction(v.z),
+    )
+}
+
+/// Offset from the pixel centre of sample i of count, within half a pixel
+pub fn sample_offset(camera: &Camera, i: u32, count: u32, state: &mut SampleState) -> Vector2<f32> {
+    let h = sample_2d(state);
+    if camera.jitter == Jitter::Random as u32 {
+        return h - vec2(0.5, 0.5);
+    }
+
+    // Stratified, one sample in each cell of the squarest grid of exactly count cells
+    let mut rows = 1;
+    let mut factor = 2;
+    while factor * factor <= count {
+        if count.is_multiple_of(factor) {
+            rows = factor;
+        }
+        factor += 1;
+    }
+    let columns = count / rows;
+    let cell = vec2((i % columns) as f32, (i / columns) as f32);
+    (cell + h).div_element_wise(vec2(columns as f32, rows as f32)) - vec2(0.5, 0.5)
+}
+
+pub fn cast_multiple_rays(
+    camera: &Camera,
+    bindings: &Bindings,
+    origin: Vector2<f32>,
+) -> Vector3<f32> {
+    let mut pixel_colour = Vector3::zero();
+    for i in 0..camera.samples {
+        let mut state = init_sample_state(camera, [origin.x as u32, origin.y as u32], i);
+        let offset = sample_offset(camera, i, camera.samples, &mut state);
+        let ray = calc_ray(camera, origin + offset, &mut state);
+        pixel_colour += iterative_ray_colour(camera, bindings, &ray, &mut state);
+    }
+    pixel_colour / camera.samples as f32
+}
+
+/// Equivalent of `fs_main` for the pixel at x, y blended into the previous
+/// average, every function in this module mirrors the shader function of the
+/// same name in raytrace.wgsl
+pub fn fs_main(
+    camera: &Camera,
+    bindings: &Bindings,
+    x: u32,
+    y: u32,
+    previous: Vector3<f32>,
+) -> Vector3<f32> {
+    // Fragment positions are sampled at pixel centres
+    let clip_position = vec2(x as f32 + 0.5, y as f32 + 0.5);
+    let colour = cast_multiple_rays(camera, bindings, clip_position);
+
+    let t = 1.0 / (camera.frame + 1) as f32;
+    previous * (1.0 - t) + colour * t
+}
+
+/// Equivalent of `fs_display`
+pub fn fs_display(colour: Vector3<f32>) -> Vector3<f32> {
+    gamma_correction_vec(colour)
+}
+
+/// Render and average frames of the camera's screen, then return the displayed colours
+pub fn render(camera: &Camera, bindings: &Bindings, frames: u32) -> Vec<Vector3<f32>> {
+    let width = camera.screen_dimensions[0] as u32;
+    let height = camera.screen_dimensions[1] as u32;
+
+    let mut camera = *camera;
+    camera.update_basis();
+    let mut accumulated = vec![Vector3::zero(); (width * height) as usize];
+    for frame in 0..frames {
+        camera.frame = frame;
+        render_frame(&camera, bindings, &mut accumulated);
+    }
+
+    accumulated.into_iter().map(fs_display).collect()
+}
+
+/// Blend a single frame into the accumulated average, rows are split across all cores
+pub fn render_frame(camera: &Camera, bindings: &Bindings, accumulated: &mut [Vector3<f32>]) {
+    let width = camera.screen_dimensions[0] as u32;
+    let height = camera.screen_dimensions[1] as u32;
+    if accumulated.is_empty() {
+        return;
+    }
+
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let rows_per_chunk = (height as usize).div_ceil(threads);
+
+    thread::scope(|scope| {
+        for (i, chunk) in accumulated
+            .chunks_mut(rows_per_chunk * width as usize)
+            .enumerate()
+        {
+            scope.spawn(move || {
+                let first_row = (i * rows_per_chunk) as u32;
+                for (j, pixel) in chunk.iter_mut().enumerate() {
+                    let x = j as u32 % width;
+                    let y = first_row + j as u32 / width;
+                    *pixel = fs_main(camera, bindings, x, y, *pixel);
+                }
+            });
+        }
+    });
+}
+
+/// Encode linear colours as RGBA8, as an sRGB render target would on store
+pub fn to_rgba8(pixels: &[Vector3<f32>]) -> Vec<u8> {
+    fn encode(c: f32) -> u8 {
+        let c = c.clamp(0.0, 1.0);
+        let srgb = if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        };
+        (srgb * 255.0).round() as u8
+    }
+
+    pixels
+        .iter()
+        .flat_map(|p| [encode(p.x), encode(p.y), encode(p.z), 255])
+        .collect()
+}
@@ -0,0 +1,241 @@
+use anyhow::{Context, Result};
+
+/// Equirectangular image surrounding the scene, lighting it from infinitely far away
+#[derive(Clone, Debug, PartialEq)]
+pub struct Environment {
+    pub width: u32,
+    pub height: u32,
+    /// Linear radiance in rows from the top, +y is at the top and -z in the middle
+    pub pixels: Vec<[f32; 4]>,
+    /// Multiplies the radiance
+    pub intensity: f32,
+    /// Rotation around the y axis in radians
+    pub rotation: f32,
+}
+
+impl Environment {
+    /// Decode a Radiance `.hdr` or OpenEXR image, or any other supported format
+    pub fn from_bytes(bytes: &[u8], intensity: f32, rotation: f32) -> Result<Self> {
+        let (width, height, pixels) = match image::guess_format(bytes) {
+            // Decoding through DynamicImage would tonemap Radiance images to 8 bits
+            Ok(image::ImageFormat::Hdr) => {
+                let decoder = image::codecs::hdr::HdrDecoder::new(bytes)
+                    .context("Failed to decode environment map")?;
+                let metadata = decoder.metadata();
+                let pixels = decoder
+                    .read_image_hdr()
+                    .context("Failed to decode environment map")?
+                    .into_iter()
+                    .map(|image::Rgb([r, g, b])| [r, g, b, 1.0])
+                    .collect();
+                (metadata.width, metadata.height, pixels)
+            }
+            _ => {
+                let image = image::load_from_memory(bytes)
+                    .context("Failed to decode environment map")?
+                    .into_rgba32f();
+                let pixels = image.pixels().map(|pixel| pixel.0).collect();
+                (image.width(), image.height(), pixels)
+            }
+        };
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+            intensity,
+            rotation,
+        })
+    }
+
+    /// Create the texture holding the image, which is sampled without filtering.
+    /// Images larger than the device allows are downsampled to fit
+    pub fn create_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
+        let max_size = device.limits().max_texture_dimension_2d;
+        if self.width > max_size || self.height > max_size {
+            return self.downsampled(max_size).create_texture(device, queue);
+        }
+        Environment::create_texture_from(device, queue, self.width, self.height, &self.pixels)
+    }
+
+    /// Halve the image until neither side is larger than max_size, averaging
+    /// each 2x2 block of pixels
+    pub fn downsampled(&self, max_size: u32) -> Self {
+        let mut environment = self.clone();
+        while environment.width > max_size || environment.height > max_size {
+            let (width, height) = (environment.width, environment.height);
+            let pixel = |x: u32, y: u32| {
+                environment.pixels[(y.min(height - 1) * width + x.min(width - 1)) as usize]
+            };
+            let pixels = (0..height.div_ceil(2))
+                .flat_map(|y| (0..width.div_ceil(2)).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let block = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                        .map(|(dx, dy)| pixel(2 * x + dx, 2 * y + dy));
+                    std::array::from_fn(|i| block.iter().map(|p| p[i]).sum::<f32>() / 4.0)
+                })
+                .collect();
+            environment.width = width.div_ceil(2);
+            environment.height = height.div_ceil(2);
+            environment.pixels = pixels;
+        }
+        environment
+    }
+
+    /// Create a black 1x1 texture, bound when a scene has no environment map
+    pub fn create_empty_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
+        Environment::create_texture_from(device, queue, 1, 1, &[[0.0; 4]])
+    }
+
+    fn create_texture_from(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: u32,
+        height: u32,
+        pixels: &[[f32; 4]],
+    ) -> wgpu::Texture {
+        use wgpu::util::DeviceExt;
+
+        device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("environment_texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            bytemuck::cast_slice(pixels),
+        )
+    }
+}
+
+/// Piecewise constant distribution over the equirectangular sky, proportional
+/// to its luminance, for sampling directions the sky lights strongly
+#[derive(Clone, Debug, PartialEq)]
+pub struct SkyDistribution {
+    pub width: u32,
+    pub height: u32,
+    /// Integral of the tabulated function, zero for a black sky which is never sampled
+    pub total: f32,
+    /// Marginal CDF over rows, height + 1 values, then a conditional CDF over
+    /// the columns of each row, width + 1 values each
+    pub cdf: Vec<f32>,
+}
+
+/// Start of the distribution buffer, followed by the CDFs
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyDistributionHeader {
+    width: u32,
+    height: u32,
+    total: f32,
+}
+
+impl SkyDistribution {
+    /// Resolution the sky gradient is tabulated at, it only varies with height
+    pub const GRADIENT_SIZE: (u32, u32) = (1, 64);
+    /// Resolution the physical sky is tabulated at, fine enough to pick out
+    /// the glow around the sun
+    pub const PHYSICAL_SIZE: (u32, u32) = (128, 64);
+
+    /// Largest resolution images are tabulated at, larger ones are averaged
+    /// down to it to keep the buffer within the storage buffer size limit
+    pub const IMAGE_SIZE: (u32, u32) = (1024, 512);
+
+    /// Tabulate the radiance of a width by height image, at its own resolution
+    /// or averaged over blocks of pixels if larger than IMAGE_SIZE
+    pub fn tabulate_image(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [f32; 3]) -> Self {
+        let columns = width.min(SkyDistribution::IMAGE_SIZE.0);
+        let rows = height.min(SkyDistribution::IMAGE_SIZE.1);
+        // Pixels covered by cell i of count along a side of the image
+        let block = |i: u32, count: u32, size: u32| {
+            let start = i as u64 * size as u64 / count as u64;
+            let end = (i + 1) as u64 * size as u64 / count as u64;
+            start as u32..end as u32
+        };
+        SkyDistribution::tabulate(columns, rows, |x, y| {
+            let mut sum = [0.0; 3];
+            let mut count = 0.0;
+            for py in block(y, rows, height) {
+                for px in block(x, columns, width) {
+                    let radiance = pixel(px, py);
+                    for (sum, radiance) in sum.iter_mut().zip(radiance) {
+                        *sum += radiance;
+                    }
+                    count += 1.0;
+                }
+            }
+            sum.map(|sum| sum / count)
+        })
+    }
+
+    /// Tabulate radiance at the centre of each cell of a width by height grid,
+    /// weighted by the solid angle of the cell
+    pub fn tabulate(width: u32, height: u32, radiance: impl Fn(u32, u32) -> [f32; 3]) -> Self {
+        let mut cdf = Vec::with_capacity(((height + 1) + height * (width + 1)) as usize);
+        let mut conditional = Vec::with_capacity((height * (width + 1)) as usize);
+        let mut rows = Vec::with_capacity(height as usize);
+
+        for y in 0..height {
+            let theta = std::f64::consts::PI * (y as f64 + 0.5) / height as f64;
+            let row: Vec<f64> = (0..width)
+                .map(|x| {
+                    let [r, g, b] = radiance(x, y);
+                    let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+                    luminance.max(0.0) as f64 * theta.sin()
+                })
+                .collect();
+            let sum: f64 = row.iter().sum();
+            conditional.extend(SkyDistribution::cumulative(&row, sum));
+            rows.push(sum / width as f64);
+        }
+
+        let sum: f64 = rows.iter().sum();
+        cdf.extend(SkyDistribution::cumulative(&rows, sum));
+        cdf.extend(conditional);
+
+        // Integral over the unit square of uv, rather than solid angle
+        let total = (sum / height as f64) as f32;
+        Self {
+            width,
+            height,
+            total,
+            cdf,
+        }
+    }
+
+    /// Normalised running sum starting at zero, uniform if everything is zero
+    fn cumulative(values: &[f64], sum: f64) -> impl Iterator<Item = f32> + '_ {
+        let count = values.len() as f64;
+        let mut running = 0.0;
+        std::iter::once(0.0).chain(values.iter().enumerate().map(move |(i, value)| {
+            running += value;
+            if sum > 0.0 {
+                (running / sum) as f32
+            } else {
+                ((i + 1) as f64 / count) as f32
+            }
+        }))
+    }
+
+    /// Contents of the distribution buffer
+    pub fn bytes(&self) -> Vec<u8> {
+        let header = SkyDistributionHeader {
+            width: self.width,
+            height: self.height,
+            total: self.total,
+        };
+
+        let mut bytes = bytemuck::bytes_of(&header).to_vec();
+        bytes.extend_from_slice(bytemuck::cast_slice(&self.cdf));
+        bytes
+    }
+}
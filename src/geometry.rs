@@ -0,0 +1,224 @@
+use std::ops::Range;
+
+use wgpu::util::DeviceExt;
+
+use crate::{
+    bvh::{Bounds, Bvh},
+    instance::Instance,
+    primitive::{Cuboid, Disc, MeshVertex, Plane, Quad, Triangle},
+    sphere::Sphere,
+};
+
+/// Geometry uploaded to the GPU, with the bind group the shader reads it from
+pub struct GeometryWithBuffers {
+    pub geometry: Geometry,
+    pub layout: wgpu::BindGroupLayout,
+    /// Spheres, planes, discs, boxes, quads, vertices, triangles, the BVH
+    /// nodes and primitives, then instances, in binding order
+    pub buffers: [wgpu::Buffer; Geometry::BUFFERS],
+    pub bind_group: wgpu::BindGroup,
+}
+
+/// Everything rays can hit besides spherical lights, each kind of primitive in
+/// its own buffer
+#[derive(Clone, Debug, Default)]
+pub struct Geometry {
+    pub spheres: Vec<Sphere>,
+    pub planes: Vec<Plane>,
+    pub discs: Vec<Disc>,
+    pub boxes: Vec<Cuboid>,
+    pub quads: Vec<Quad>,
+    /// Vertices of every mesh, triangles index into these
+    pub vertices: Vec<MeshVertex>,
+    pub triangles: Vec<Triangle>,
+    /// Groups of primitives placed only by instances
+    pub models: Vec<Model>,
+    pub instances: Vec<Instance>,
+    /// Hierarchy over everything but planes, rebuilt by with_bvh, primitives
+    /// left out of it are never hit
+    pub bvh: Bvh,
+}
+
+/// Primitives placed in the world only by instances, as a range of each
+/// kind's buffer indexed by kind. These come after the primitives placed
+/// directly in the world, and can't include planes as those have no bounds
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Model {
+    pub ranges: [Range<usize>; Geometry::PRIMITIVE_KINDS],
+}
+
+/// Contents of a primitive buffer, the count padded to 16 bytes then the
+/// primitives, or a zeroed one standing in when there are none
+fn primitive_bytes<T: bytemuck::Pod>(primitives: &[T]) -> Vec<u8> {
+    let mut bytes = bytemuck::bytes_of(&[primitives.len() as u32, 0, 0, 0]).to_vec();
+    if primitives.is_empty() {
+        bytes.extend_from_slice(bytemuck::bytes_of(&T::zeroed()));
+    } else {
+        bytes.extend_from_slice(bytemuck::cast_slice(primitives));
+    }
+    bytes
+}
+
+impl Geometry {
+    /// Values of RayHit primitive, saying which buffer index refers to
+    pub const SPHERE: u32 = 0;
+    pub const PLANE: u32 = 1;
+    pub const DISC: u32 = 2;
+    pub const BOX: u32 = 3;
+    pub const QUAD: u32 = 4;
+    pub const TRIANGLE: u32 = 5;
+    /// Kind of BVH leaf entry which refers to an instance rather than a
+    /// primitive, never that of a hit
+    pub const INSTANCE: u32 = 6;
+
+    /// Number of kinds of primitive
+    pub const PRIMITIVE_KINDS: usize = 6;
+
+    /// Number of storage buffers in the bind group
+    pub const BUFFERS: usize = 10;
+
+    /// Build the BVH over the current primitives and instances
+    pub fn with_bvh(mut self) -> Self {
+        self.bvh = Bvh::new(&self);
+        for instance in &mut self.instances {
+            instance.root = self.bvh.roots[instance.model as usize];
+        }
+        self
+    }
+
+    /// Number of primitives of a kind, or of instances
+    pub fn count(&self, kind: u32) -> usize {
+        match kind {
+            Geometry::SPHERE => self.spheres.len(),
+            Geometry::PLANE => self.planes.len(),
+            Geometry::DISC => self.discs.len(),
+            Geometry::BOX => self.boxes.len(),
+            Geometry::QUAD => self.quads.len(),
+            Geometry::TRIANGLE => self.triangles.len(),
+            _ => self.instances.len(),
+        }
+    }
+
+    /// Number of primitives of a kind placed directly in the world, which
+    /// come before those of any model
+    pub fn world_count(&self, kind: u32) -> usize {
+        self.models
+            .iter()
+            .map(|model| &model.ranges[kind as usize])
+            .filter(|range| !range.is_empty())
+            .map(|range| range.start)
+            .fold(self.count(kind), usize::min)
+    }
+
+    /// Bounds of a primitive of any kind but plane
+    pub fn primitive_bounds(&self, kind: u32, index: usize) -> Bounds {
+        match kind {
+            Geometry::SPHERE => self.spheres[index].bounds(),
+            Geometry::DISC => self.discs[index].bounds(),
+            Geometry::BOX => self.boxes[index].bounds(),
+            Geometry::QUAD => self.quads[index].bounds(),
+            Geometry::TRIANGLE => self.triangles[index].bounds(&self.vertices),
+            _ => panic!("primitives of kind {kind} have no bounds"),
+        }
+    }
+
+    /// Contents of each primitive buffer, in binding order
+    pub fn buffer_bytes(&self) -> [Vec<u8>; Geometry::BUFFERS] {
+        // Spheres also say how many of them are placed directly in the world,
+        // only those are sampled as lights
+        let mut spheres = primitive_bytes(&self.spheres);
+        let world_spheres = self.world_count(Geometry::SPHERE) as u32;
+        spheres[4..8].copy_from_slice(bytemuck::bytes_of(&world_spheres));
+        [
+            spheres,
+            primitive_bytes(&self.planes),
+            primitive_bytes(&self.discs),
+            primitive_bytes(&self.boxes),
+            primitive_bytes(&self.quads),
+            primitive_bytes(&self.vertices),
+            primitive_bytes(&self.triangles),
+            primitive_bytes(&self.bvh.nodes),
+            primitive_bytes(&self.bvh.primitives),
+            primitive_bytes(&self.instances),
+        ]
+    }
+
+    pub fn new_geometry_buffers(geometry: Geometry, device: &wgpu::Device) -> GeometryWithBuffers {
+        // Create layout from entries, a read only storage buffer for each kind
+        let entries = (0..Geometry::BUFFERS as u32)
+            .map(|i| wgpu::BindGroupLayoutEntry {
+                binding: i,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            })
+            .collect::<Vec<wgpu::BindGroupLayoutEntry>>();
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("geometry_binding"),
+        });
+
+        let buffers = Geometry::create_buffers(&geometry, device);
+        let bind_group = Geometry::create_bind_group(&layout, &buffers, device);
+
+        GeometryWithBuffers {
+            geometry,
+            layout,
+            buffers,
+            bind_group,
+        }
+    }
+
+    /// Create buffers with the initial contents of each kind of primitive
+    fn create_buffers(
+        geometry: &Geometry,
+        device: &wgpu::Device,
+    ) -> [wgpu::Buffer; Geometry::BUFFERS] {
+        let labels = [
+            "spheres_buf",
+            "planes_buf",
+            "discs_buf",
+            "boxes_buf",
+            "quads_buf",
+            "vertices_buf",
+            "triangles_buf",
+            "bvh_nodes_buf",
+            "bvh_primitives_buf",
+            "instances_buf",
+        ];
+        let bytes = geometry.buffer_bytes();
+        std::array::from_fn(|i| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(labels[i]),
+                contents: &bytes[i],
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+        })
+    }
+
+    fn create_bind_group(
+        layout: &wgpu::BindGroupLayout,
+        buffers: &[wgpu::Buffer; Geometry::BUFFERS],
+        device: &wgpu::Device,
+    ) -> wgpu::BindGroup {
+        let entries = buffers
+            .iter()
+            .enumerate()
+            .map(|(i, buffer)| wgpu::BindGroupEntry {
+                binding: i as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<wgpu::BindGroupEntry>>();
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some("geometry_group"),
+        })
+    }
+}
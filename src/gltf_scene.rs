@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use cgmath::{Matrix4, SquareMatrix};
+use gltf::{camera::Projection, mesh::Mode, Document, Gltf};
+
+use crate::{
+    load_bytes,
+    material::Material,
+    mesh::{sibling_path, Mesh},
+    primitive::{MeshVertex, Triangle},
+    texture::Texture,
+};
+
+/// Meshes, materials, textures and camera of a glTF 2.0 file, either `.gltf`
+/// with its buffers and images embedded or in files next to it, or binary
+/// `.glb`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GltfScene {
+    /// One for each mesh of the file, their triangles' materials index into
+    /// the file's material table so their own is empty
+    pub meshes: Vec<Mesh>,
+    /// Material of each material of the file, then the default for mesh
+    /// primitives without one
+    pub materials: Vec<Material>,
+    /// Base colour textures, materials refer to them by index
+    pub textures: Vec<Texture>,
+    /// Every node of the scene with a mesh, each an instance of the mesh
+    pub nodes: Vec<GltfNode>,
+    /// First camera of the file, if a node of the scene places it
+    pub camera: Option<GltfCamera>,
+}
+
+/// Node placing a mesh
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GltfNode {
+    /// Index into the meshes
+    pub mesh: usize,
+    /// From the mesh's space to the scene's, through every parent node
+    pub transform: Matrix4<f32>,
+}
+
+/// Perspective camera, looking down -z of its node with +y up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GltfCamera {
+    /// From the camera's space to the scene's
+    pub transform: Matrix4<f32>,
+    /// Vertical field of view in degrees
+    pub vfov: f32,
+}
+
+impl GltfScene {
+    /// Load a `.gltf` or `.glb` file and the buffers and images it names,
+    /// which are found next to it
+    pub async fn load(path: &str) -> Result<GltfScene> {
+        let bytes = load_bytes(path)
+            .await
+            .with_context(|| format!("Failed to read glTF file {path}"))?;
+        GltfScene::from_bytes(&bytes, path)
+            .await
+            .with_context(|| format!("Failed to load glTF file {path}"))
+    }
+
+    /// Parse the contents of a `.gltf` or `.glb` file, path is where the
+    /// buffers and images it names are found relative to
+    pub async fn from_bytes(bytes: &[u8], path: &str) -> Result<GltfScene> {
+        let Gltf { document, blob } = Gltf::from_slice(bytes)?;
+
+        let mut buffers = Vec::new();
+        for buffer in document.buffers() {
+            let data = match buffer.source() {
+                gltf::buffer::Source::Bin => blob
+                    .clone()
+                    .ok_or_else(|| anyhow!("buffer {} has no binary chunk", buffer.index()))?,
+                gltf::buffer::Source::Uri(uri) => load_uri(path, uri).await?,
+            };
+            if data.len() < buffer.length() {
+                return Err(anyhow!(
+                    "buffer {} has {} bytes rather than {}",
+                    buffer.index(),
+                    data.len(),
+                    buffer.length()
+                ));
+            }
+            buffers.push(data);
+        }
+
+        // Only images used as base colour are decoded, each once however many
+        // textures share it
+        let mut textures = Vec::new();
+        let mut image_textures = HashMap::new();
+        for material in document.materials() {
+            let pbr = material.pbr_metallic_roughness();
+            let Some(info) = pbr.base_color_texture() else {
+                continue;
+            };
+            let image = info.texture().source();
+            if image_textures.contains_key(&image.index()) {
+                continue;
+            }
+            let bytes = match image.source() {
+                gltf::image::Source::View { view, .. } => {
+                    let start = view.offset();
+                    let buffer = &buffers[view.buffer().index()];
+                    buffer
+                        .get(start..start + view.length())
+                        .ok_or_else(|| anyhow!("image {} is outside its buffer", image.index()))?
+                        .to_vec()
+                }
+                gltf::image::Source::Uri { uri, .. } => load_uri(path, uri).await?,
+            };
+            let texture = Texture::from_bytes(&bytes)
+                .with_context(|| format!("Failed to decode image {}", image.index()))?;
+            image_textures.insert(image.index(), textures.len() as u32);
+            textures.push(texture);
+        }
+
+        let materials = document
+            .materials()
+            .map(|material| {
+                let texture = material
+                    .pbr_metallic_roughness()
+                    .base_color_texture()
+                    .map(|info| image_textures[&info.texture().source().index()]);
+                gltf_material(&material, texture)
+            })
+            .chain([Material::lambertian(Mesh::DEFAULT_ALBEDO)])
+            .collect::<Vec<_>>();
+        let meshes = document
+            .meshes()
+            .map(|mesh| gltf_mesh(&mesh, &buffers, materials.len() as u32 - 1))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut scene = GltfScene {
+            meshes,
+            materials,
+            textures,
+            ..Default::default()
+        };
+        scene.add_nodes(&document);
+        Ok(scene)
+    }
+
+    /// Add every node of the default scene, or of the first without one, with
+    /// the transforms of their parents applied
+    fn add_nodes(&mut self, document: &Document) {
+        let Some(root) = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+        else {
+            return;
+        };
+        let mut first_camera = None;
+        let mut stack: Vec<_> = root
+            .nodes()
+            .map(|node| (node, Matrix4::identity()))
+            .collect();
+        while let Some((node, parent)) = stack.pop() {
+            let transform = parent * Matrix4::from(node.transform().matrix());
+            if let Some(mesh) = node.mesh() {
+                self.nodes.push(GltfNode {
+                    mesh: mesh.index(),
+                    transform,
+                });
+            }
+            if let Some(camera) = node.camera() {
+                if let Projection::Perspective(perspective) = camera.projection() {
+                    if first_camera.is_none_or(|first| camera.index() < first) {
+                        first_camera = Some(camera.index());
+                        self.camera = Some(GltfCamera {
+                            transform,
+                            vfov: perspective.yfov().to_degrees(),
+                        });
+                    }
+                }
+            }
+            stack.extend(node.children().map(|child| (child, transform)));
+        }
+    }
+}
+
+/// Nearest of the tracer's materials to a metallic-roughness one. Emissive
+/// and transmissive materials become lights and glass, otherwise mostly
+/// metallic ones are metal blurred by their roughness, and the rest diffuse
+fn gltf_material(material: &gltf::Material, texture: Option<u32>) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, _] = pbr.base_color_factor();
+    let strength = material.emissive_strength().unwrap_or(1.0);
+    let emission = material.emissive_factor().map(|c| c * strength);
+    let transmission = material
+        .transmission()
+        .map_or(0.0, |transmission| transmission.transmission_factor());
+
+    // Glowing and see through surfaces don't use the base colour texture
+    if emission != [0.0; 3] {
+        return Material::emissive(emission);
+    }
+    if transmission > 0.5 {
+        return Material::dielectric(material.ior().unwrap_or(1.5));
+    }
+
+    let material = if pbr.metallic_factor() >= 0.5 {
+        Material::metal([r, g, b], pbr.roughness_factor())
+    } else {
+        Material::lambertian([r, g, b])
+    };
+    match texture {
+        Some(texture) => material.with_texture(texture),
+        None => material,
+    }
+}
+
+/// Triangles of every primitive of a mesh, whose vertices are shared between
+/// its triangles but not between primitives. Primitives of points or lines
+/// are left out
+fn gltf_mesh(mesh: &gltf::Mesh, buffers: &[Vec<u8>], default_material: u32) -> Result<Mesh> {
+    let mut out = Mesh::default();
+    for primitive in mesh.primitives() {
+        let material = primitive.material();
+        let material_index = material.index().map_or(default_material, |i| i as u32);
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+        let positions = reader.read_positions().ok_or_else(|| {
+            anyhow!(
+                "primitive {} of mesh {} has no positions",
+                primitive.index(),
+                mesh.index()
+            )
+        })?;
+
+        let first = out.vertices.len() as u32;
+        out.vertices
+            .extend(positions.map(|pos| MeshVertex::new(pos, [0.0; 3])));
+        let count = out.vertices.len() - first as usize;
+        if let Some(normals) = reader.read_normals() {
+            for (vertex, normal) in out.vertices[first as usize..].iter_mut().zip(normals) {
+                vertex.normal = normal;
+            }
+        }
+        let set = material
+            .pbr_metallic_roughness()
+            .base_color_texture()
+            .map_or(0, |info| info.tex_coord());
+        if let Some(uvs) = reader.read_tex_coords(set) {
+            for (vertex, uv) in out.vertices[first as usize..]
+                .iter_mut()
+                .zip(uvs.into_f32())
+            {
+                *vertex = vertex.with_uv(uv);
+            }
+        }
+
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..count as u32).collect(),
+        };
+        if let Some(&index) = indices.iter().find(|&&index| index as usize >= count) {
+            return Err(anyhow!(
+                "primitive {} of mesh {} has vertex index {index} but only {count} vertices",
+                primitive.index(),
+                mesh.index()
+            ));
+        }
+        let corners: Vec<[u32; 3]> = match primitive.mode() {
+            Mode::Triangles => indices
+                .chunks_exact(3)
+                .map(|c| [c[0], c[1], c[2]])
+                .collect(),
+            // Every other triangle of a strip is wound the other way
+            Mode::TriangleStrip => indices
+                .windows(3)
+                .enumerate()
+                .map(|(i, c)| match i % 2 {
+                    0 => [c[0], c[1], c[2]],
+                    _ => [c[1], c[0], c[2]],
+                })
+                .collect(),
+            Mode::TriangleFan => indices
+                .windows(2)
+                .skip(1)
+                .map(|c| [indices[0], c[0], c[1]])
+                .collect(),
+            _ => Vec::new(),
+        };
+        out.triangles.extend(
+            corners
+                .into_iter()
+                .map(|corners| Triangle::new(corners.map(|corner| first + corner), material_index)),
+        );
+    }
+    Ok(out)
+}
+
+/// Contents of a buffer or image named by URI, either embedded as base64 or a
+/// file found relative to the glTF file
+async fn load_uri(path: &str, uri: &str) -> Result<Vec<u8>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data
+            .split_once(";base64,")
+            .ok_or_else(|| anyhow!("data URI is not base64"))?;
+        return base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .context("Failed to decode data URI");
+    }
+    let file = sibling_path(path, uri);
+    load_bytes(&file)
+        .await
+        .with_context(|| format!("Failed to read {file}"))
+}
@@ -0,0 +1,171 @@
+use std::iter;
+
+use anyhow::{Context, Result};
+
+use crate::{options::Options, renderer::Renderer, scene::Scene};
+
+/// Offscreen rendering context which needs neither a window nor a surface
+pub struct HeadlessContext {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub texture: wgpu::Texture,
+    pub size: (u32, u32),
+
+    pub renderer: Renderer,
+}
+
+impl HeadlessContext {
+    /// Format of the offscreen texture, matches the byte layout of a PNG
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    /// Create a new HeadlessContext rendering a scene into a width by height texture
+    pub async fn new(width: u32, height: u32, scene: &Scene, options: &Options) -> Result<Self> {
+        // Create a new backend instance
+        let instance = wgpu::Instance::new(options.instance_descriptor());
+
+        // Create a new device adapter, software adapters are fine without a surface
+        let adapter_options = options.adapter_options(None);
+        let adapter = match instance.request_adapter(&adapter_options).await {
+            Some(adapter) => adapter,
+            None => instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    force_fallback_adapter: true,
+                    ..adapter_options
+                })
+                .await
+                .context("No graphics adapter available for headless rendering")?,
+        };
+
+        // Get the queue and device from the adapter
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    features: wgpu::Features::empty(),
+                    limits: Renderer::limits(),
+                    label: None,
+                },
+                None,
+            )
+            .await
+            .context("Failed to create device")?;
+
+        // Create the texture rendered into in place of a surface
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("headless_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HeadlessContext::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let renderer = Renderer::new(
+            &device,
+            &queue,
+            HeadlessContext::FORMAT,
+            [width as f32, height as f32],
+            scene,
+            options.trace,
+        )
+        .await;
+
+        Ok(Self {
+            device,
+            queue,
+            texture,
+            size: (width, height),
+
+            renderer,
+        })
+    }
+
+    /// Render a single frame into the offscreen texture
+    pub fn render(&mut self) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Headless Encoder"),
+            });
+
+        let view = self
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        self.renderer.render(&self.queue, &mut encoder, &view);
+
+        self.queue.submit(iter::once(encoder.finish()));
+    }
+
+    /// Copy the offscreen texture back to the CPU as tightly packed RGBA8 rows
+    pub async fn read_pixels(&self) -> Result<Vec<u8>> {
+        let (width, height) = self.size;
+
+        // Rows in a texture copy must be padded to a fixed alignment
+        let unpadded_bytes_per_row = width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("headless_readback_buf"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            self.texture.size(),
+        );
+
+        self.queue.submit(iter::once(encoder.finish()));
+
+        // Map the buffer and wait for the copy to finish
+        let slice = buffer.slice(..);
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.await??;
+
+        // Strip the row padding
+        let pixels = slice
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+            .copied()
+            .collect();
+        buffer.unmap();
+
+        Ok(pixels)
+    }
+
+    /// Read back the offscreen texture and write it to a PNG file
+    pub async fn save_png(&self, path: &str) -> Result<()> {
+        let (width, height) = self.size;
+        let pixels = self.read_pixels().await?;
+
+        image::save_buffer(path, &pixels, width, height, image::ColorType::Rgba8)
+            .with_context(|| format!("Failed to write image to {path}"))
+    }
+}
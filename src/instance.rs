@@ -0,0 +1,54 @@
+use anyhow::{Context, Result};
+use cgmath::{Matrix4, SquareMatrix, Vector3};
+
+use crate::bvh::Bounds;
+
+/// Copy of a model placed in the world by a transform, rays are moved into
+/// the model's space to test against its primitives
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Instance {
+    /// Model to world transform, column major
+    pub transform: [[f32; 4]; 4],
+    /// World to model transform
+    pub inverse: [[f32; 4]; 4],
+    /// Index into the geometry's models
+    pub model: u32,
+    /// Index into the material table used for the whole model, or NO_MATERIAL
+    /// to keep the model's own
+    pub material: u32,
+    /// First node of the model's BVH, set when the BVH is built
+    pub root: u32,
+    _pad: u32,
+}
+
+impl Instance {
+    /// Material of instances keeping those of their model's primitives
+    pub const NO_MATERIAL: u32 = u32::MAX;
+
+    /// Fails if the transform can't be inverted, such as when it scales to zero
+    pub fn new(model: u32, transform: Matrix4<f32>, material: Option<u32>) -> Result<Self> {
+        let inverse = transform.invert().context("transform can't be inverted")?;
+        Ok(Self {
+            transform: transform.into(),
+            inverse: inverse.into(),
+            model,
+            material: material.unwrap_or(Instance::NO_MATERIAL),
+            root: 0,
+            _pad: 0,
+        })
+    }
+
+    /// Bounds in the world of the corners of the model's bounds
+    pub fn bounds(&self, model: Bounds) -> Bounds {
+        let transform = Matrix4::from(self.transform);
+        Bounds::around((0..8).map(|corner| {
+            let pick = |axis: usize| match corner >> axis & 1 {
+                0 => model.min[axis],
+                _ => model.max[axis],
+            };
+            let pos = Vector3::new(pick(0), pick(1), pick(2));
+            (transform * pos.extend(1.0)).truncate()
+        }))
+    }
+}
@@ -1,6 +1,12 @@
 use context::GraphicsContext;
+use controller::CameraController;
+use instant::Instant;
+use options::Options;
+use scene::Scene;
 use window::Window;
 use winit::{event::{Event, WindowEvent}, event_loop::ControlFlow};
+#[cfg(not(target_arch = "wasm32"))]
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode};
 use anyhow::Result;
 use cfg_if::cfg_if;
 
@@ -11,6 +17,28 @@ pub mod thread_context;
 pub mod pipeline;
 pub mod camera;
 pub mod sphere;
+pub mod primitive;
+pub mod geometry;
+pub mod mesh;
+pub mod ply;
+pub mod stl;
+pub mod gltf_scene;
+pub mod bvh;
+pub mod instance;
+pub mod material;
+pub mod texture;
+pub mod light;
+pub mod environment;
+pub mod sky;
+pub mod shading;
+pub mod renderer;
+pub mod cpu;
+pub mod scene;
+pub mod options;
+pub mod accumulation;
+pub mod controller;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod headless;
 
 /// Load bytes from path, if compiled for web then do via http request
 pub async fn load_bytes(path: &str) -> Result<Vec<u8>> {
@@ -31,30 +59,141 @@ pub async fn load_bytes(path: &str) -> Result<Vec<u8>> {
     Ok(bytes.to_vec())
 }
 
+/// Load the scene named in the options and apply any camera overrides
+pub async fn load_scene(options: &Options) -> Result<Scene> {
+    let mut scene = Scene::load(&options.scene).await?;
+    options.apply(&mut scene.camera);
+    Ok(scene)
+}
+
+/// Replace the scene with a scene or model file picked in the native file
+/// dialog, the current one is kept if nothing is picked
+#[cfg(not(target_arch = "wasm32"))]
+fn open_scene(context: &mut GraphicsContext, options: &Options) -> Result<()> {
+    let extensions: Vec<&str> = ["ron"].into_iter().chain(Scene::MODEL_EXTENSIONS).collect();
+    let Some(path) = rfd::FileDialog::new()
+        .set_title("Open scene or model")
+        .add_filter("Scenes and models", &extensions)
+        .set_directory("scenes")
+        .pick_file()
+    else {
+        return Ok(());
+    };
+
+    let mut scene = pollster::block_on(Scene::load(&path.to_string_lossy()))?;
+    options.apply(&mut scene.camera);
+    pollster::block_on(context.set_scene(&scene, options));
+    Ok(())
+}
+
+/// Render frames offscreen and write the result to an image file
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn run_headless(options: &Options, output: &str) -> Result<()> {
+    let (width, height) = options.output_size();
+    let scene = load_scene(options).await?;
+
+    let mut context = headless::HeadlessContext::new(width, height, &scene, options).await?;
+    for _ in 0..options.frames {
+        context.render();
+    }
+    context.save_png(output).await
+}
+
+/// Render frames on the CPU and write the result to an image file
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_cpu(options: &Options, output: &str) -> Result<()> {
+    use anyhow::Context;
+
+    let (width, height) = options.output_size();
+    let scene = pollster::block_on(load_scene(options))?;
+    let camera = scene.camera.build([width as f32, height as f32]);
+
+    let geometry = scene.geometry();
+    let shading = scene.shading();
+    let bindings = cpu::Bindings {
+        geometry: &geometry,
+        shading: &shading,
+    };
+
+    let pixels = cpu::render(&camera, &bindings, options.frames);
+    image::save_buffer(output, &cpu::to_rgba8(&pixels), width, height, image::ColorType::Rgba8)
+        .with_context(|| format!("Failed to write image to {output}"))
+}
 
 /// Entry point for web
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
+    run_with(Options::default()).await.unwrap();
+}
+
+/// Run with the given options, rendering headless if an output file is set
+pub async fn run_with(options: Options) -> Result<()> {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
             console_log::init_with_level(log::Level::Warn).expect("Couldn't initialize logger");
         } else {
             env_logger::init();
+
+            if let Some(output) = &options.output {
+                return if options.cpu {
+                    run_cpu(&options, output)
+                } else {
+                    run_headless(&options, output).await
+                };
+            }
         }
     }
 
-    // Create a window and graphics context
-    let window = Window::new();
-    let mut context = GraphicsContext::new(&window).await;
+    // Load the scene, then create a window and graphics context
+    let scene = load_scene(&options).await?;
+    let window = match (options.width, options.height) {
+        (None, None) => Window::new(),
+        _ => {
+            let (width, height) = options.output_size();
+            Window::with_size(width, height)
+        }
+    };
+    let mut context = GraphicsContext::new(&window, &scene, &options).await;
+    let mut controller = CameraController::new(options.speed, options.sensitivity);
+    let mut last_frame = Instant::now();
 
     window.run(move |window, event, control_flow| {
         // Handle Winit Events
         match event {
-            // Render everything
+            // Move the camera and render everything
             Event::RedrawRequested(_) => {
+                let now = Instant::now();
+                let dt = (now - last_frame).as_secs_f32();
+                last_frame = now;
+
+                controller.update(&mut context.renderer.camera.camera, dt);
                 context.render().unwrap();
             }
+            // Camera input
+            Event::WindowEvent { ref event, .. } if controller.process_window_event(event) => {}
+            // Open another scene or model, the dialog stops frames so the time
+            // it was open is skipped
+            #[cfg(not(target_arch = "wasm32"))]
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::O),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                if let Err(err) = open_scene(&mut context, &options) {
+                    log::error!("{err:#}");
+                }
+                last_frame = Instant::now();
+            }
+            Event::DeviceEvent { ref event, .. } => controller.process_device_event(event),
             // Trigger a resize
             Event::WindowEvent {
                 event: WindowEvent::Resized(size),
@@ -75,4 +214,6 @@ pub async fn run() {
             _ => (),
         }
     });
+
+    Ok(())
 }
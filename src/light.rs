@@ -0,0 +1,80 @@
+/// Explicit light source, kind selects which of the other fields are used
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Light {
+    /// Position of point and spherical lights
+    pub pos: [f32; 3],
+    pub kind: u32,
+    /// Direction directional lights and the sun travel in, normalised
+    pub direction: [f32; 3],
+    /// Radius of spherical lights, angular radius in radians of the sun
+    pub radius: f32,
+    /// Intensity of point lights, irradiance of directional lights and
+    /// radiance leaving the surface of spherical lights and the sun
+    pub emission: [f32; 3],
+    _pad: [f32; 1],
+}
+
+impl Light {
+    /// Infinitely small light, falling off with the square of distance
+    pub const POINT: u32 = 0;
+    /// Infinitely far light, like the sun
+    pub const DIRECTIONAL: u32 = 1;
+    /// Glowing sphere which rays can hit
+    pub const SPHERICAL: u32 = 2;
+    /// Infinitely far disk covering a small cone of directions, which rays
+    /// escaping the scene can hit
+    pub const SUN: u32 = 3;
+
+    pub fn point(pos: [f32; 3], intensity: [f32; 3]) -> Self {
+        Self {
+            pos,
+            kind: Light::POINT,
+            emission: intensity,
+            ..Default::default()
+        }
+    }
+
+    pub fn directional(direction: [f32; 3], irradiance: [f32; 3]) -> Self {
+        let [x, y, z] = direction;
+        let length = (x * x + y * y + z * z).sqrt();
+        Self {
+            kind: Light::DIRECTIONAL,
+            direction: [x / length, y / length, z / length],
+            emission: irradiance,
+            ..Default::default()
+        }
+    }
+
+    pub fn spherical(pos: [f32; 3], radius: f32, radiance: [f32; 3]) -> Self {
+        Self {
+            pos,
+            kind: Light::SPHERICAL,
+            radius,
+            emission: radiance,
+            ..Default::default()
+        }
+    }
+
+    pub fn sun(direction: [f32; 3], angular_radius: f32, radiance: [f32; 3]) -> Self {
+        Self {
+            kind: Light::SUN,
+            radius: angular_radius,
+            emission: radiance,
+            ..Light::directional(direction, [0.0; 3])
+        }
+    }
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Self {
+            pos: [0.0, 0.0, 0.0],
+            kind: Light::POINT,
+            direction: [0.0, -1.0, 0.0],
+            radius: 0.0,
+            emission: [0.0, 0.0, 0.0],
+            _pad: Default::default(),
+        }
+    }
+}
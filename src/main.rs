@@ -1,6 +1,9 @@
 #![feature(async_closure)]
 
+use clap::Parser;
+use ray_tracer::options::Options;
+
 /// Entry point for a standalone binary
-fn main() {
-    pollster::block_on(ray_tracer::run());
+fn main() -> anyhow::Result<()> {
+    pollster::block_on(ray_tracer::run_with(Options::parse()))
 }
@@ -0,0 +1,83 @@
+/// Surface properties referenced by index from each primitive, kind selects
+/// how rays scatter and which of the other fields are used
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Material {
+    pub albedo: [f32; 3],
+    pub kind: u32,
+    pub emission: [f32; 3],
+    /// Blur of metal reflections, zero is a perfect mirror
+    pub fuzz: f32,
+    /// Index of refraction of dielectrics
+    pub ior: f32,
+    /// Index into the textures multiplying albedo, or NO_TEXTURE
+    pub texture: u32,
+    _pad: [f32; 2],
+}
+
+impl Material {
+    /// Diffuse surface
+    pub const LAMBERTIAN: u32 = 0;
+    /// Reflective surface
+    pub const METAL: u32 = 1;
+    /// Transparent surface which reflects and refracts, like glass
+    pub const DIELECTRIC: u32 = 2;
+    /// Light emitting surface which does not scatter
+    pub const EMISSIVE: u32 = 3;
+
+    /// Texture of materials with a plain albedo
+    pub const NO_TEXTURE: u32 = u32::MAX;
+
+    pub fn lambertian(albedo: [f32; 3]) -> Self {
+        Self {
+            albedo,
+            kind: Material::LAMBERTIAN,
+            ..Default::default()
+        }
+    }
+
+    pub fn metal(albedo: [f32; 3], fuzz: f32) -> Self {
+        Self {
+            albedo,
+            kind: Material::METAL,
+            fuzz,
+            ..Default::default()
+        }
+    }
+
+    pub fn dielectric(ior: f32) -> Self {
+        Self {
+            albedo: [1.0, 1.0, 1.0],
+            kind: Material::DIELECTRIC,
+            ior,
+            ..Default::default()
+        }
+    }
+
+    pub fn emissive(emission: [f32; 3]) -> Self {
+        Self {
+            kind: Material::EMISSIVE,
+            emission,
+            ..Default::default()
+        }
+    }
+
+    /// Albedo multiplied by a texture, looked up by texture coordinates
+    pub fn with_texture(self, texture: u32) -> Self {
+        Self { texture, ..self }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            albedo: [0.0, 0.0, 0.0],
+            kind: Material::LAMBERTIAN,
+            emission: [0.0, 0.0, 0.0],
+            fuzz: 0.0,
+            ior: 1.0,
+            texture: Material::NO_TEXTURE,
+            _pad: Default::default(),
+        }
+    }
+}
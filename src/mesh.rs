@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{
+    load_bytes,
+    material::Material,
+    ply::parse_ply,
+    primitive::{MeshVertex, Triangle},
+    stl::parse_stl,
+};
+
+/// Triangles loaded from a model file, with material indices into its own
+/// material table
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mesh {
+    pub vertices: Vec<MeshVertex>,
+    pub triangles: Vec<Triangle>,
+    pub materials: Vec<Material>,
+}
+
+impl Mesh {
+    /// Albedo of faces without a material, or whose material wasn't found
+    pub const DEFAULT_ALBEDO: [f32; 3] = [0.8, 0.8, 0.8];
+
+    /// Load a Wavefront OBJ file and the MTL libraries it names, which are
+    /// found next to it
+    pub async fn load_obj(path: &str) -> Result<Mesh> {
+        let bytes = load_bytes(path)
+            .await
+            .with_context(|| format!("Failed to read mesh {path}"))?;
+        let obj = Obj::parse(&bytes).with_context(|| format!("Failed to load mesh {path}"))?;
+
+        let mut materials = HashMap::new();
+        for library in &obj.libraries {
+            let library = sibling_path(path, library);
+            let bytes = load_bytes(&library)
+                .await
+                .with_context(|| format!("Failed to read material library {library}"))?;
+            let library_materials = parse_mtl(&bytes)
+                .with_context(|| format!("Failed to load material library {library}"))?;
+            materials.extend(library_materials);
+        }
+        Ok(obj.into_mesh(&materials))
+    }
+
+    /// Load a PLY file, ASCII or binary, with any vertex colours
+    pub async fn load_ply(path: &str) -> Result<Mesh> {
+        let bytes = load_bytes(path)
+            .await
+            .with_context(|| format!("Failed to read mesh {path}"))?;
+        parse_ply(&bytes).with_context(|| format!("Failed to load mesh {path}"))
+    }
+
+    /// Load an STL file, ASCII or binary
+    pub async fn load_stl(path: &str) -> Result<Mesh> {
+        let bytes = load_bytes(path)
+            .await
+            .with_context(|| format!("Failed to read mesh {path}"))?;
+        parse_stl(&bytes).with_context(|| format!("Failed to load mesh {path}"))
+    }
+}
+
+/// Path of a file named relative to the directory another file is in
+pub(crate) fn sibling_path(path: &str, name: &str) -> String {
+    match path.rfind(['/', '\\']) {
+        Some(end) => format!("{}/{name}", &path[..end]),
+        None => name.to_string(),
+    }
+}
+
+/// Corner of an OBJ face, indices into the positions and normals
+type Corner = (usize, Option<usize>);
+
+/// Contents of a Wavefront OBJ file, before its materials are known
+#[derive(Clone, Debug, Default)]
+pub struct Obj {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    /// Corners of each polygon with the index of its material name
+    faces: Vec<(Vec<Corner>, Option<usize>)>,
+    material_names: Vec<String>,
+    /// MTL files named by `mtllib`
+    pub libraries: Vec<String>,
+}
+
+impl Obj {
+    /// Parse positions, normals and polygonal faces, texture coordinates,
+    /// groups and anything else are ignored
+    pub fn parse(bytes: &[u8]) -> Result<Obj> {
+        let text = std::str::from_utf8(bytes).context("OBJ file is not UTF-8")?;
+        let mut obj = Obj::default();
+        let mut material = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let Some(keyword) = words.next() else {
+                continue;
+            };
+            let context = || format!("Invalid OBJ line {}: {}", number + 1, line.trim());
+            match keyword {
+                "v" => obj
+                    .positions
+                    .push(parse_floats(&mut words).with_context(context)?),
+                "vn" => obj
+                    .normals
+                    .push(parse_floats(&mut words).with_context(context)?),
+                "f" => {
+                    let corners = words
+                        .map(|corner| obj.parse_corner(corner))
+                        .collect::<Result<Vec<Corner>>>()
+                        .with_context(context)?;
+                    if corners.len() < 3 {
+                        return Err(anyhow!("a face needs at least 3 corners"))
+                            .with_context(context);
+                    }
+                    obj.faces.push((corners, material));
+                }
+                "usemtl" => {
+                    let name = words.collect::<Vec<_>>().join(" ");
+                    let index = match obj.material_names.iter().position(|n| *n == name) {
+                        Some(index) => index,
+                        None => {
+                            obj.material_names.push(name);
+                            obj.material_names.len() - 1
+                        }
+                    };
+                    material = Some(index);
+                }
+                "mtllib" => obj.libraries.extend(words.map(str::to_string)),
+                _ => {}
+            }
+        }
+        Ok(obj)
+    }
+
+    /// Parse a face corner written as `v`, `v/vt`, `v//vn` or `v/vt/vn`,
+    /// indices count from one or back from the end when negative
+    fn parse_corner(&self, corner: &str) -> Result<Corner> {
+        let mut indices = corner.split('/');
+        let resolve = |index: &str, count: usize, kind: &str| -> Result<usize> {
+            let index: i64 = index
+                .parse()
+                .with_context(|| format!("invalid {kind} index {index}"))?;
+            let resolved = if index < 0 {
+                count as i64 + index
+            } else {
+                index - 1
+            };
+            if !(0..count as i64).contains(&resolved) {
+                return Err(anyhow!(
+                    "{kind} index {index} is out of range, there are {count}"
+                ));
+            }
+            Ok(resolved as usize)
+        };
+        let position = resolve(
+            indices.next().unwrap_or_default(),
+            self.positions.len(),
+            "position",
+        )?;
+        let normal = match indices.nth(1) {
+            Some(normal) if !normal.is_empty() => {
+                Some(resolve(normal, self.normals.len(), "normal")?)
+            }
+            _ => None,
+        };
+        Ok((position, normal))
+    }
+
+    /// Triangulate the faces into a mesh, materials are looked up by name
+    pub fn into_mesh(self, materials: &HashMap<String, Material>) -> Mesh {
+        let mut mesh = Mesh::default();
+
+        // Materials in order of first use, shared by faces without one
+        let mut material_indices: HashMap<Option<usize>, u32> = HashMap::new();
+        let mut vertex_indices: HashMap<Corner, u32> = HashMap::new();
+        for (corners, name) in &self.faces {
+            let material = *material_indices.entry(*name).or_insert_with(|| {
+                let material = name
+                    .and_then(|name| materials.get(&self.material_names[name]))
+                    .copied()
+                    .unwrap_or(Material::lambertian(Mesh::DEFAULT_ALBEDO));
+                mesh.materials.push(material);
+                mesh.materials.len() as u32 - 1
+            });
+
+            let vertices: Vec<u32> = corners
+                .iter()
+                .map(|&corner| {
+                    *vertex_indices.entry(corner).or_insert_with(|| {
+                        let (position, normal) = corner;
+                        let normal = normal.map_or([0.0; 3], |normal| self.normals[normal]);
+                        mesh.vertices
+                            .push(MeshVertex::new(self.positions[position], normal));
+                        mesh.vertices.len() as u32 - 1
+                    })
+                })
+                .collect();
+
+            // Polygons are assumed convex and split into a fan
+            for i in 1..vertices.len() - 1 {
+                mesh.triangles.push(Triangle::new(
+                    [vertices[0], vertices[i], vertices[i + 1]],
+                    material,
+                ));
+            }
+        }
+        mesh
+    }
+}
+
+fn parse_float<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<f32> {
+    let word = words.next().context("expected a number")?;
+    word.parse()
+        .with_context(|| format!("invalid number {word}"))
+}
+
+/// Parse the first three numbers of a line, any more are ignored
+pub(crate) fn parse_floats<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<[f32; 3]> {
+    let mut values = [0.0; 3];
+    for value in &mut values {
+        *value = parse_float(words)?;
+    }
+    Ok(values)
+}
+
+/// Wavefront MTL material before choosing which kind of Material it is
+#[derive(Clone, Copy, Debug)]
+struct Mtl {
+    diffuse: [f32; 3],
+    specular: [f32; 3],
+    emission: [f32; 3],
+    /// Phong exponent, the higher the sharper the reflections
+    shininess: f32,
+    ior: Option<f32>,
+    /// Opacity, 1 is opaque
+    dissolve: f32,
+    illum: u32,
+}
+
+impl Default for Mtl {
+    fn default() -> Self {
+        Self {
+            diffuse: Mesh::DEFAULT_ALBEDO,
+            specular: [0.0; 3],
+            emission: [0.0; 3],
+            shininess: 0.0,
+            ior: None,
+            dissolve: 1.0,
+            illum: 2,
+        }
+    }
+}
+
+impl From<Mtl> for Material {
+    /// Emissive if it glows, glass if it is see through or refracts, metal if
+    /// it mirrors or reflects more than it diffuses, otherwise diffuse
+    fn from(mtl: Mtl) -> Self {
+        let mean = |[r, g, b]: [f32; 3]| (r + g + b) / 3.0;
+        if mtl.emission.iter().any(|&e| e > 0.0) {
+            Material::emissive(mtl.emission)
+        } else if mtl.dissolve < 1.0 || matches!(mtl.illum, 4 | 6 | 7 | 9) {
+            Material::dielectric(mtl.ior.filter(|&ior| ior > 1.0).unwrap_or(1.5))
+        } else if mtl.illum == 3 || mean(mtl.specular) > mean(mtl.diffuse) {
+            // Roughness of the Beckmann distribution matching a Phong exponent
+            let fuzz = (2.0 / (mtl.shininess + 2.0)).sqrt().min(1.0);
+            Material::metal(mtl.specular, fuzz)
+        } else {
+            Material::lambertian(mtl.diffuse)
+        }
+    }
+}
+
+/// Parse the materials of a Wavefront MTL file by name, textures are ignored
+pub fn parse_mtl(bytes: &[u8]) -> Result<HashMap<String, Material>> {
+    let text = std::str::from_utf8(bytes).context("MTL file is not UTF-8")?;
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, Mtl)> = None;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let context = || format!("Invalid MTL line {}: {}", number + 1, line.trim());
+        if keyword == "newmtl" {
+            if let Some((name, mtl)) = current.take() {
+                materials.insert(name, Material::from(mtl));
+            }
+            current = Some((words.collect::<Vec<_>>().join(" "), Mtl::default()));
+            continue;
+        }
+        let Some((_, mtl)) = &mut current else {
+            continue;
+        };
+        match keyword {
+            "Kd" => mtl.diffuse = parse_floats(&mut words).with_context(context)?,
+            "Ks" => mtl.specular = parse_floats(&mut words).with_context(context)?,
+            "Ke" => mtl.emission = parse_floats(&mut words).with_context(context)?,
+            "Ns" => mtl.shininess = parse_float(&mut words).with_context(context)?,
+            "Ni" => mtl.ior = Some(parse_float(&mut words).with_context(context)?),
+            "d" => mtl.dissolve = parse_float(&mut words).with_context(context)?,
+            "Tr" => mtl.dissolve = 1.0 - parse_float(&mut words).with_context(context)?,
+            "illum" => mtl.illum = parse_float(&mut words).with_context(context)? as u32,
+            _ => {}
+        }
+    }
+    if let Some((name, mtl)) = current {
+        materials.insert(name, Material::from(mtl));
+    }
+    Ok(materials)
+}
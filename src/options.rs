@@ -0,0 +1,161 @@
+use clap::{Parser, ValueEnum};
+
+use crate::{
+    camera::{Jitter, Sampler},
+    scene::{Scene, SceneCamera},
+};
+
+/// Command line options for the ray tracer
+#[derive(Debug, Clone, Parser)]
+#[command(version, about)]
+pub struct Options {
+    /// Scene file to render, or a glTF, OBJ, PLY or STL model to view by itself
+    #[arg(short, long, default_value = Scene::DEFAULT_PATH)]
+    pub scene: String,
+
+    /// Width of the window or output image
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: Option<u32>,
+
+    /// Height of the window or output image
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub height: Option<u32>,
+
+    /// Maximum number of bounces per ray, overrides the scene
+    #[arg(short = 'd', long, value_parser = clap::value_parser!(i32).range(0..))]
+    pub max_depth: Option<i32>,
+
+    /// Number of samples per pixel per frame, overrides the scene
+    #[arg(short = 'n', long)]
+    pub samples: Option<u32>,
+
+    /// How samples are placed within each pixel, overrides the scene
+    #[arg(long, value_enum)]
+    pub jitter: Option<Jitter>,
+
+    /// Source of samples for jitter, lens and bounces, overrides the scene
+    #[arg(long, value_enum)]
+    pub sampler: Option<Sampler>,
+
+    /// Comma separated list of backends to use, e.g. "vulkan,gl"
+    #[arg(long, value_parser = parse_backends)]
+    pub backends: Option<wgpu::Backends>,
+
+    /// Power preference used when selecting an adapter
+    #[arg(long, value_enum, default_value_t = PowerPreference::Default)]
+    pub power_preference: PowerPreference,
+
+    /// Only use a fallback (software) adapter
+    #[arg(long)]
+    pub fallback_adapter: bool,
+
+    /// Whether frames are traced by a fragment or a compute shader
+    #[arg(long, value_enum, default_value_t = TraceMode::Fragment)]
+    pub trace: TraceMode,
+
+    /// Camera movement speed in units per second
+    #[arg(long, default_value_t = 1.0)]
+    pub speed: f32,
+
+    /// Mouse look sensitivity in radians per pixel
+    #[arg(long, default_value_t = 0.003)]
+    pub sensitivity: f32,
+
+    /// Render without a window and write the result to this image file
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Number of frames to render before writing the output image
+    #[arg(short, long, default_value_t = 1)]
+    pub frames: u32,
+
+    /// Render the output image on the CPU instead of the GPU
+    #[arg(long, requires = "output")]
+    pub cpu: bool,
+}
+
+/// Mirror of wgpu::PowerPreference usable as a command line value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PowerPreference {
+    Default,
+    Low,
+    High,
+}
+
+/// Shader stage which traces rays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TraceMode {
+    /// Fragment shader of a full screen quad
+    Fragment,
+    /// Compute shader writing to a storage texture
+    Compute,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options::parse_from(["ray_tracer"])
+    }
+}
+
+impl Options {
+    /// Size of the output image or window when not given
+    pub const DEFAULT_SIZE: (u32, u32) = (800, 600);
+
+    /// Apply any camera overrides to the scene camera
+    pub fn apply(&self, camera: &mut SceneCamera) {
+        if let Some(max_depth) = self.max_depth {
+            camera.max_depth = max_depth;
+        }
+        if let Some(samples) = self.samples {
+            camera.samples = samples;
+        }
+        if let Some(jitter) = self.jitter {
+            camera.jitter = jitter;
+        }
+        if let Some(sampler) = self.sampler {
+            camera.sampler = sampler;
+        }
+    }
+
+    /// Size of the output image or window, using the default for unset dimensions
+    pub fn output_size(&self) -> (u32, u32) {
+        (
+            self.width.unwrap_or(Options::DEFAULT_SIZE.0),
+            self.height.unwrap_or(Options::DEFAULT_SIZE.1),
+        )
+    }
+
+    /// Descriptor for creating the wgpu instance
+    pub fn instance_descriptor(&self) -> wgpu::InstanceDescriptor {
+        wgpu::InstanceDescriptor {
+            backends: self.backends.unwrap_or(wgpu::Backends::all()),
+            ..Default::default()
+        }
+    }
+
+    /// Options for requesting an adapter compatible with the given surface
+    pub fn adapter_options<'a>(
+        &self,
+        compatible_surface: Option<&'a wgpu::Surface>,
+    ) -> wgpu::RequestAdapterOptions<'a> {
+        wgpu::RequestAdapterOptions {
+            power_preference: match self.power_preference {
+                PowerPreference::Default => wgpu::PowerPreference::default(),
+                PowerPreference::Low => wgpu::PowerPreference::LowPower,
+                PowerPreference::High => wgpu::PowerPreference::HighPerformance,
+            },
+            compatible_surface,
+            force_fallback_adapter: self.fallback_adapter,
+        }
+    }
+}
+
+/// Parse a comma separated list of backend names
+fn parse_backends(s: &str) -> Result<wgpu::Backends, String> {
+    let backends = wgpu::util::parse_backends_from_comma_list(s);
+    if backends.is_empty() {
+        Err(format!("no known backends in `{s}`"))
+    } else {
+        Ok(backends)
+    }
+}
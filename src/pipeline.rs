@@ -1,41 +1,95 @@
-use crate::{load_bytes, vertex::Vertex};
+use crate::{accumulation::Accumulation, load_bytes, vertex::Vertex};
 
 pub struct Pipeline {
     // layout: wgpu::BindGroupLayout,
     pub pipeline: wgpu::RenderPipeline,
+    pub display: wgpu::RenderPipeline,
+    /// Traces in place of pipeline when tracing with a compute shader
+    pub compute: Option<wgpu::ComputePipeline>,
 }
 
 impl Pipeline {
     pub async fn new(
         device: &wgpu::Device,
-        camera_layout: &wgpu::BindGroupLayout,
-        spheres_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        compute_bind_group_layouts: Option<&[&wgpu::BindGroupLayout]>,
     ) -> Self {
         let shader = Pipeline::load_shader(device, "./src/raytrace.wgsl").await;
 
         let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("render_pipeline_layout"),
-            bind_group_layouts: &[camera_layout, spheres_layout],
+            bind_group_layouts,
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("raytrace"),
-            layout: Some(&layout),
-            vertex: wgpu::VertexState {
+        // Trace into the accumulation texture, then display it on the target
+        let pipeline = Pipeline::create_render_pipeline(
+            device,
+            &shader,
+            &layout,
+            "fs_main",
+            Accumulation::FORMAT,
+            None,
+        );
+        let display = Pipeline::create_render_pipeline(
+            device,
+            &shader,
+            &layout,
+            "fs_display",
+            format,
+            Some(wgpu::BlendState {
+                color: wgpu::BlendComponent::REPLACE,
+                alpha: wgpu::BlendComponent::REPLACE,
+            }),
+        );
+
+        // Trace with a compute shader instead if layouts for it are given
+        let compute = compute_bind_group_layouts.map(|bind_group_layouts| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("compute_pipeline_layout"),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            });
+
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("cs_main"),
+                layout: Some(&layout),
                 module: &shader,
+                entry_point: "cs_main",
+            })
+        });
+
+        Pipeline {
+            pipeline,
+            display,
+            compute,
+        }
+    }
+
+    /// Create a full screen render pipeline using the given fragment entry point
+    fn create_render_pipeline(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        layout: &wgpu::PipelineLayout,
+        entry_point: &str,
+        format: wgpu::TextureFormat,
+        blend: Option<wgpu::BlendState>,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
                 entry_point: "vs_main",
                 buffers: &[Vertex::desc()],
             },
             fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
+                module: shader,
+                entry_point,
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: wgpu::TextureFormat::Bgra8UnormSrgb,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
+                    format,
+                    blend,
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
@@ -55,9 +109,7 @@ impl Pipeline {
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
-        });
-
-        Pipeline { pipeline }
+        })
     }
 
     async fn load_shader(device: &wgpu::Device, path: &str) -> wgpu::ShaderModule {
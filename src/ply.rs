@@ -0,0 +1,324 @@
+use anyhow::{anyhow, Context, Result};
+
+use crate::{
+    cpu::srgb_to_linear,
+    material::Material,
+    mesh::Mesh,
+    primitive::{MeshVertex, Triangle},
+};
+
+/// How the body of a PLY file after its header is written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+/// Type of a PLY property value, with its size in bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Scalar> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(anyhow!("unknown property type {name}")),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    /// Largest value of integer types, which colours are fractions of
+    fn colour_scale(self) -> f64 {
+        match self {
+            Scalar::I8 => i8::MAX as f64,
+            Scalar::U8 => u8::MAX as f64,
+            Scalar::I16 => i16::MAX as f64,
+            Scalar::U16 => u16::MAX as f64,
+            Scalar::I32 => i32::MAX as f64,
+            Scalar::U32 => u32::MAX as f64,
+            Scalar::F32 | Scalar::F64 => 1.0,
+        }
+    }
+}
+
+/// Property of each item of an element, either one value or a list of them
+/// preceded by its length
+#[derive(Clone, Debug)]
+struct Property {
+    name: String,
+    list: Option<Scalar>,
+    scalar: Scalar,
+}
+
+/// Element of a PLY file, such as its vertices or faces
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// Reads the values of the body one at a time
+struct Body<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    offset: usize,
+    words: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl Body<'_> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64> {
+        if self.format == Format::Ascii {
+            let word = self.words.next().context("unexpected end of file")?;
+            return word
+                .parse()
+                .with_context(|| format!("invalid number {word}"));
+        }
+
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + scalar.size())
+            .context("unexpected end of file")?;
+        self.offset += scalar.size();
+        let mut value = [0; 8];
+        value[..bytes.len()].copy_from_slice(bytes);
+        if self.format == Format::BinaryBigEndian {
+            value[..bytes.len()].reverse();
+        }
+        Ok(match scalar {
+            Scalar::I8 => value[0] as i8 as f64,
+            Scalar::U8 => value[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([value[0], value[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([value[0], value[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes(value[..4].try_into().unwrap()) as f64,
+            Scalar::U32 => u32::from_le_bytes(value[..4].try_into().unwrap()) as f64,
+            Scalar::F32 => f32::from_le_bytes(value[..4].try_into().unwrap()) as f64,
+            Scalar::F64 => f64::from_le_bytes(value),
+        })
+    }
+}
+
+/// Parse a Stanford PLY file in ASCII or either binary format. Vertices need
+/// x, y and z and may have nx, ny and nz normals and red, green and blue
+/// sRGB colours, faces are polygons split into fans. Every face shares one
+/// material, white with vertex colours to show them as they are
+pub fn parse_ply(bytes: &[u8]) -> Result<Mesh> {
+    let end = b"end_header";
+    let header_end = bytes
+        .windows(end.len())
+        .position(|window| window == end)
+        .context("PLY header has no end_header")?;
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(bytes.len(), |newline| header_end + newline + 1);
+    let header = std::str::from_utf8(&bytes[..header_end]).context("PLY header is not UTF-8")?;
+
+    let (format, elements) = parse_header(header)?;
+
+    let text = match format {
+        Format::Ascii => {
+            std::str::from_utf8(&bytes[body_start..]).context("PLY body is not UTF-8")?
+        }
+        _ => "",
+    };
+    let mut body = Body {
+        format,
+        bytes: &bytes[body_start..],
+        offset: 0,
+        words: text.split_ascii_whitespace(),
+    };
+
+    let mut mesh = Mesh::default();
+    let mut faces = Vec::new();
+    let mut coloured = false;
+    for element in &elements {
+        let find = |name: &str| element.properties.iter().position(|p| p.name == name);
+        let [x, y, z, nx, ny, nz, red, green, blue] =
+            ["x", "y", "z", "nx", "ny", "nz", "red", "green", "blue"].map(find);
+        let indices = find("vertex_indices").or_else(|| find("vertex_index"));
+        if element.name == "vertex" && [x, y, z].contains(&None) {
+            return Err(anyhow!("PLY vertices need x, y and z"));
+        }
+        coloured |= element.name == "vertex" && ![red, green, blue].contains(&None);
+
+        let mut values = vec![0.0; element.properties.len()];
+        for item in 0..element.count {
+            let mut list = Vec::new();
+            for (i, property) in element.properties.iter().enumerate() {
+                let read = |body: &mut Body| {
+                    body.read(property.scalar).with_context(|| {
+                        format!("Invalid {} {item} {}", element.name, property.name)
+                    })
+                };
+                match property.list {
+                    Some(length) => {
+                        let length =
+                            body.read(length).and_then(whole_number).with_context(|| {
+                                format!("Invalid {} {item} {} length", element.name, property.name)
+                            })?;
+                        let items = (0..length)
+                            .map(|_| read(&mut body))
+                            .collect::<Result<_>>()?;
+                        if Some(i) == indices {
+                            list = items;
+                        }
+                    }
+                    None => values[i] = read(&mut body)?,
+                }
+            }
+
+            match element.name.as_str() {
+                "vertex" => {
+                    let value = |i: Option<usize>| i.map_or(0.0, |i| values[i] as f32);
+                    let normal = match [nx, ny, nz].contains(&None) {
+                        true => [0.0; 3],
+                        false => [nx, ny, nz].map(value),
+                    };
+                    let mut vertex = MeshVertex::new([x, y, z].map(value), normal);
+                    if coloured {
+                        vertex = vertex.with_colour([red, green, blue].map(|i| {
+                            let i = i.unwrap();
+                            let scale = element.properties[i].scalar.colour_scale();
+                            srgb_to_linear((values[i] / scale).clamp(0.0, 1.0) as f32)
+                        }));
+                    }
+                    mesh.vertices.push(vertex);
+                }
+                "face" => faces.push(list),
+                _ => {}
+            }
+        }
+    }
+
+    for (i, face) in faces.iter().enumerate() {
+        let face = face
+            .iter()
+            .map(|&index| whole_number(index))
+            .collect::<Result<Vec<u32>>>()
+            .with_context(|| format!("Invalid face {i}"))?;
+        if face.len() < 3 {
+            return Err(anyhow!(
+                "Invalid face {i}: a face needs at least 3 vertices"
+            ));
+        }
+        if let Some(index) = face
+            .iter()
+            .find(|&&index| index as usize >= mesh.vertices.len())
+        {
+            return Err(anyhow!(
+                "Invalid face {i}: vertex {index} does not exist, the file has {} vertices",
+                mesh.vertices.len()
+            ));
+        }
+        // Polygons are assumed convex and split into a fan
+        for i in 1..face.len() - 1 {
+            mesh.triangles
+                .push(Triangle::new([face[0], face[i], face[i + 1]], 0));
+        }
+    }
+    let albedo = match coloured {
+        true => [1.0; 3],
+        false => Mesh::DEFAULT_ALBEDO,
+    };
+    mesh.materials.push(Material::lambertian(albedo));
+    Ok(mesh)
+}
+
+/// List lengths and vertex indices, which are read like any other value but
+/// must be whole and not negative
+fn whole_number(value: f64) -> Result<u32> {
+    if value >= 0.0 && value <= u32::MAX as f64 && value.fract() == 0.0 {
+        Ok(value as u32)
+    } else {
+        Err(anyhow!("{value} is not a whole number"))
+    }
+}
+
+/// Format and elements declared by the header, up to end_header
+fn parse_header(header: &str) -> Result<(Format, Vec<Element>)> {
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err(anyhow!("not a PLY file"));
+    }
+    let mut format = None;
+    let mut elements = Vec::new();
+    for (number, line) in lines.enumerate() {
+        parse_header_line(line, &mut format, &mut elements)
+            .with_context(|| format!("Invalid header line {}", number + 2))?;
+    }
+    Ok((format.context("PLY header has no format")?, elements))
+}
+
+fn parse_header_line(
+    line: &str,
+    format: &mut Option<Format>,
+    elements: &mut Vec<Element>,
+) -> Result<()> {
+    let mut words = line.split_ascii_whitespace();
+    match words.next() {
+        Some("format") => {
+            *format = Some(match words.next() {
+                Some("ascii") => Format::Ascii,
+                Some("binary_little_endian") => Format::BinaryLittleEndian,
+                Some("binary_big_endian") => Format::BinaryBigEndian,
+                other => return Err(anyhow!("unknown format {}", other.unwrap_or_default())),
+            });
+        }
+        Some("element") => {
+            let name = words.next().context("expected an element name")?;
+            let count = words.next().context("expected an element count")?;
+            elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .with_context(|| format!("invalid count {count}"))?,
+                properties: Vec::new(),
+            });
+        }
+        Some("property") => {
+            let element = elements.last_mut().context("property before any element")?;
+            let mut scalar = words.next().context("expected a property type")?;
+            let mut list = None;
+            if scalar == "list" {
+                let length = words.next().context("expected a list length type")?;
+                list = Some(Scalar::parse(length)?);
+                scalar = words.next().context("expected a list item type")?;
+            }
+            let name = words.next().context("expected a property name")?;
+            element.properties.push(Property {
+                name: name.to_string(),
+                list,
+                scalar: Scalar::parse(scalar)?,
+            });
+        }
+        Some("comment" | "obj_info") | None => {}
+        Some(keyword) => return Err(anyhow!("unknown keyword {keyword}")),
+    }
+    Ok(())
+}
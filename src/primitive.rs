@@ -0,0 +1,215 @@
+use cgmath::{Deg, InnerSpace, Matrix3, Vector3};
+
+use crate::bvh::Bounds;
+
+/// Rotation by the given angles in degrees around the x, y and z axes, in
+/// that order
+pub fn rotation_matrix(rotation: [f32; 3]) -> Matrix3<f32> {
+    let [x, y, z] = rotation;
+    Matrix3::from_angle_z(Deg(z)) * Matrix3::from_angle_y(Deg(y)) * Matrix3::from_angle_x(Deg(x))
+}
+
+/// Infinite plane through a point
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Plane {
+    pub pos: [f32; 3],
+    /// Index into the material table
+    pub material: u32,
+    /// Outward normal, normalised
+    pub normal: [f32; 3],
+    _pad: f32,
+}
+
+impl Plane {
+    pub fn new(pos: [f32; 3], normal: [f32; 3], material: u32) -> Self {
+        Self {
+            pos,
+            material,
+            normal: Vector3::from(normal).normalize().into(),
+            _pad: 0.0,
+        }
+    }
+}
+
+/// Flat circle, one sided only in which way its normal points
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Disc {
+    /// Centre of the disc
+    pub pos: [f32; 3],
+    pub radius: f32,
+    /// Normal of the front face, normalised
+    pub normal: [f32; 3],
+    /// Index into the material table
+    pub material: u32,
+}
+
+impl Disc {
+    pub fn new(pos: [f32; 3], normal: [f32; 3], radius: f32, material: u32) -> Self {
+        Self {
+            pos,
+            radius,
+            normal: Vector3::from(normal).normalize().into(),
+            material,
+        }
+    }
+
+    /// Along each axis the disc reaches out by the radius scaled by how much
+    /// the disc lies across that axis
+    pub fn bounds(&self) -> Bounds {
+        let pos = Vector3::from(self.pos);
+        let reach = Vector3::from(
+            self.normal
+                .map(|n| self.radius * (1.0 - n * n).max(0.0).sqrt()),
+        );
+        Bounds {
+            min: pos - reach,
+            max: pos + reach,
+        }
+    }
+}
+
+/// Box, which would clash with std's Box, rotated about its centre
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Cuboid {
+    /// Centre of the box
+    pub pos: [f32; 3],
+    /// Index into the material table
+    pub material: u32,
+    /// Half the size along each of the box's axes
+    pub half_size: [f32; 3],
+    _pad: f32,
+    /// The box's x, y and z axes in world space, columns of a rotation
+    /// matrix, the last component of each is unused
+    pub axes: [[f32; 4]; 3],
+}
+
+impl Cuboid {
+    /// Box spanning min to max, then rotated about its centre by the given
+    /// angles in degrees around the x, y and z axes, in that order
+    pub fn new(min: [f32; 3], max: [f32; 3], rotation: [f32; 3], material: u32) -> Self {
+        let (min, max) = (Vector3::from(min), Vector3::from(max));
+        let rotation = rotation_matrix(rotation);
+        let axis = |v: Vector3<f32>| [v.x, v.y, v.z, 0.0];
+        Self {
+            pos: ((min + max) / 2.0).into(),
+            material,
+            half_size: ((max - min) / 2.0).into(),
+            _pad: 0.0,
+            axes: [axis(rotation.x), axis(rotation.y), axis(rotation.z)],
+        }
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        let pos = Vector3::from(self.pos);
+        let reach = Vector3::from(std::array::from_fn(|i| {
+            (0..3)
+                .map(|axis| self.axes[axis][i].abs() * self.half_size[axis])
+                .sum()
+        }));
+        Bounds {
+            min: pos - reach,
+            max: pos + reach,
+        }
+    }
+}
+
+/// Parallelogram with a corner at pos and edges u and v, its front face is
+/// the side u x v points to
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Quad {
+    pub pos: [f32; 3],
+    /// Index into the material table
+    pub material: u32,
+    pub u: [f32; 3],
+    _pad0: f32,
+    pub v: [f32; 3],
+    _pad1: f32,
+}
+
+impl Quad {
+    pub fn new(pos: [f32; 3], u: [f32; 3], v: [f32; 3], material: u32) -> Self {
+        Self {
+            pos,
+            material,
+            u,
+            _pad0: 0.0,
+            v,
+            _pad1: 0.0,
+        }
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        let (pos, u, v) = (
+            Vector3::from(self.pos),
+            Vector3::from(self.u),
+            Vector3::from(self.v),
+        );
+        Bounds::around([pos, pos + u, pos + v, pos + u + v])
+    }
+}
+
+/// Corner of mesh triangles, shared between the triangles meeting there
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MeshVertex {
+    pub pos: [f32; 3],
+    /// Texture coordinates, in what would otherwise be padding after each
+    /// vector
+    pub u: f32,
+    /// Normal interpolated across the triangles for smooth shading, zero to
+    /// shade them flat
+    pub normal: [f32; 3],
+    pub v: f32,
+    /// Linear colour multiplying the albedo, interpolated across the triangles
+    pub colour: [f32; 3],
+    _pad: f32,
+}
+
+impl MeshVertex {
+    pub fn new(pos: [f32; 3], normal: [f32; 3]) -> Self {
+        Self {
+            pos,
+            u: 0.0,
+            normal,
+            v: 0.0,
+            colour: [1.0; 3],
+            _pad: 0.0,
+        }
+    }
+
+    pub fn with_uv(self, [u, v]: [f32; 2]) -> Self {
+        Self { u, v, ..self }
+    }
+
+    pub fn with_colour(self, colour: [f32; 3]) -> Self {
+        Self { colour, ..self }
+    }
+}
+
+/// Triangle of a mesh, its front face is the side its vertices are
+/// anticlockwise from
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Triangle {
+    /// Indices into the vertex buffer
+    pub vertices: [u32; 3],
+    /// Index into the material table
+    pub material: u32,
+}
+
+impl Triangle {
+    pub fn new(vertices: [u32; 3], material: u32) -> Self {
+        Self { vertices, material }
+    }
+
+    pub fn bounds(&self, vertices: &[MeshVertex]) -> Bounds {
+        Bounds::around(
+            self.vertices
+                .map(|i| Vector3::from(vertices[i as usize].pos)),
+        )
+    }
+}
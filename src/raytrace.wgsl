@@ -4,22 +4,132 @@ var<uniform> camera : Camera;
 @group(1) @binding(0)
 var<storage, read> spheres: Spheres;
 
+@group(1) @binding(1)
+var<storage, read> planes: Planes;
+
+@group(1) @binding(2)
+var<storage, read> discs: Discs;
+
+@group(1) @binding(3)
+var<storage, read> boxes: Boxes;
+
+@group(1) @binding(4)
+var<storage, read> quads: Quads;
+
+@group(1) @binding(5)
+var<storage, read> vertices: Vertices;
+
+@group(1) @binding(6)
+var<storage, read> triangles: Triangles;
+
+@group(1) @binding(7)
+var<storage, read> bvh_nodes: BvhNodes;
+
+@group(1) @binding(8)
+var<storage, read> bvh_primitives: BvhPrimitives;
+
+@group(1) @binding(9)
+var<storage, read> instances: Instances;
+
+@group(2) @binding(0)
+var accumulated: texture_2d<f32>;
+
+// Only bound when tracing with cs_main
+@group(2) @binding(1)
+var output: texture_storage_2d<rgba32float, write>;
+
+@group(3) @binding(0)
+var<storage, read> materials: Materials;
+
+@group(3) @binding(1)
+var<storage, read> lights: Lights;
+
+// Black 1x1 texture when the scene has no environment map
+@group(3) @binding(2)
+var environment: texture_2d<f32>;
+
+@group(3) @binding(3)
+var environment_sampler: sampler;
+
+@group(3) @binding(4)
+var<storage, read> sky_distribution: SkyDistribution;
+
+@group(3) @binding(5)
+var<storage, read> textures: Textures;
+
 const EPSILON = 0.0001;
+const PI = 3.14159265359;
+
+// Material kinds
+const LAMBERTIAN = 0u;
+const METAL = 1u;
+const DIELECTRIC = 2u;
+const EMISSIVE = 3u;
+
+// Light kinds
+const POINT = 0u;
+const DIRECTIONAL = 1u;
+const SPHERICAL = 2u;
+const SUN = 3u;
+
+// Sky models, Sky in sky.rs
+const SKY_GRADIENT = 0u;
+const SKY_ENVIRONMENT = 1u;
+const SKY_PHYSICAL = 2u;
+
+// Kinds of primitive, Geometry in geometry.rs
+const PRIMITIVE_SPHERE = 0u;
+const PRIMITIVE_PLANE = 1u;
+const PRIMITIVE_DISC = 2u;
+const PRIMITIVE_BOX = 3u;
+const PRIMITIVE_QUAD = 4u;
+const PRIMITIVE_TRIANGLE = 5u;
+// BVH leaf entry referring to an instance
+const PRIMITIVE_INSTANCE = 6u;
+
+// Most levels of a BVH and the size of its traversal stack, Bvh::MAX_DEPTH in
+// bvh.rs
+const BVH_MAX_DEPTH = 32;
+
+// RayHit light when a primitive rather than a light was hit
+const NO_LIGHT = 0xffffffffu;
+
+// RayHit instance when a primitive placed directly in the world was hit
+const NO_INSTANCE = 0xffffffffu;
 
-const SAMPLE_COUNT = 4;
-const SAMPLES = array<vec2<f32>, SAMPLE_COUNT>(
-    vec2<f32>(-0.25, -0.25),
-    vec2<f32>(-0.25, 0.25),
-    vec2<f32>(0.25, -0.25),
-    vec2<f32>(0.25, 0.25),
-);
+// Instance material keeping those of the model, Instance::NO_MATERIAL
+const NO_MATERIAL = 0xffffffffu;
+
+// Material with a plain albedo, Material::NO_TEXTURE
+const NO_TEXTURE = 0xffffffffu;
+
+// Sub-pixel jitter, Jitter in camera.rs
+const JITTER_STRATIFIED = 0u;
+const JITTER_RANDOM = 1u;
+
+// Source of samples, Sampler in camera.rs
+const SAMPLER_RANDOM = 0u;
+const SAMPLER_SOBOL = 1u;
 
 struct Camera {
     dimensions: vec2<f32>,
     focal: f32,
-    viewport_height: f32,
+    vfov: f32,
     pos: vec3<f32>,
     max_depth: i32,
+    // `target` in Camera, which is a reserved word in WGSL
+    look_at: vec3<f32>,
+    samples: u32,
+    up: vec3<f32>,
+    frame: u32,
+    u: vec3<f32>,
+    aperture: f32,
+    v: vec3<f32>,
+    focus_dist: f32,
+    w: vec3<f32>,
+    jitter: u32,
+    // `sampler` in Camera, which is a reserved word in WGSL
+    sampling: u32,
 }
 
 struct Ray {
@@ -31,21 +141,244 @@ struct RayHit {
     hit: bool,
     distance: f32,
     pos: vec3<f32>,
+    // Faces against the ray, front_face is set if that is the outside
     normal: vec3<f32>,
+    front_face: bool,
+    material: u32,
+    // Kind of primitive hit, only meaningful when light is NO_LIGHT
+    primitive: u32,
+    // Index of the primitive hit within its kind
+    index: u32,
+    // Index of the spherical light hit, or NO_LIGHT
+    light: u32,
+    // Index of the instance the primitive was hit through, or NO_INSTANCE
+    instance: u32,
+    // Texture coordinates, interpolated across triangles and zero elsewhere
+    uv: vec2<f32>,
+    // Vertex colour, interpolated across triangles and white elsewhere
     colour: vec3<f32>,
-    reflection: f32,
 }
 
+struct Scatter {
+    scattered: bool,
+    ray: Ray,
+    attenuation: vec3<f32>,
+}
+
+// Each kind of primitive is counted, as empty buffers hold one zeroed item
 struct Spheres {
+    count: u32,
+    // Those placed directly in the world come first, only they are lights
+    world_count: u32,
     @align(16)
-    spheres: array<Sphere>,
+    items: array<Sphere>,
 };
 
 struct Sphere {
     pos: vec3<f32>,
     radius: f32,
+    material: u32,
+}
+
+struct Planes {
+    count: u32,
+    @align(16)
+    items: array<Plane>,
+};
+
+struct Plane {
+    pos: vec3<f32>,
+    material: u32,
+    normal: vec3<f32>,
+}
+
+struct Discs {
+    count: u32,
+    @align(16)
+    items: array<Disc>,
+};
+
+struct Disc {
+    pos: vec3<f32>,
+    radius: f32,
+    normal: vec3<f32>,
+    material: u32,
+}
+
+struct Boxes {
+    count: u32,
+    @align(16)
+    items: array<Cuboid>,
+};
+
+struct Cuboid {
+    pos: vec3<f32>,
+    material: u32,
+    half_size: vec3<f32>,
+    // Columns are the box's axes in world space
+    axes: mat3x3<f32>,
+}
+
+struct Quads {
+    count: u32,
+    @align(16)
+    items: array<Quad>,
+};
+
+struct Quad {
+    pos: vec3<f32>,
+    material: u32,
+    u: vec3<f32>,
+    v: vec3<f32>,
+}
+
+struct Vertices {
+    count: u32,
+    @align(16)
+    items: array<MeshVertex>,
+};
+
+struct MeshVertex {
+    pos: vec3<f32>,
+    // Texture coordinates
+    u: f32,
+    // Zero for flat shading
+    normal: vec3<f32>,
+    v: f32,
+    // Linear colour multiplying the albedo
     colour: vec3<f32>,
-    reflection: f32,
+}
+
+struct Triangles {
+    count: u32,
+    @align(16)
+    items: array<Triangle>,
+};
+
+struct Triangle {
+    vertices: vec3<u32>,
+    material: u32,
+}
+
+struct BvhNodes {
+    count: u32,
+    @align(16)
+    items: array<BvhNode>,
+};
+
+struct BvhNode {
+    min: vec3<f32>,
+    // First primitive of a leaf, or second child of an interior node
+    start: u32,
+    max: vec3<f32>,
+    // Zero for interior nodes
+    count: u32,
+}
+
+// Padded by hand, the items are only 4 byte aligned and naga places them
+// straight after count whatever @align says
+struct BvhPrimitives {
+    count: u32,
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
+    items: array<BvhPrimitive>,
+};
+
+struct BvhPrimitive {
+    kind: u32,
+    index: u32,
+}
+
+struct Instances {
+    count: u32,
+    @align(16)
+    items: array<Instance>,
+}
+
+struct Instance {
+    // Model to world
+    transform: mat4x4<f32>,
+    // World to model
+    inverse: mat4x4<f32>,
+    model: u32,
+    // Overrides the model's materials unless NO_MATERIAL
+    material: u32,
+    // First node of the model's BVH
+    root: u32,
+}
+
+struct Materials {
+    materials: array<Material>,
+}
+
+struct Material {
+    albedo: vec3<f32>,
+    kind: u32,
+    emission: vec3<f32>,
+    fuzz: f32,
+    ior: f32,
+    // Multiplies albedo unless NO_TEXTURE
+    texture: u32,
+}
+
+struct Lights {
+    // Colour of the gradient sky
+    sky: vec3<f32>,
+    count: u32,
+    sky_model: u32,
+    // Multiplies the environment map
+    intensity: f32,
+    // Of the environment map around the y axis in radians
+    rotation: f32,
+    physical: PhysicalSky,
+    lights: array<Light>,
+}
+
+// Preetham et al. 1999 clear sky, see PhysicalSky in sky.rs
+struct PhysicalSky {
+    // Perez coefficients A to E for Y, x and y
+    coefficients: array<vec4<f32>, 5>,
+    // Y, x and y at the zenith divided by the Perez function there
+    zenith: vec3<f32>,
+    turbidity: f32,
+    sun_direction: vec3<f32>,
+    intensity: f32,
+}
+
+struct SkyDistribution {
+    width: u32,
+    height: u32,
+    // Zero for a black sky, which is never sampled
+    total: f32,
+    // Marginal CDF over rows, then a conditional CDF over the columns of each row
+    cdf: array<f32>,
+}
+
+// Textures materials refer to, see Texture::buffer_bytes in texture.rs
+struct Textures {
+    count: u32,
+    // Explicit as naga ignores @align on arrays of u32
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
+    // Offset, width and height of each texture padded to four, then the
+    // texels of every texture, sRGB packed as unpack4x8unorm expects
+    data: array<u32>,
+}
+
+struct SkySample {
+    dir: vec3<f32>,
+    // Zero when no direction could be sampled
+    pdf: f32,
+}
+
+struct Light {
+    pos: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    radius: f32,
+    emission: vec3<f32>,
 }
 
 // Vertex shader
@@ -66,39 +399,127 @@ fn vs_main(
     return out;
 }
 
-var<private> seed: f32 = 0.0;
+// Source of every sample taken while tracing a camera sample, set by init_sample_state
+struct SampleState {
+    // Random number generator state
+    rng: u32,
+    // Index into the low discrepancy sequence
+    index: u32,
+    // Scrambles the sequence differently for each pixel
+    seed: u32,
+    // Pair of dimensions of the sequence the next sample uses
+    dimension: u32,
+    sampling: u32,
+}
+
+var<private> sample_state: SampleState;
+
+// PCG hash, see "Hash Functions for GPU Rendering", Jarzynski and Olano 2020
+fn pcg(v: u32) -> u32 {
+    var state = v * 747796405u + 2891336453u;
+    var word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
 
-fn base_hash(p: vec2<u32>) -> u32 {
-    var p_shifted = vec2<u32>(p.x >> u32(1), p.y >> u32(1));
-    var q = u32(1103515245) * ((p_shifted) ^ (p.yx));
-    var h32 = u32(1103515245) * ((q.x) ^ (q.y >> u32(3)));
-    return h32 ^ (h32 >> u32(16));
+// Seed state so every pixel, frame and sample has an independent sequence
+fn init_rng(pixel: vec2<u32>, frame: u32, sample: u32) -> u32 {
+    return pcg(pixel.x + pcg(pixel.y + pcg(frame + pcg(sample))));
 }
 
-fn hash3(seed: ptr<private, f32>) -> vec3<f32> {
-    var l = *seed;
-    *seed += 0.1;
-    var r = *seed;
-    *seed += 0.1;
+// Next 32 random bits, advancing state by the same LCG step pcg permutes
+fn rand_u32(state: ptr<private, u32>) -> u32 {
+    var old = *state;
+    *state = old * 747796405u + 2891336453u;
+    return pcg(old);
+}
 
-    var n = base_hash(vec2<u32>(vec2<f32>(l, r)));
-    var rz = vec3<u32>(
-        n & u32(0x7fffffff),
-        (n * u32(16807)) & u32(0x7fffffff),
-        (n * u32(48271)) & u32(0x7fffffff)
-    );
-    return vec3<f32>(rz) / f32(0x7fffffff);
+// Uniform in [0, 1), 24 bits so every value is exact in f32
+fn rand(state: ptr<private, u32>) -> f32 {
+    return f32(rand_u32(state) >> 8u) / 16777216.0;
 }
 
-fn random_in_unit_sphere(seed: ptr<private, f32>) -> vec3<f32> {
-    var h = hash3(seed) * vec3<f32>(2., 6.28318530718, 1.) - vec3<f32>(1.0, 0.0, 0.0);
+fn rand2(state: ptr<private, u32>) -> vec2<f32> {
+    var x = rand(state);
+    return vec2<f32>(x, rand(state));
+}
+
+// Sample i of a pixel continues the pixel's sequence from previous frames
+fn init_sample_state(pixel: vec2<u32>, sample: u32) -> SampleState {
+    var out: SampleState;
+    out.rng = init_rng(pixel, camera.frame, sample);
+    out.index = camera.frame * camera.samples + sample;
+    out.seed = init_rng(pixel, 0u, 0u);
+    out.dimension = 0u;
+    out.sampling = camera.sampling;
+    return out;
+}
+
+// Owen scrambling by hashing, see "Practical Hash-based Owen Scrambling", Burley 2020
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    var v = reverseBits(x);
+    v ^= v * 0x3d20adeau;
+    v += seed;
+    v *= (seed >> 16u) | 1u;
+    v ^= v * 0x05526c56u;
+    v ^= v * 0x53a22864u;
+    return reverseBits(v);
+}
+
+// Second dimension of the Sobol sequence, the first is reverseBits(index)
+fn sobol_second(index: u32) -> u32 {
+    var result = 0u;
+    var v = 1u << 31u;
+    for (var i = index; i != 0u; i >>= 1u) {
+        if (i & 1u) != 0u {
+            result ^= v;
+        }
+        v ^= v >> 1u;
+    }
+    return result;
+}
+
+// Point of the Sobol sequence, with the index shuffled and each dimension scrambled
+fn sobol_2d(index: u32, seed: u32) -> vec2<f32> {
+    var i = nested_uniform_scramble(index, seed);
+    var x = nested_uniform_scramble(reverseBits(i), pcg(seed));
+    var y = nested_uniform_scramble(sobol_second(i), pcg(seed + 1u));
+    return vec2<f32>(vec2<u32>(x, y) >> vec2<u32>(8u)) / 16777216.0;
+}
+
+// Next pair of dimensions of the sample, each in [0, 1)
+fn sample_2d(state: ptr<private, SampleState>) -> vec2<f32> {
+    if (*state).sampling == SAMPLER_SOBOL {
+        var seed = pcg((*state).seed + (*state).dimension);
+        (*state).dimension += 1u;
+        return sobol_2d((*state).index, seed);
+    }
+    return rand2(&(*state).rng);
+}
+
+fn sample_1d(state: ptr<private, SampleState>) -> f32 {
+    return sample_2d(state).x;
+}
+
+fn random_unit_vector(state: ptr<private, SampleState>) -> vec3<f32> {
+    var h = sample_2d(state) * vec2<f32>(2.0, 6.28318530718) - vec2<f32>(1.0, 0.0);
     var phi = h.y;
-    var r = pow(h.z, 1. / 3.);
-    return r * vec3(sqrt(1. - h.x * h.x) * vec2(sin(phi), cos(phi)), h.x);
+    return vec3(sqrt(1. - h.x * h.x) * vec2(sin(phi), cos(phi)), h.x);
+}
+
+fn random_in_unit_sphere(state: ptr<private, SampleState>) -> vec3<f32> {
+    var direction = random_unit_vector(state);
+    return pow(sample_1d(state), 1. / 3.) * direction;
+}
+
+fn random_in_unit_disk(state: ptr<private, SampleState>) -> vec2<f32> {
+    var h = sample_2d(state);
+    var r = sqrt(h.x);
+    var theta = 6.28318530718 * h.y;
+    return r * vec2<f32>(cos(theta), sin(theta));
 }
 
 fn near_zero(v: vec3<f32>) -> bool {
-    return v.x < EPSILON && v.y < EPSILON && v.z < EPSILON;
+    return all(abs(v) < vec3<f32>(EPSILON));
 }
 
 fn hit_sphere(sphere: Sphere, ray: Ray) -> RayHit {
@@ -110,29 +531,24 @@ fn hit_sphere(sphere: Sphere, ray: Ray) -> RayHit {
 
     if d > 0.0 {
         var xy = sqrt(d);
-        var root1 = -x - xy;
-        if root1 >= 0.0 {
-            var ray_hit: RayHit;
-            ray_hit.hit = true;
-            ray_hit.distance = root1;
-            ray_hit.pos = ray.pos + root1 * ray.dir;
-            ray_hit.normal = normalize(ray_hit.pos - sphere.pos);
-
-            ray_hit.colour = sphere.colour;
-            ray_hit.reflection = sphere.reflection;
-
-            return ray_hit;
+        // Nearest root in front of the ray, ignoring the surface it starts on
+        var root = -x - xy;
+        if root < EPSILON {
+            root = -x + xy;
         }
-        var root2 = -x + xy;
-        if root2 >= 0.0 {
+        if root >= EPSILON {
             var ray_hit: RayHit;
             ray_hit.hit = true;
-            ray_hit.distance = root2;
-            ray_hit.pos = ray.pos + root2 * ray.dir;
-            ray_hit.normal = normalize(ray_hit.pos - sphere.pos);
+            ray_hit.distance = root;
+            ray_hit.pos = ray.pos + root * ray.dir;
 
-            ray_hit.colour = sphere.colour;
-            ray_hit.reflection = sphere.reflection;
+            var outward = (ray_hit.pos - sphere.pos) / sphere.radius;
+            ray_hit.front_face = dot(ray.dir, outward) < 0.0;
+            ray_hit.normal = select(-outward, outward, ray_hit.front_face);
+            ray_hit.material = sphere.material;
+            ray_hit.light = NO_LIGHT;
+            ray_hit.instance = NO_INSTANCE;
+            ray_hit.colour = vec3<f32>(1.0);
 
             return ray_hit;
         }
@@ -141,75 +557,861 @@ fn hit_sphere(sphere: Sphere, ray: Ray) -> RayHit {
     return ray_hit;
 }
 
+// Hit at distance along the ray on a surface with the given outward normal,
+// or a miss if that is behind the ray or too close to its start
+fn surface_hit(ray: Ray, distance: f32, outward: vec3<f32>, material: u32) -> RayHit {
+    var ray_hit: RayHit;
+    if distance < EPSILON {
+        return ray_hit;
+    }
+    ray_hit.hit = true;
+    ray_hit.distance = distance;
+    ray_hit.pos = ray.pos + distance * ray.dir;
+    ray_hit.front_face = dot(ray.dir, outward) < 0.0;
+    ray_hit.normal = select(-outward, outward, ray_hit.front_face);
+    ray_hit.material = material;
+    ray_hit.light = NO_LIGHT;
+    ray_hit.instance = NO_INSTANCE;
+    ray_hit.colour = vec3<f32>(1.0);
+    return ray_hit;
+}
+
+// Distance along the ray to the plane through pos with the given normal,
+// negative if the ray is parallel to it
+fn plane_distance(pos: vec3<f32>, normal: vec3<f32>, ray: Ray) -> f32 {
+    var denominator = dot(ray.dir, normal);
+    if abs(denominator) < 1e-8 {
+        return -1.0;
+    }
+    return dot(pos - ray.pos, normal) / denominator;
+}
+
+fn hit_plane(plane: Plane, ray: Ray) -> RayHit {
+    var distance = plane_distance(plane.pos, plane.normal, ray);
+    var ray_hit = surface_hit(ray, distance, plane.normal, plane.material);
+    ray_hit.primitive = PRIMITIVE_PLANE;
+    return ray_hit;
+}
+
+fn hit_disc(disc: Disc, ray: Ray) -> RayHit {
+    var distance = plane_distance(disc.pos, disc.normal, ray);
+    var ray_hit = surface_hit(ray, distance, disc.normal, disc.material);
+    var offset = ray_hit.pos - disc.pos;
+    if dot(offset, offset) > disc.radius * disc.radius {
+        var miss: RayHit;
+        return miss;
+    }
+    ray_hit.primitive = PRIMITIVE_DISC;
+    return ray_hit;
+}
+
+// Slab test in the box's own frame, from inside the box this finds the way out
+fn hit_box(cuboid: Cuboid, ray: Ray) -> RayHit {
+    var pos = (ray.pos - cuboid.pos) * cuboid.axes;
+    var dir = ray.dir * cuboid.axes;
+
+    // Directions parallel to a slab give huge distances of the right sign
+    // rather than dividing by zero
+    var safe = select(dir, vec3<f32>(1e-20), abs(dir) < vec3<f32>(1e-20));
+    var inverse = 1.0 / safe;
+    var t0 = (-cuboid.half_size - pos) * inverse;
+    var t1 = (cuboid.half_size - pos) * inverse;
+    var near_t = min(t0, t1);
+    var far_t = max(t0, t1);
+    var near = max(max(near_t.x, near_t.y), near_t.z);
+    var far = min(min(far_t.x, far_t.y), far_t.z);
+    if near > far {
+        var miss: RayHit;
+        return miss;
+    }
+    var distance = select(far, near, near >= EPSILON);
+
+    // The face hit is the one the hit point is relatively furthest out on
+    var local = (pos + distance * dir) / cuboid.half_size;
+    var a = abs(local);
+    var axis = 2;
+    if a.x >= a.y && a.x >= a.z {
+        axis = 0;
+    } else if a.y >= a.z {
+        axis = 1;
+    }
+    var axes = cuboid.axes;
+    var outward = axes[axis] * sign(local[axis]);
+    var ray_hit = surface_hit(ray, distance, outward, cuboid.material);
+    ray_hit.primitive = PRIMITIVE_BOX;
+    return ray_hit;
+}
+
+fn hit_quad(quad: Quad, ray: Ray) -> RayHit {
+    var n = cross(quad.u, quad.v);
+    var distance = plane_distance(quad.pos, n, ray);
+    var ray_hit = surface_hit(ray, distance, normalize(n), quad.material);
+
+    // Coordinates of the hit along each edge, inside for 0 to 1 on both
+    var w = n / dot(n, n);
+    var offset = ray_hit.pos - quad.pos;
+    var alpha = dot(w, cross(offset, quad.v));
+    var beta = dot(w, cross(quad.u, offset));
+    if alpha < 0.0 || alpha > 1.0 || beta < 0.0 || beta > 1.0 {
+        var miss: RayHit;
+        return miss;
+    }
+    ray_hit.primitive = PRIMITIVE_QUAD;
+    return ray_hit;
+}
+
+// Equirectangular coordinates of a direction in the environment map, with -z
+// in the middle and +y at the top
+fn environment_uv(dir: vec3<f32>) -> vec2<f32> {
+    var c = cos(lights.rotation);
+    var s = sin(lights.rotation);
+    var d = vec3<f32>(c * dir.x - s * dir.z, dir.y, s * dir.x + c * dir.z);
+    return vec2<f32>(0.5 + atan2(d.x, -d.z) / (2.0 * PI), acos(clamp(d.y, -1.0, 1.0)) / PI);
+}
+
+// Direction with the given equirectangular coordinates, inverse of environment_uv
+fn environment_direction(uv: vec2<f32>) -> vec3<f32> {
+    var theta = uv.y * PI;
+    var phi = (uv.x - 0.5) * 2.0 * PI;
+    var d = vec3<f32>(sin(theta) * sin(phi), cos(theta), -sin(theta) * cos(phi));
+    var c = cos(lights.rotation);
+    var s = sin(lights.rotation);
+    return vec3<f32>(c * d.x + s * d.z, d.y, -s * d.x + c * d.z);
+}
+
+// Interval of the count + 1 values of the CDF starting at offset which contains u
+fn find_interval(offset: u32, count: u32, u: f32) -> u32 {
+    var low = 0u;
+    var high = count;
+    while high - low > 1u {
+        var middle = (low + high) / 2u;
+        if sky_distribution.cdf[offset + middle] <= u {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    return low;
+}
+
+// Sample a direction with density proportional to the tabulated sky radiance
+fn sample_sky(u: vec2<f32>) -> SkySample {
+    var out: SkySample;
+    if sky_distribution.total == 0.0 {
+        return out;
+    }
+
+    var width = sky_distribution.width;
+    var height = sky_distribution.height;
+    var row = find_interval(0u, height, u.y);
+    var row_start = sky_distribution.cdf[row];
+    var row_size = sky_distribution.cdf[row + 1u] - row_start;
+    var v = (f32(row) + (u.y - row_start) / row_size) / f32(height);
+
+    var offset = height + 1u + row * (width + 1u);
+    var column = find_interval(offset, width, u.x);
+    var column_start = sky_distribution.cdf[offset + column];
+    var column_size = sky_distribution.cdf[offset + column + 1u] - column_start;
+    var uv = vec2<f32>((f32(column) + (u.x - column_start) / column_size) / f32(width), v);
+
+    // Density over uv, divided by the area the cell covers on the sphere
+    var sin_theta = sin(v * PI);
+    if sin_theta > 0.0 {
+        out.dir = environment_direction(uv);
+        out.pdf = row_size * f32(height) * column_size * f32(width) / (2.0 * PI * PI * sin_theta);
+    }
+    return out;
+}
+
+// Solid angle density of sample_sky producing a direction
+fn sky_pdf(dir: vec3<f32>) -> f32 {
+    var sin_theta = sqrt(max(0.0, 1.0 - dir.y * dir.y));
+    if sky_distribution.total == 0.0 || sin_theta == 0.0 {
+        return 0.0;
+    }
+
+    var width = sky_distribution.width;
+    var height = sky_distribution.height;
+    var uv = environment_uv(dir);
+    var row = min(u32(uv.y * f32(height)), height - 1u);
+    var column = min(u32(uv.x * f32(width)), width - 1u);
+    var offset = height + 1u + row * (width + 1u);
+    var row_size = sky_distribution.cdf[row + 1u] - sky_distribution.cdf[row];
+    var column_size = sky_distribution.cdf[offset + column + 1u] - sky_distribution.cdf[offset + column];
+    return row_size * f32(height) * column_size * f32(width) / (2.0 * PI * PI * sin_theta);
+}
+
+// Perez function of Y, x and y for the cosine of the angle from the zenith
+// and the angle from the sun
+fn perez(cos_theta: f32, gamma: f32) -> vec3<f32> {
+    var c = lights.physical.coefficients;
+    var cos_gamma = cos(gamma);
+    return (1.0 + c[0].xyz * exp(c[1].xyz / cos_theta)) * (1.0 + c[2].xyz * exp(c[3].xyz * gamma) + c[4].xyz * cos_gamma * cos_gamma);
+}
+
+// Linear sRGB radiance of the physical sky, which continues the horizon below
+// it so curved ground doesn't leave a black band
+fn physical_sky_colour(dir: vec3<f32>) -> vec3<f32> {
+    var gamma = acos(clamp(dot(dir, lights.physical.sun_direction), -1.0, 1.0));
+    // HORIZON in PhysicalSky
+    var cos_theta = max(dir.y, 0.001);
+    var yxy = lights.physical.zenith * perez(cos_theta, gamma);
+    var xyz = vec3<f32>(yxy.y / yxy.z * yxy.x, yxy.x, (1.0 - yxy.y - yxy.z) / yxy.z * yxy.x);
+    var rgb = vec3<f32>(
+        dot(vec3<f32>(3.2406, -1.5372, -0.4986), xyz),
+        dot(vec3<f32>(-0.9689, 1.8758, 0.0415), xyz),
+        dot(vec3<f32>(0.0557, -0.2040, 1.0570), xyz),
+    );
+    return lights.physical.intensity * max(rgb, vec3<f32>(0.0));
+}
+
 fn sky_colour(ray: Ray) -> vec3<f32> {
+    if lights.sky_model == SKY_ENVIRONMENT {
+        var uv = environment_uv(normalize(ray.dir));
+        return lights.intensity * textureSampleLevel(environment, environment_sampler, uv, 0.0).rgb;
+    }
+    if lights.sky_model == SKY_PHYSICAL {
+        return physical_sky_colour(normalize(ray.dir));
+    }
+
     var a = 0.5 * (normalize(ray.dir).y + 1.0);
-    return (1.0 - a) * vec3<f32>(1.0, 1.0, 1.0) + a * vec3<f32>(0.5, 0.7, 1.0);
+    return lights.sky * ((1.0 - a) * vec3<f32>(1.0, 1.0, 1.0) + a * vec3<f32>(0.5, 0.7, 1.0));
+}
+
+// Whether nothing is hit along the ray before max_distance
+fn visible(ray: Ray, max_distance: f32) -> bool {
+    var hit = cast_ray(ray);
+    return !hit.hit || hit.distance >= max_distance;
+}
+
+// Irradiance at the hit from point and directional lights, which paths can
+// never hit so have to be sampled explicitly
+fn delta_light_irradiance(hit: RayHit) -> vec3<f32> {
+    var irradiance = vec3<f32>(0.0);
+    for (var i = 0u; i < lights.count; i += 1u) {
+        var light = lights.lights[i];
+
+        var to_light: vec3<f32>;
+        var light_distance: f32;
+        var incident: vec3<f32>;
+        switch light.kind {
+            case 0u /* POINT */ {
+                to_light = light.pos - hit.pos;
+                light_distance = length(to_light);
+                to_light /= light_distance;
+                incident = light.emission / (light_distance * light_distance);
+            }
+            case 1u /* DIRECTIONAL */ {
+                to_light = -light.direction;
+                light_distance = 3.40282347e38;
+                incident = light.emission;
+            }
+            default {
+                continue;
+            }
+        }
+
+        var cos_theta = dot(hit.normal, to_light);
+        if cos_theta <= 0.0 {
+            continue;
+        }
+
+        var shadow_ray: Ray;
+        shadow_ray.pos = hit.pos + hit.normal * EPSILON;
+        shadow_ray.dir = to_light;
+        if visible(shadow_ray, light_distance) {
+            irradiance += cos_theta * incident;
+        }
+    }
+    return irradiance;
+}
+
+// Orthonormal basis with n as the last column, see "Building an Orthonormal
+// Basis, Revisited", Duff et al. 2017
+fn orthonormal_basis(n: vec3<f32>) -> mat3x3<f32> {
+    var sign = select(-1.0, 1.0, n.z >= 0.0);
+    var a = -1.0 / (sign + n.z);
+    var b = n.x * n.y * a;
+    return mat3x3<f32>(
+        vec3<f32>(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        vec3<f32>(b, sign + n.y * n.y * a, -n.y),
+        n,
+    );
+}
+
+// 1 - cos of the half angle of the cone a sphere subtends from a point, written
+// to stay accurate for small spheres, or zero from inside the sphere
+fn sphere_cone(centre: vec3<f32>, radius: f32, origin: vec3<f32>) -> f32 {
+    var to_centre = centre - origin;
+    var sin2_max = radius * radius / dot(to_centre, to_centre);
+    if sin2_max >= 1.0 {
+        return 0.0;
+    }
+    return sin2_max / (1.0 + sqrt(1.0 - sin2_max));
+}
+
+// Solid angle density of sampling a direction towards a sphere from a point,
+// zero from inside it where it isn't sampled
+fn sphere_pdf(centre: vec3<f32>, radius: f32, origin: vec3<f32>) -> f32 {
+    var cone = sphere_cone(centre, radius, origin);
+    if cone == 0.0 {
+        return 0.0;
+    }
+    return 1.0 / (2.0 * PI * cone);
+}
+
+// Uniformly sample a direction within a cone around axis, given 1 - cos of
+// its half angle
+fn sample_cone(axis: vec3<f32>, cone: f32, u: vec2<f32>) -> vec3<f32> {
+    // sin^2 = (1 - cos)(1 + cos), which stays accurate for narrow cones
+    var one_minus_cos = u.x * cone;
+    var cos_theta = 1.0 - one_minus_cos;
+    var sin_theta = sqrt(max(0.0, one_minus_cos * (2.0 - one_minus_cos)));
+    var phi = 2.0 * PI * u.y;
+    var local = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+    return orthonormal_basis(axis) * local;
+}
+
+// Uniformly sample a direction within the cone a sphere subtends from a point
+fn sample_sphere(centre: vec3<f32>, radius: f32, origin: vec3<f32>, u: vec2<f32>) -> vec3<f32> {
+    return sample_cone(normalize(centre - origin), sphere_cone(centre, radius, origin), u);
+}
+
+// 1 - cos of the angular radius of the sun
+fn sun_cone(light: Light) -> f32 {
+    var s = sin(0.5 * light.radius);
+    return 2.0 * s * s;
+}
+
+// Multiple importance sampling weight of a sample taken with density a, when
+// it could also have been taken with density b
+fn power_heuristic(a: f32, b: f32) -> f32 {
+    return a * a / (a * a + b * b);
+}
+
+// Weight of emission found by a diffuse bounce with the given density, which
+// sampling the emitter directly from the bounce could also have found
+fn bsdf_weight(bsdf_pdf: f32, centre: vec3<f32>, radius: f32, origin: vec3<f32>) -> f32 {
+    if bsdf_pdf == 0.0 {
+        return 1.0;
+    }
+    return power_heuristic(bsdf_pdf, sphere_pdf(centre, radius, origin));
+}
+
+// Irradiance at a diffuse hit from directly sampling one spherical emitter,
+// either sphere or light, with a shadow ray checking that emitter is hit
+fn sample_emitter(hit: RayHit, centre: vec3<f32>, radius: f32, emission: vec3<f32>, sphere: u32, light: u32) -> vec3<f32> {
+    // Always drawn so later dimensions of the sample don't depend on the hit
+    var u = sample_2d(&sample_state);
+    var light_pdf = sphere_pdf(centre, radius, hit.pos);
+    if light_pdf == 0.0 {
+        return vec3<f32>(0.0);
+    }
+
+    var dir = sample_sphere(centre, radius, hit.pos, u);
+    var cos_theta = dot(hit.normal, dir);
+    if cos_theta <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+
+    var shadow = cast_ray(Ray(hit.pos + hit.normal * EPSILON, dir));
+    var other_sphere = shadow.primitive != PRIMITIVE_SPHERE || shadow.index != sphere || shadow.instance != NO_INSTANCE;
+    if !shadow.hit || shadow.light != light || (light == NO_LIGHT && other_sphere) {
+        return vec3<f32>(0.0);
+    }
+
+    var bsdf_pdf = cos_theta / PI;
+    return emission * cos_theta / light_pdf * power_heuristic(light_pdf, bsdf_pdf);
+}
+
+// Irradiance at a diffuse hit from directly sampling the disk of the sun,
+// which is visible when the shadow ray escapes
+fn sample_sun(hit: RayHit, light: Light) -> vec3<f32> {
+    // Always drawn so later dimensions of the sample don't depend on the hit
+    var u = sample_2d(&sample_state);
+    var cone = sun_cone(light);
+    var dir = sample_cone(-light.direction, cone, u);
+    var cos_theta = dot(hit.normal, dir);
+    if cos_theta <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+
+    if cast_ray(Ray(hit.pos + hit.normal * EPSILON, dir)).hit {
+        return vec3<f32>(0.0);
+    }
+
+    var light_pdf = 1.0 / (2.0 * PI * cone);
+    var bsdf_pdf = cos_theta / PI;
+    return light.emission * cos_theta / light_pdf * power_heuristic(light_pdf, bsdf_pdf);
+}
+
+// Radiance of any sun seen along an escaping ray, weighted against sampling
+// the sun directly when the ray comes from a diffuse bounce
+fn sun_radiance(dir: vec3<f32>, bsdf_pdf: f32) -> vec3<f32> {
+    var radiance = vec3<f32>(0.0);
+    for (var i = 0u; i < lights.count; i += 1u) {
+        var light = lights.lights[i];
+        if light.kind != SUN {
+            continue;
+        }
+
+        var cone = sun_cone(light);
+        if dot(normalize(dir), -light.direction) < 1.0 - cone {
+            continue;
+        }
+        var weight = 1.0;
+        if bsdf_pdf > 0.0 {
+            weight = power_heuristic(bsdf_pdf, 1.0 / (2.0 * PI * cone));
+        }
+        radiance += weight * light.emission;
+    }
+    return radiance;
+}
+
+// Irradiance at a diffuse hit from sampling every emissive sphere, spherical
+// light and sun, small bright emitters are rarely found by bouncing
+fn emitter_irradiance(hit: RayHit) -> vec3<f32> {
+    var irradiance = vec3<f32>(0.0);
+    // Spheres of models are only lit by bouncing
+    for (var i = 0u; i < spheres.world_count; i += 1u) {
+        var sphere = spheres.items[i];
+        var material = materials.materials[sphere.material];
+        if material.kind == EMISSIVE {
+            irradiance += sample_emitter(hit, sphere.pos, abs(sphere.radius), material.emission, i, NO_LIGHT);
+        }
+    }
+
+    for (var i = 0u; i < lights.count; i += 1u) {
+        var light = lights.lights[i];
+        if light.kind == SPHERICAL {
+            irradiance += sample_emitter(hit, light.pos, light.radius, light.emission, 0u, i);
+        } else if light.kind == SUN {
+            irradiance += sample_sun(hit, light);
+        }
+    }
+    return irradiance;
+}
+
+// Irradiance at a diffuse hit from importance sampling the sky, weighted
+// against the chance of a diffuse bounce escaping the same way
+fn sky_irradiance(hit: RayHit) -> vec3<f32> {
+    // Always drawn so later dimensions of the sample don't depend on the hit
+    var sky_sample = sample_sky(sample_2d(&sample_state));
+    if sky_sample.pdf == 0.0 {
+        return vec3<f32>(0.0);
+    }
+
+    var cos_theta = dot(hit.normal, sky_sample.dir);
+    if cos_theta <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+
+    var shadow_ray = Ray(hit.pos + hit.normal * EPSILON, sky_sample.dir);
+    if cast_ray(shadow_ray).hit {
+        return vec3<f32>(0.0);
+    }
+
+    var bsdf_pdf = cos_theta / PI;
+    var weight = power_heuristic(sky_sample.pdf, bsdf_pdf);
+    return sky_colour(shadow_ray) * cos_theta / sky_sample.pdf * weight;
+}
+
+// Schlick's approximation of the reflectance of a dielectric
+fn reflectance(cosine: f32, ratio: f32) -> f32 {
+    var r0 = (1.0 - ratio) / (1.0 + ratio);
+    r0 = r0 * r0;
+    return r0 + (1.0 - r0) * pow(1.0 - cosine, 5.0);
+}
+
+// Linear value of an sRGB encoded one
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    return select(pow((c + 0.055) / 1.055, vec3<f32>(2.4)), c / 12.92, c <= vec3<f32>(0.04045));
+}
+
+// Nearest texel of a texture, the coordinates wrap around so textures repeat
+fn texture_colour(index: u32, uv: vec2<f32>) -> vec3<f32> {
+    var offset = textures.data[4u * index];
+    var width = textures.data[4u * index + 1u];
+    var height = textures.data[4u * index + 2u];
+    var wrapped = uv - floor(uv);
+    var x = min(u32(wrapped.x * f32(width)), width - 1u);
+    var y = min(u32(wrapped.y * f32(height)), height - 1u);
+    var texel = unpack4x8unorm(textures.data[offset + y * width + x]);
+    return srgb_to_linear(texel.rgb);
+}
+
+// Material of the hit, with its albedo multiplied by its vertex colour and by
+// its texture if it has one
+fn hit_material(hit: RayHit) -> Material {
+    var material = materials.materials[hit.material];
+    material.albedo *= hit.colour;
+    if material.texture != NO_TEXTURE {
+        material.albedo *= texture_colour(material.texture, hit.uv);
+    }
+    return material;
+}
+
+fn scatter(ray: Ray, hit: RayHit) -> Scatter {
+    var material = hit_material(hit);
+
+    var out: Scatter;
+    out.scattered = true;
+    out.ray.pos = hit.pos;
+    out.attenuation = material.albedo;
+
+    switch material.kind {
+        case 1u /* METAL */ {
+            var reflected = reflect(ray.dir, hit.normal);
+            out.ray.dir = normalize(reflected) + material.fuzz * random_in_unit_sphere(&sample_state);
+            out.scattered = dot(out.ray.dir, hit.normal) > 0.0;
+        }
+        case 2u /* DIELECTRIC */ {
+            var ratio = select(material.ior, 1.0 / material.ior, hit.front_face);
+            var cos_theta = min(dot(-ray.dir, hit.normal), 1.0);
+            var sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+
+            if ratio * sin_theta > 1.0 || reflectance(cos_theta, ratio) > sample_1d(&sample_state) {
+                out.ray.dir = reflect(ray.dir, hit.normal);
+            } else {
+                out.ray.dir = refract(ray.dir, hit.normal, ratio);
+            }
+        }
+        case 3u /* EMISSIVE */ {
+            out.scattered = false;
+        }
+        default /* LAMBERTIAN */ {
+            out.ray.dir = hit.normal + random_unit_vector(&sample_state);
+
+            if near_zero(out.ray.dir) {
+                out.ray.dir = hit.normal;
+            }
+        }
+    }
+
+    out.ray.dir = normalize(out.ray.dir);
+
+    // Start just off the surface on the side the ray leaves through, so rounding
+    // can't leave it behind the surface and hit it again
+    var side = select(-hit.normal, hit.normal, dot(out.ray.dir, hit.normal) > 0.0);
+    out.ray.pos = hit.pos + side * EPSILON;
+    return out;
 }
 
+// Path trace from the given ray, emission along the path is weighted by the
+// throughput, the product of the attenuation of every bounce so far. Diffuse
+// bounces also sample emitters and the sky directly, emission found by the
+// next bounce is weighted against that
 fn iterative_ray_colour(ray: Ray) -> vec3<f32> {
-    var cumulative_colour: vec3<f32>;
-    var colour_multiplier: f32 = 1.0;
+    var radiance = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
 
     var current_ray: Ray = ray;
+    // Density of the last bounce direction if it was diffuse, zero otherwise
+    var bsdf_pdf = 0.0;
+    var bounce_pos = vec3<f32>(0.0);
 
     for (var depth = 0; depth < camera.max_depth; depth += 1) {
         var hit_out = cast_ray(current_ray);
-        if hit_out.hit {
-
-            var direction = hit_out.normal * (1.0 + EPSILON) + random_in_unit_sphere(&seed);
-
-            if near_zero(direction) {
-                direction = hit_out.normal * (1.0 + EPSILON);
+        if !hit_out.hit {
+            var weight = 1.0;
+            if bsdf_pdf > 0.0 {
+                weight = power_heuristic(bsdf_pdf, sky_pdf(current_ray.dir));
             }
-            cumulative_colour += hit_out.colour;
-            colour_multiplier *= hit_out.reflection;
+            radiance += throughput * (weight * sky_colour(current_ray) + sun_radiance(current_ray.dir, bsdf_pdf));
+            break;
+        }
+
+        if hit_out.light != NO_LIGHT {
+            var light = lights.lights[hit_out.light];
+            var weight = bsdf_weight(bsdf_pdf, light.pos, light.radius, bounce_pos);
+            radiance += throughput * weight * light.emission;
+            break;
+        }
 
-            current_ray.pos = hit_out.pos;
-            current_ray.dir = direction;
+        // Only emissive spheres placed directly in the world are also sampled
+        // directly
+        var material = hit_material(hit_out);
+        var world_sphere = hit_out.primitive == PRIMITIVE_SPHERE && hit_out.instance == NO_INSTANCE;
+        if material.kind == EMISSIVE && world_sphere {
+            var sphere = spheres.items[hit_out.index];
+            var weight = bsdf_weight(bsdf_pdf, sphere.pos, abs(sphere.radius), bounce_pos);
+            radiance += throughput * weight * material.emission;
         } else {
-            cumulative_colour += colour_multiplier * sky_colour(ray);
+            radiance += throughput * material.emission;
+        }
+
+        // Diffuse surfaces reflect albedo / pi of the irradiance from lights
+        if material.kind == LAMBERTIAN {
+            var irradiance = delta_light_irradiance(hit_out) + emitter_irradiance(hit_out) + sky_irradiance(hit_out);
+            radiance += throughput * material.albedo / PI * irradiance;
+        }
+
+        var scattered = scatter(current_ray, hit_out);
+        if !scattered.scattered {
             break;
         }
+        throughput *= scattered.attenuation;
+        current_ray = scattered.ray;
+
+        bsdf_pdf = 0.0;
+        if material.kind == LAMBERTIAN {
+            bsdf_pdf = max(dot(current_ray.dir, hit_out.normal), 0.0) / PI;
+            bounce_pos = hit_out.pos;
+        }
+    }
+    return radiance;
+}
+
+// Möller–Trumbore intersection, smooth shaded when the vertices have normals
+fn hit_triangle(triangle: Triangle, ray: Ray) -> RayHit {
+    var miss: RayHit;
+    var a = vertices.items[triangle.vertices.x];
+    var b = vertices.items[triangle.vertices.y];
+    var c = vertices.items[triangle.vertices.z];
+    var edge1 = b.pos - a.pos;
+    var edge2 = c.pos - a.pos;
+
+    var p = cross(ray.dir, edge2);
+    var determinant = dot(edge1, p);
+    if abs(determinant) < 1e-8 {
+        return miss;
+    }
+    var inverse = 1.0 / determinant;
+    var offset = ray.pos - a.pos;
+    var u = dot(offset, p) * inverse;
+    if u < 0.0 || u > 1.0 {
+        return miss;
+    }
+    var q = cross(offset, edge1);
+    var v = dot(ray.dir, q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return miss;
+    }
+
+    var distance = dot(edge2, q) * inverse;
+    var ray_hit = surface_hit(ray, distance, normalize(cross(edge1, edge2)), triangle.material);
+    if any(a.normal != vec3<f32>(0.0)) {
+        var normal = normalize((1.0 - u - v) * a.normal + u * b.normal + v * c.normal);
+        ray_hit.normal = select(-normal, normal, ray_hit.front_face);
+    }
+    ray_hit.uv = (1.0 - u - v) * vec2<f32>(a.u, a.v) + u * vec2<f32>(b.u, b.v) + v * vec2<f32>(c.u, c.v);
+    ray_hit.colour = (1.0 - u - v) * a.colour + u * b.colour + v * c.colour;
+    ray_hit.primitive = PRIMITIVE_TRIANGLE;
+    return ray_hit;
+}
+
+// Keep ray_hit as the closest if it is nearer, recording its index
+fn closest_hit(closest: ptr<function, RayHit>, ray_hit: RayHit, index: u32) {
+    if ray_hit.hit && (!(*closest).hit || (*closest).distance >= ray_hit.distance) {
+        *closest = ray_hit;
+        (*closest).index = index;
+    }
+}
+
+// Distance along the ray to where it enters the node's bounds, or -1 if it
+// misses them or only reaches them beyond max_distance
+fn bounds_distance(node: BvhNode, ray: Ray, inverse: vec3<f32>, max_distance: f32) -> f32 {
+    var t0 = (node.min - ray.pos) * inverse;
+    var t1 = (node.max - ray.pos) * inverse;
+    var low = min(t0, t1);
+    var high = max(t0, t1);
+    var near = max(max(low.x, low.y), max(low.z, 0.0));
+    var far = min(min(high.x, high.y), min(high.z, max_distance));
+    if near > far {
+        return -1.0;
+    }
+    return near;
+}
+
+fn hit_primitive(primitive: BvhPrimitive, ray: Ray) -> RayHit {
+    switch primitive.kind {
+        case PRIMITIVE_SPHERE: {
+            return hit_sphere(spheres.items[primitive.index], ray);
+        }
+        case PRIMITIVE_DISC: {
+            return hit_disc(discs.items[primitive.index], ray);
+        }
+        case PRIMITIVE_BOX: {
+            return hit_box(boxes.items[primitive.index], ray);
+        }
+        case PRIMITIVE_QUAD: {
+            return hit_quad(quads.items[primitive.index], ray);
+        }
+        default: {
+            return hit_triangle(triangles.items[primitive.index], ray);
+        }
+    }
+}
+
+// Keep a spherical light as the closest if one is nearer, they are hit like
+// spheres
+fn closest_light(ray: Ray, closest_in: RayHit) -> RayHit {
+    var closest = closest_in;
+    var hit = closest.hit;
+    for (var i = 0u; i < lights.count; i += 1u) {
+        var light = lights.lights[i];
+        if light.kind != SPHERICAL {
+            continue;
+        }
+
+        var ray_hit = hit_sphere(Sphere(light.pos, light.radius, 0u), ray);
+        if ray_hit.hit && (!hit || closest.distance >= ray_hit.distance) {
+            closest = ray_hit;
+            closest.light = i;
+            hit = true;
+        }
     }
-    return cumulative_colour;
+    return closest;
+}
+
+// Reciprocal of each component of the direction, those parallel to an axis
+// give huge values of the right sign rather than dividing by zero
+fn inverse_direction(dir: vec3<f32>) -> vec3<f32> {
+    return 1.0 / select(dir, vec3<f32>(1e-20), abs(dir) < vec3<f32>(1e-20));
+}
+
+// Push the children of an interior node the ray reaches onto the stack, the
+// further first so the nearer is visited next
+fn push_children(index: u32, ray: Ray, inverse: vec3<f32>, max_distance: f32, stack: ptr<function, array<u32, BVH_MAX_DEPTH>>, stack_size: ptr<function, u32>) {
+    var first = index + 1u;
+    var second = bvh_nodes.items[index].start;
+    var first_distance = bounds_distance(bvh_nodes.items[first], ray, inverse, max_distance);
+    var second_distance = bounds_distance(bvh_nodes.items[second], ray, inverse, max_distance);
+    if first_distance > second_distance {
+        let swap = first;
+        first = second;
+        second = swap;
+        let swap_distance = first_distance;
+        first_distance = second_distance;
+        second_distance = swap_distance;
+    }
+    if second_distance >= 0.0 {
+        (*stack)[*stack_size] = second;
+        *stack_size += 1u;
+    }
+    if first_distance >= 0.0 {
+        (*stack)[*stack_size] = first;
+        *stack_size += 1u;
+    }
+}
+
+// Closest hit within max_distance in a model's hierarchy, starting at root
+fn hit_model(root: u32, ray: Ray, max_distance_in: f32) -> RayHit {
+    var closest: RayHit;
+    var inverse = inverse_direction(ray.dir);
+    var stack: array<u32, BVH_MAX_DEPTH>;
+    stack[0] = root;
+    var stack_size = 1u;
+    while stack_size > 0u {
+        stack_size -= 1u;
+        var index = stack[stack_size];
+        var node = bvh_nodes.items[index];
+        var max_distance = select(max_distance_in, closest.distance, closest.hit);
+        if bounds_distance(node, ray, inverse, max_distance) < 0.0 {
+            continue;
+        }
+
+        if node.count == 0u {
+            push_children(index, ray, inverse, max_distance, &stack, &stack_size);
+            continue;
+        }
+        for (var i = node.start; i < node.start + node.count; i += 1u) {
+            var primitive = bvh_primitives.items[i];
+            closest_hit(&closest, hit_primitive(primitive, ray), primitive.index);
+        }
+    }
+    return closest;
+}
+
+// Closest hit within max_distance on the model an instance places, found by
+// walking the model's hierarchy with the ray moved into its space
+fn hit_instance(index: u32, ray: Ray, max_distance: f32) -> RayHit {
+    var instance = instances.items[index];
+    var dir = (instance.inverse * vec4<f32>(ray.dir, 0.0)).xyz;
+    var scale = length(dir);
+    var local = Ray((instance.inverse * vec4<f32>(ray.pos, 1.0)).xyz, dir / scale);
+    var ray_hit = hit_model(instance.root, local, max_distance * scale);
+    if !ray_hit.hit {
+        return ray_hit;
+    }
+
+    ray_hit.distance /= scale;
+    ray_hit.pos = ray.pos + ray_hit.distance * ray.dir;
+    // Normals are transformed by the inverse transpose to stay perpendicular
+    ray_hit.normal = normalize((vec4<f32>(ray_hit.normal, 0.0) * instance.inverse).xyz);
+    if instance.material != NO_MATERIAL {
+        ray_hit.material = instance.material;
+    }
+    ray_hit.instance = index;
+    return ray_hit;
 }
 
+// Closest hit, planes are tested one by one and everything else by walking
+// the BVH nearest node first, skipping nodes beyond the closest hit so far.
+// Leaves of the top level can be instances, whose models are walked in turn
 fn cast_ray(ray: Ray) -> RayHit {
-    var hit: bool = false;
     var closest: RayHit;
-    for (var i = 0; i < i32(arrayLength(&spheres.spheres)); i += 1) {
-        var sphere = spheres.spheres[i];
-        var ray_hit = hit_sphere(spheres.spheres[i], ray);
-
-        if ray_hit.hit {
-            if !hit || closest.distance >= ray_hit.distance {
-                closest = ray_hit;
-                hit = true;
+    for (var i = 0u; i < planes.count; i += 1u) {
+        closest_hit(&closest, hit_plane(planes.items[i], ray), i);
+    }
+
+    var inverse = inverse_direction(ray.dir);
+    var stack: array<u32, BVH_MAX_DEPTH>;
+    var stack_size = select(0u, 1u, bvh_nodes.count > 0u);
+    while stack_size > 0u {
+        stack_size -= 1u;
+        var index = stack[stack_size];
+        var node = bvh_nodes.items[index];
+        var max_distance = select(3.40282347e38, closest.distance, closest.hit);
+        if bounds_distance(node, ray, inverse, max_distance) < 0.0 {
+            continue;
+        }
+
+        if node.count == 0u {
+            push_children(index, ray, inverse, max_distance, &stack, &stack_size);
+            continue;
+        }
+        for (var i = node.start; i < node.start + node.count; i += 1u) {
+            var primitive = bvh_primitives.items[i];
+            if primitive.kind != PRIMITIVE_INSTANCE {
+                closest_hit(&closest, hit_primitive(primitive, ray), primitive.index);
+                continue;
             }
+            var instance_distance = select(3.40282347e38, closest.distance, closest.hit);
+            var ray_hit = hit_instance(primitive.index, ray, instance_distance);
+            closest_hit(&closest, ray_hit, ray_hit.index);
         }
     }
-    return closest;
+    return closest_light(ray, closest);
 }
 
 fn calc_ray(screen_pos: vec2<f32>) -> Ray {
-    var focal_length = 1.0;
-    var viewport_width = camera.viewport_height * (camera.dimensions.x / camera.dimensions.y);
+    var viewport_height = 2.0 * tan(radians(camera.vfov) / 2.0) * camera.focal;
+    var viewport_width = viewport_height * (camera.dimensions.x / camera.dimensions.y);
 
-    var viewport_u: vec3<f32> = vec3<f32>(viewport_width, 0.0, 0.0);
-    var viewport_v = vec3<f32>(0.0, -camera.viewport_height, 0.0);
+    var viewport_u = viewport_width * camera.u;
+    var viewport_v = viewport_height * -camera.v;
 
     var pixel_delta_u = viewport_u / camera.dimensions.x;
     var pixel_delta_v = viewport_v / camera.dimensions.y;
 
-    var viewport_upper_left = camera.pos - vec3<f32>(0.0, 0.0, focal_length) - viewport_u / 2.0 - viewport_v / 2.0;
+    var viewport_upper_left = camera.pos - camera.focal * camera.w - viewport_u / 2.0 - viewport_v / 2.0;
     var pixel00_loc = viewport_upper_left + 0.5 * (pixel_delta_u + pixel_delta_v);
 
     var pixel_center = pixel00_loc + (screen_pos.x * pixel_delta_u) + (screen_pos.y * pixel_delta_v);
-    var ray_direction = pixel_center - camera.pos;
+    var ray_direction = normalize(pixel_center - camera.pos);
 
     var ray: Ray;
     ray.dir = ray_direction;
-    ray.pos = pixel_center;
+    ray.pos = camera.pos;
+
+    // Thin lens, start from a point on the lens and aim at the focus plane
+    if camera.aperture > 0.0 {
+        var focus_point = camera.pos + ray_direction * (camera.focus_dist / dot(ray_direction, -camera.w));
+        var lens = 0.5 * camera.aperture * random_in_unit_disk(&sample_state);
+        ray.pos = camera.pos + lens.x * camera.u + lens.y * camera.v;
+        ray.dir = normalize(focus_point - ray.pos);
+    }
     return ray;
 }
 
@@ -229,19 +1431,63 @@ fn gamma_correction_vec(in: vec3<f32>) -> vec3<f32> {
     );
 }
 
+// Offset from the pixel centre of sample i of count, within half a pixel
+fn sample_offset(i: u32, count: u32) -> vec2<f32> {
+    var h = sample_2d(&sample_state);
+    if camera.jitter == JITTER_RANDOM {
+        return h - 0.5;
+    }
+
+    // Stratified, one sample in each cell of the squarest grid of exactly count cells
+    var rows = 1u;
+    var factor = 2u;
+    while factor * factor <= count {
+        if count % factor == 0u {
+            rows = factor;
+        }
+        factor += 1u;
+    }
+    var columns = count / rows;
+    var cell = vec2<f32>(f32(i % columns), f32(i / columns));
+    return (cell + h) / vec2<f32>(f32(columns), f32(rows)) - 0.5;
+}
+
 fn cast_multiple_rays(origin: vec2<f32>) -> vec3<f32> {
     var pixel_colour: vec3<f32>;
-    pixel_colour += iterative_ray_colour(calc_ray(origin + SAMPLES[0]));
-    pixel_colour += iterative_ray_colour(calc_ray(origin + SAMPLES[1]));
-    pixel_colour += iterative_ray_colour(calc_ray(origin + SAMPLES[2]));
-    pixel_colour += iterative_ray_colour(calc_ray(origin + SAMPLES[3]));
-    return gamma_correction_vec(pixel_colour / 4.0);
+    for (var i = 0u; i < camera.samples; i += 1u) {
+        sample_state = init_sample_state(vec2<u32>(origin), i);
+        pixel_colour += iterative_ray_colour(calc_ray(origin + sample_offset(i, camera.samples)));
+    }
+    return pixel_colour / f32(camera.samples);
 }
 
 
-// Fragment shader
+// Trace the pixel centred at pos and blend it into the running average
+fn trace_pixel(pos: vec2<f32>) -> vec4<f32> {
+    var colour = cast_multiple_rays(pos);
+
+    var previous = textureLoad(accumulated, vec2<i32>(pos), 0).xyz;
+    return vec4<f32>(mix(previous, colour, 1.0 / f32(camera.frame + 1u)), 1.0);
+}
+
+// Fragment shader, blends this frame into the running average
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    seed = f32(base_hash(vec2<u32>(in.clip_position.xy)));
-    return vec4<f32>(cast_multiple_rays(in.clip_position.xy), 1.0);
+    return trace_pixel(in.clip_position.xy);
+}
+
+// Compute shader, equivalent of fs_main for one pixel per invocation
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= textureDimensions(output)) {
+        return;
+    }
+    textureStore(output, id.xy, trace_pixel(vec2<f32>(id.xy) + 0.5));
+}
+
+// Fragment shader, displays the running average
+@fragment
+fn fs_display(in: VertexOutput) -> @location(0) vec4<f32> {
+    var colour = textureLoad(accumulated, vec2<i32>(in.clip_position.xy), 0).xyz;
+    return vec4<f32>(gamma_correction_vec(colour), 1.0);
 }
\ No newline at end of file
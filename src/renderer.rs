@@ -0,0 +1,222 @@
+use wgpu::util::DeviceExt;
+
+use crate::{
+    accumulation::Accumulation,
+    camera::{Camera, CameraWithBuffers},
+    geometry::{Geometry, GeometryWithBuffers},
+    options::TraceMode,
+    pipeline::Pipeline,
+    scene::Scene,
+    shading::{Shading, ShadingWithBuffers},
+    vertex::Vertex,
+};
+
+/// Device side state shared between windowed and headless rendering
+pub struct Renderer {
+    pub buffers: (wgpu::Buffer, wgpu::Buffer),
+    pub pipeline: Pipeline,
+    pub camera: CameraWithBuffers,
+    pub geometry: GeometryWithBuffers,
+    pub shading: ShadingWithBuffers,
+    pub accumulation: Accumulation,
+    /// Camera uploaded last frame, any change to it restarts accumulation
+    last_camera: Camera,
+}
+
+impl Renderer {
+    /// Vertexes spanning screenspace
+    const VERTICES: &'static [Vertex] = &[
+        Vertex::xyz(1.0, 1.0, 0.0),
+        Vertex::xyz(1.0, -1.0, 0.0),
+        Vertex::xyz(-1.0, -1.0, 0.0),
+        Vertex::xyz(-1.0, 1.0, 0.0),
+    ];
+
+    /// Indices for vertexes
+    const INDICES: &'static [u16] = &[0, 3, 1, 1, 3, 2];
+
+    /// Size of compute workgroups, must match `@workgroup_size` of `cs_main`
+    const WORKGROUP_SIZE: (u32, u32) = (8, 8);
+
+    /// Device limits needed by the bind groups, the defaults allow only 8
+    /// storage buffers in each shader stage
+    pub fn limits() -> wgpu::Limits {
+        wgpu::Limits {
+            max_storage_buffers_per_shader_stage: (Geometry::BUFFERS + Shading::BUFFERS) as u32,
+            ..Default::default()
+        }
+    }
+
+    /// Create a new Renderer for a scene, targeting textures of the given format
+    pub async fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        dimensions: [f32; 2],
+        scene: &Scene,
+        trace: TraceMode,
+    ) -> Self {
+        let buffers = Renderer::create_buffers(device);
+        let camera = Camera::new(device, scene.camera.build(dimensions));
+        let accumulation =
+            Accumulation::new(device, dimensions[0] as u32, dimensions[1] as u32, trace);
+        let geometry = Geometry::new_geometry_buffers(scene.geometry(), device);
+        let shading = Shading::new_shading_buffers(scene.shading(), device, queue);
+        let compute_layouts = accumulation.compute_layout.as_ref().map(|compute_layout| {
+            [
+                &camera.layout,
+                &geometry.layout,
+                compute_layout,
+                &shading.layout,
+            ]
+        });
+        let pipeline = Pipeline::new(
+            device,
+            format,
+            &[
+                &camera.layout,
+                &geometry.layout,
+                &accumulation.layout,
+                &shading.layout,
+            ],
+            compute_layouts.as_ref().map(|layouts| layouts.as_slice()),
+        )
+        .await;
+
+        Self {
+            buffers,
+            pipeline,
+            last_camera: camera.camera,
+            camera,
+            geometry,
+            shading,
+            accumulation,
+        }
+    }
+
+    /// Create vertex and index buffers
+    pub fn create_buffers(device: &wgpu::Device) -> (wgpu::Buffer, wgpu::Buffer) {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("vertex_buf"),
+            contents: bytemuck::cast_slice(Renderer::VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("index_buf"),
+            contents: bytemuck::cast_slice(Renderer::INDICES),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        (vertex_buffer, index_buffer)
+    }
+
+    /// Update the screen dimensions used by the camera and the accumulation size
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let dims = &mut self.camera.camera.screen_dimensions;
+        dims[0] = width as f32;
+        dims[1] = height as f32;
+
+        self.accumulation.resize(device, width, height);
+    }
+
+    /// Upload the camera, trace a frame into the accumulation and display it on the target view
+    pub fn render(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+    ) {
+        self.camera.camera.update_basis();
+
+        // Any change to the camera other than the frame counter restarts accumulation
+        self.camera.camera.frame = self.last_camera.frame;
+        if self.camera.camera != self.last_camera {
+            self.accumulation.reset();
+        }
+        self.camera.camera.frame = self.accumulation.frame;
+        self.last_camera = self.camera.camera;
+
+        queue.write_buffer(
+            &self.camera.buffer,
+            0,
+            bytemuck::bytes_of(&self.camera.camera),
+        );
+
+        match (&self.pipeline.compute, self.accumulation.compute_group()) {
+            (Some(compute), Some(compute_group)) => self.dispatch(encoder, compute, compute_group),
+            _ => self.draw(
+                encoder,
+                &self.pipeline.pipeline,
+                self.accumulation.write_view(),
+                self.accumulation.read_group(),
+            ),
+        }
+        self.draw(
+            encoder,
+            &self.pipeline.display,
+            view,
+            self.accumulation.write_group(),
+        );
+
+        self.accumulation.swap();
+    }
+
+    /// Record a compute pass tracing every pixel into the accumulation
+    fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::ComputePipeline,
+        accumulation_group: &wgpu::BindGroup,
+    ) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Compute Pass"),
+            timestamp_writes: None,
+        });
+
+        let [width, height] = self.camera.camera.screen_dimensions;
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &self.camera.bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.geometry.bind_group, &[]);
+        compute_pass.set_bind_group(2, accumulation_group, &[]);
+        compute_pass.set_bind_group(3, &self.shading.bind_group, &[]);
+        compute_pass.dispatch_workgroups(
+            (width as u32).div_ceil(Renderer::WORKGROUP_SIZE.0),
+            (height as u32).div_ceil(Renderer::WORKGROUP_SIZE.1),
+            1,
+        );
+    }
+
+    /// Record a full screen pass with the given pipeline into the target view
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        view: &wgpu::TextureView,
+        accumulation_group: &wgpu::BindGroup,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.geometry.bind_group, &[]);
+        render_pass.set_bind_group(2, accumulation_group, &[]);
+        render_pass.set_bind_group(3, &self.shading.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffers.0.slice(..));
+        render_pass.set_index_buffer(self.buffers.1.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..Renderer::INDICES.len() as u32, 0, 0..1);
+    }
+}
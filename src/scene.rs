@@ -0,0 +1,994 @@
+use anyhow::{anyhow, Context, Result};
+use cgmath::{InnerSpace, Matrix4, Vector3, Vector4};
+use serde::Deserialize;
+
+use crate::{
+    camera::{Camera, Jitter, Sampler},
+    environment::Environment,
+    geometry::{Geometry, Model},
+    gltf_scene::GltfScene,
+    instance::Instance,
+    light::Light,
+    load_bytes,
+    material::Material,
+    mesh::Mesh,
+    primitive::{rotation_matrix, Cuboid, Disc, MeshVertex, Plane, Quad, Triangle},
+    shading::Shading,
+    sky::{PhysicalSky, Sky},
+    sphere::Sphere,
+    texture::Texture,
+};
+
+/// Declarative description of everything rendered, loaded from a RON file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+    #[serde(default)]
+    pub camera: SceneCamera,
+    pub materials: Vec<SceneMaterial>,
+    #[serde(default)]
+    pub spheres: Vec<Sphere>,
+    #[serde(default)]
+    pub planes: Vec<ScenePlane>,
+    #[serde(default)]
+    pub discs: Vec<SceneDisc>,
+    #[serde(default)]
+    pub boxes: Vec<SceneBox>,
+    #[serde(default)]
+    pub quads: Vec<SceneQuad>,
+    #[serde(default)]
+    pub meshes: Vec<SceneMesh>,
+    /// Groups of primitives which only appear where instances place them
+    #[serde(default)]
+    pub models: Vec<SceneModel>,
+    #[serde(default)]
+    pub instances: Vec<SceneInstance>,
+    /// glTF files whose meshes are placed as instances by their nodes
+    #[serde(default)]
+    pub gltf: Vec<SceneGltf>,
+    #[serde(default)]
+    pub lights: Vec<SceneLight>,
+    /// Multiplies the sky gradient, black for scenes lit only by lights
+    #[serde(default = "Scene::default_sky")]
+    pub sky: [f32; 3],
+    /// Image replacing the sky as background and light
+    #[serde(default)]
+    pub environment: Option<SceneEnvironment>,
+    /// Analytic clear sky replacing the sky gradient, with a matching sun
+    #[serde(default)]
+    pub physical_sky: Option<ScenePhysicalSky>,
+    /// Decoded environment image, filled in by Scene::load
+    #[serde(skip)]
+    pub environment_map: Option<Environment>,
+    /// Loaded models, one for each of meshes, filled in by Scene::load
+    #[serde(skip)]
+    pub mesh_data: Vec<Mesh>,
+    /// Loaded files, one for each of gltf, filled in by Scene::load
+    #[serde(skip)]
+    pub gltf_data: Vec<GltfScene>,
+}
+
+/// Material as written in a scene, primitives refer to them by index
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum SceneMaterial {
+    Lambertian {
+        albedo: [f32; 3],
+    },
+    Metal {
+        albedo: [f32; 3],
+        #[serde(default)]
+        fuzz: f32,
+    },
+    Dielectric {
+        ior: f32,
+    },
+    Emissive {
+        emission: [f32; 3],
+    },
+}
+
+impl From<SceneMaterial> for Material {
+    fn from(material: SceneMaterial) -> Self {
+        match material {
+            SceneMaterial::Lambertian { albedo } => Material::lambertian(albedo),
+            SceneMaterial::Metal { albedo, fuzz } => Material::metal(albedo, fuzz),
+            SceneMaterial::Dielectric { ior } => Material::dielectric(ior),
+            SceneMaterial::Emissive { emission } => Material::emissive(emission),
+        }
+    }
+}
+
+/// Infinite plane as written in a scene
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenePlane {
+    /// Any point on the plane
+    pub pos: [f32; 3],
+    pub normal: [f32; 3],
+    pub material: u32,
+}
+
+impl From<ScenePlane> for Plane {
+    fn from(plane: ScenePlane) -> Self {
+        Plane::new(plane.pos, plane.normal, plane.material)
+    }
+}
+
+/// Disc as written in a scene
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDisc {
+    /// Centre of the disc
+    pub pos: [f32; 3],
+    pub normal: [f32; 3],
+    pub radius: f32,
+    pub material: u32,
+}
+
+impl From<SceneDisc> for Disc {
+    fn from(disc: SceneDisc) -> Self {
+        Disc::new(disc.pos, disc.normal, disc.radius, disc.material)
+    }
+}
+
+/// Box as written in a scene, axis aligned unless rotated
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneBox {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    /// Degrees around the x, y and z axes in that order, about the centre
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    pub material: u32,
+}
+
+impl From<SceneBox> for Cuboid {
+    fn from(cuboid: SceneBox) -> Self {
+        Cuboid::new(cuboid.min, cuboid.max, cuboid.rotation, cuboid.material)
+    }
+}
+
+/// Parallelogram as written in a scene, a rectangle when u and v are
+/// perpendicular
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneQuad {
+    /// Corner the edges start from
+    pub pos: [f32; 3],
+    pub u: [f32; 3],
+    pub v: [f32; 3],
+    pub material: u32,
+}
+
+impl From<SceneQuad> for Quad {
+    fn from(quad: SceneQuad) -> Self {
+        Quad::new(quad.pos, quad.u, quad.v, quad.material)
+    }
+}
+
+/// Triangle mesh as written in a scene, the model is scaled, rotated about
+/// its origin and then moved to pos
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneMesh {
+    /// Path of a Wavefront `.obj`, `.ply` or `.stl` file, loaded like the scene
+    /// itself
+    pub path: String,
+    #[serde(default)]
+    pub pos: [f32; 3],
+    /// Degrees around the x, y and z axes in that order
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default = "SceneMesh::default_scale")]
+    pub scale: f32,
+    /// Material for every face instead of those from the model file
+    #[serde(default)]
+    pub material: Option<u32>,
+}
+
+impl SceneMesh {
+    fn default_scale() -> f32 {
+        1.0
+    }
+
+    /// Load the model, choosing the format by file extension
+    pub async fn load(&self) -> Result<Mesh> {
+        let extension = self.path.rsplit('.').next().unwrap_or_default();
+        match extension.to_lowercase().as_str() {
+            "obj" => Mesh::load_obj(&self.path).await,
+            "ply" => Mesh::load_ply(&self.path).await,
+            "stl" => Mesh::load_stl(&self.path).await,
+            _ => Err(anyhow!(
+                "Unsupported mesh format {}, expected an .obj, .ply or .stl file",
+                self.path
+            )),
+        }
+    }
+
+    /// Vertex placed in the scene, normals only turn as scaling is uniform
+    fn place(&self, vertex: MeshVertex) -> MeshVertex {
+        let rotation = rotation_matrix(self.rotation);
+        let [pos, normal, offset] =
+            [vertex.pos, vertex.normal, self.pos].map(cgmath::Vector3::from);
+        let pos = rotation * (self.scale * pos) + offset;
+        let normal = rotation * normal;
+        let mut placed = vertex;
+        placed.pos = pos.into();
+        placed.normal = normal.into();
+        placed
+    }
+}
+
+/// Group of primitives as written in a scene, placed by instances rather than
+/// appearing by itself. Planes have no bounds so can't be part of one
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneModel {
+    #[serde(default)]
+    pub spheres: Vec<Sphere>,
+    #[serde(default)]
+    pub discs: Vec<SceneDisc>,
+    #[serde(default)]
+    pub boxes: Vec<SceneBox>,
+    #[serde(default)]
+    pub quads: Vec<SceneQuad>,
+    #[serde(default)]
+    pub meshes: Vec<SceneMesh>,
+    /// Loaded models, one for each of meshes, filled in by Scene::load
+    #[serde(skip)]
+    pub mesh_data: Vec<Mesh>,
+}
+
+impl SceneModel {
+    fn primitives(&self) -> ScenePrimitives<'_> {
+        ScenePrimitives {
+            spheres: &self.spheres,
+            planes: &[],
+            discs: &self.discs,
+            boxes: &self.boxes,
+            quads: &self.quads,
+            meshes: &self.meshes,
+            mesh_data: &self.mesh_data,
+        }
+    }
+}
+
+/// Copy of a model as written in a scene, the model is scaled, rotated about
+/// its origin and then moved to pos
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneInstance {
+    /// Index into the scene's models
+    pub model: u32,
+    #[serde(default)]
+    pub pos: [f32; 3],
+    /// Degrees around the x, y and z axes in that order
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    /// Along each of the model's axes
+    #[serde(default = "SceneInstance::default_scale")]
+    pub scale: [f32; 3],
+    /// Material for the whole model instead of those of its primitives
+    #[serde(default)]
+    pub material: Option<u32>,
+}
+
+impl SceneInstance {
+    fn default_scale() -> [f32; 3] {
+        [1.0, 1.0, 1.0]
+    }
+
+    /// Instance in the layout used by the GPU, fails if its transform can't be
+    /// inverted
+    pub fn build(&self) -> Result<Instance> {
+        let transform = placement(self.pos, self.rotation, self.scale);
+        Instance::new(self.model, transform, self.material)
+    }
+}
+
+/// Transform scaling along each axis, rotating about the origin by degrees
+/// around the x, y and z axes in that order, then moving to pos
+fn placement(pos: [f32; 3], rotation: [f32; 3], [x, y, z]: [f32; 3]) -> Matrix4<f32> {
+    Matrix4::from_translation(Vector3::from(pos))
+        * Matrix4::from(rotation_matrix(rotation))
+        * Matrix4::from_nonuniform_scale(x, y, z)
+}
+
+/// glTF 2.0 file as written in a scene, the whole file is scaled, rotated
+/// about its origin and then moved to pos
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneGltf {
+    /// Path of a `.gltf` or `.glb` file, loaded like the scene itself
+    pub path: String,
+    #[serde(default)]
+    pub pos: [f32; 3],
+    /// Degrees around the x, y and z axes in that order
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default = "SceneGltf::default_scale")]
+    pub scale: f32,
+    /// Material for every mesh instead of those from the file
+    #[serde(default)]
+    pub material: Option<u32>,
+    /// Whether to view the scene through the file's first camera instead of
+    /// the scene's
+    #[serde(default)]
+    pub camera: bool,
+}
+
+impl SceneGltf {
+    fn default_scale() -> f32 {
+        1.0
+    }
+
+    /// From the file's space to the scene's
+    fn transform(&self) -> Matrix4<f32> {
+        placement(self.pos, self.rotation, [self.scale; 3])
+    }
+
+    /// Point the camera like the file's first camera, if it has one
+    fn place_camera(&self, data: &GltfScene, camera: &mut SceneCamera) {
+        let Some(gltf_camera) = data.camera else {
+            return;
+        };
+        let transform = self.transform() * gltf_camera.transform;
+        let pos = (transform * Vector4::unit_w()).truncate();
+        let forward = (transform * -Vector4::unit_z()).truncate();
+        let up = (transform * Vector4::unit_y()).truncate();
+        camera.pos = pos.into();
+        camera.target = (pos + forward.normalize()).into();
+        camera.up = up.normalize().into();
+        camera.vfov = gltf_camera.vfov;
+    }
+}
+
+/// Primitives of the scene or of one of its models, which are checked and
+/// added to the geometry the same way
+struct ScenePrimitives<'a> {
+    spheres: &'a [Sphere],
+    planes: &'a [ScenePlane],
+    discs: &'a [SceneDisc],
+    boxes: &'a [SceneBox],
+    quads: &'a [SceneQuad],
+    meshes: &'a [SceneMesh],
+    mesh_data: &'a [Mesh],
+}
+
+impl ScenePrimitives<'_> {
+    /// Add the primitives to the geometry. Meshes share the vertex buffer and
+    /// the materials of those not given one are numbered from first_material,
+    /// in the same order as Scene::gpu_materials
+    fn add_to(&self, geometry: &mut Geometry, first_material: &mut u32) {
+        geometry.spheres.extend_from_slice(self.spheres);
+        geometry
+            .planes
+            .extend(self.planes.iter().copied().map(Plane::from));
+        geometry
+            .discs
+            .extend(self.discs.iter().copied().map(Disc::from));
+        geometry
+            .boxes
+            .extend(self.boxes.iter().copied().map(Cuboid::from));
+        geometry
+            .quads
+            .extend(self.quads.iter().copied().map(Quad::from));
+
+        for (mesh, data) in self.meshes.iter().zip(self.mesh_data) {
+            let first_vertex = geometry.vertices.len() as u32;
+            geometry
+                .vertices
+                .extend(data.vertices.iter().map(|&vertex| mesh.place(vertex)));
+            geometry
+                .triangles
+                .extend(data.triangles.iter().map(|triangle| {
+                    Triangle::new(
+                        triangle.vertices.map(|vertex| first_vertex + vertex),
+                        mesh.material.unwrap_or(*first_material + triangle.material),
+                    )
+                }));
+            if mesh.material.is_none() {
+                *first_material += data.materials.len() as u32;
+            }
+        }
+    }
+
+    /// Check the primitives refer to existing materials and have valid
+    /// shapes, parent is prefixed to the fields named in errors
+    fn validate(&self, parent: &str, material_count: usize) -> Result<()> {
+        // Meshes without a material keep their own
+        let materials = [
+            (
+                "spheres",
+                self.spheres
+                    .iter()
+                    .map(|p| Some(p.material))
+                    .collect::<Vec<_>>(),
+            ),
+            (
+                "planes",
+                self.planes.iter().map(|p| Some(p.material)).collect(),
+            ),
+            (
+                "discs",
+                self.discs.iter().map(|p| Some(p.material)).collect(),
+            ),
+            (
+                "boxes",
+                self.boxes.iter().map(|p| Some(p.material)).collect(),
+            ),
+            (
+                "quads",
+                self.quads.iter().map(|p| Some(p.material)).collect(),
+            ),
+            ("meshes", self.meshes.iter().map(|p| p.material).collect()),
+        ];
+        for (field, materials) in materials {
+            for (i, material) in materials.into_iter().enumerate() {
+                let Some(material) = material else {
+                    continue;
+                };
+                if material as usize >= material_count {
+                    return Err(anyhow!(
+                        "Invalid field `{parent}{field}[{i}].material`: material {material} does not exist, the scene has {material_count} materials"
+                    ));
+                }
+            }
+        }
+        for (i, mesh) in self.meshes.iter().enumerate() {
+            if mesh.scale <= 0.0 {
+                return Err(anyhow!(
+                    "Invalid field `{parent}meshes[{i}].scale`: scale must be positive"
+                ));
+            }
+        }
+        for (i, plane) in self.planes.iter().enumerate() {
+            if plane.normal == [0.0; 3] {
+                return Err(anyhow!(
+                    "Invalid field `{parent}planes[{i}].normal`: normal must not be zero"
+                ));
+            }
+        }
+        for (i, disc) in self.discs.iter().enumerate() {
+            if disc.normal == [0.0; 3] {
+                return Err(anyhow!(
+                    "Invalid field `{parent}discs[{i}].normal`: normal must not be zero"
+                ));
+            }
+            if disc.radius <= 0.0 {
+                return Err(anyhow!(
+                    "Invalid field `{parent}discs[{i}].radius`: radius must be positive"
+                ));
+            }
+        }
+        for (i, cuboid) in self.boxes.iter().enumerate() {
+            if (0..3).any(|axis| cuboid.min[axis] >= cuboid.max[axis]) {
+                return Err(anyhow!(
+                    "Invalid field `{parent}boxes[{i}].max`: max must be greater than min on every axis"
+                ));
+            }
+        }
+        for (i, quad) in self.quads.iter().enumerate() {
+            let [u, v] = [quad.u, quad.v].map(Vector3::from);
+            if u.cross(v) == Vector3::new(0.0, 0.0, 0.0) {
+                return Err(anyhow!(
+                    "Invalid field `{parent}quads[{i}].v`: edges u and v must not be parallel or zero"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Light source as written in a scene
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum SceneLight {
+    Point {
+        pos: [f32; 3],
+        intensity: [f32; 3],
+    },
+    Directional {
+        /// Direction the light travels in
+        direction: [f32; 3],
+        irradiance: [f32; 3],
+    },
+    Spherical {
+        pos: [f32; 3],
+        radius: f32,
+        radiance: [f32; 3],
+    },
+}
+
+impl From<SceneLight> for Light {
+    fn from(light: SceneLight) -> Self {
+        match light {
+            SceneLight::Point { pos, intensity } => Light::point(pos, intensity),
+            SceneLight::Directional {
+                direction,
+                irradiance,
+            } => Light::directional(direction, irradiance),
+            SceneLight::Spherical {
+                pos,
+                radius,
+                radiance,
+            } => Light::spherical(pos, radius, radiance),
+        }
+    }
+}
+
+/// Equirectangular environment map as written in a scene
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneEnvironment {
+    /// Path of a `.hdr` or `.exr` image, loaded like the scene itself
+    pub path: String,
+    /// Multiplies the radiance of the image
+    #[serde(default = "SceneEnvironment::default_intensity")]
+    pub intensity: f32,
+    /// Rotation around the y axis in degrees
+    #[serde(default)]
+    pub rotation: f32,
+}
+
+impl SceneEnvironment {
+    fn default_intensity() -> f32 {
+        1.0
+    }
+
+    /// Load and decode the image
+    pub async fn load(&self) -> Result<Environment> {
+        let bytes = load_bytes(&self.path)
+            .await
+            .with_context(|| format!("Failed to read environment map {}", self.path))?;
+        Environment::from_bytes(&bytes, self.intensity, self.rotation.to_radians())
+            .with_context(|| format!("Failed to load environment map {}", self.path))
+    }
+}
+
+/// Physical sky as written in a scene
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenePhysicalSky {
+    /// Angle of the sun above the horizon in degrees
+    pub elevation: f32,
+    /// Angle of the sun around the y axis in degrees, from -z towards +x
+    #[serde(default)]
+    pub azimuth: f32,
+    /// Haziness of the air, from 2 for a very clear sky to 10 for a hazy one
+    #[serde(default = "ScenePhysicalSky::default_turbidity")]
+    pub turbidity: f32,
+    /// Multiplies the radiance of the sky and sun
+    #[serde(default = "ScenePhysicalSky::default_intensity")]
+    pub intensity: f32,
+    /// Whether to add the sun's disk as a light
+    #[serde(default = "ScenePhysicalSky::default_sun")]
+    pub sun: bool,
+}
+
+impl ScenePhysicalSky {
+    fn default_turbidity() -> f32 {
+        3.0
+    }
+
+    fn default_intensity() -> f32 {
+        1.0
+    }
+
+    fn default_sun() -> bool {
+        true
+    }
+
+    /// Model in the layout used by the GPU
+    pub fn build(&self) -> PhysicalSky {
+        PhysicalSky::new(
+            self.elevation.to_radians(),
+            self.azimuth.to_radians(),
+            self.turbidity,
+            self.intensity,
+        )
+    }
+}
+
+/// Camera settings which can be set from a scene, unset fields use defaults
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneCamera {
+    pub pos: [f32; 3],
+    pub target: [f32; 3],
+    pub up: [f32; 3],
+    /// Vertical field of view in degrees
+    pub vfov: f32,
+    pub focal: f32,
+    /// Lens diameter, zero for a pinhole camera
+    pub aperture: f32,
+    /// Distance to the plane in focus when the aperture is non zero
+    pub focus_dist: f32,
+    pub max_depth: i32,
+    pub samples: u32,
+    pub jitter: Jitter,
+    pub sampler: Sampler,
+}
+
+impl Default for SceneCamera {
+    fn default() -> Self {
+        let camera = Camera::with_dimensions([0.0, 0.0]);
+        Self {
+            pos: camera.pos,
+            target: camera.target,
+            up: camera.up,
+            vfov: camera.vfov,
+            focal: camera.focal,
+            aperture: camera.aperture,
+            focus_dist: camera.focus_dist,
+            max_depth: camera.max_depth,
+            samples: camera.samples,
+            jitter: Jitter::default(),
+            sampler: Sampler::default(),
+        }
+    }
+}
+
+impl SceneCamera {
+    /// Create a Camera from the scene settings for the given screen dimensions
+    pub fn build(&self, dimensions: [f32; 2]) -> Camera {
+        let mut camera = Camera::with_dimensions(dimensions);
+        camera.pos = self.pos;
+        camera.target = self.target;
+        camera.up = self.up;
+        camera.vfov = self.vfov;
+        camera.focal = self.focal;
+        camera.aperture = self.aperture;
+        camera.focus_dist = self.focus_dist;
+        camera.max_depth = self.max_depth;
+        camera.samples = self.samples.max(1);
+        camera.jitter = self.jitter as u32;
+        camera.sampler = self.sampler as u32;
+        camera.update_basis();
+        camera
+    }
+}
+
+impl Scene {
+    /// Scene loaded when none is specified
+    pub const DEFAULT_PATH: &'static str = "./scenes/default.ron";
+    /// Extensions of model files which can be loaded as scenes by themselves
+    pub const MODEL_EXTENSIONS: [&'static str; 5] = ["gltf", "glb", "obj", "ply", "stl"];
+
+    /// Load a scene from path and any images and models it refers to, errors
+    /// name the file and the offending field. A model file is loaded as a
+    /// scene of just that model, seen through its camera if it is a glTF file
+    /// with one and from in front otherwise
+    pub async fn load(path: &str) -> Result<Self> {
+        let extension = path.rsplit('.').next().unwrap_or_default().to_lowercase();
+        let model = Scene::MODEL_EXTENSIONS.contains(&extension.as_str());
+        let mut scene = if model {
+            Scene::model(path, &extension)?
+        } else {
+            let bytes = load_bytes(path)
+                .await
+                .with_context(|| format!("Failed to read scene file {path}"))?;
+            Scene::parse(&bytes).with_context(|| format!("Failed to load scene file {path}"))?
+        };
+
+        if let Some(environment) = &scene.environment {
+            scene.environment_map = Some(environment.load().await?);
+        }
+        for mesh in &scene.meshes {
+            scene.mesh_data.push(mesh.load().await?);
+        }
+        for model in &mut scene.models {
+            for mesh in &model.meshes {
+                model.mesh_data.push(mesh.load().await?);
+            }
+        }
+        for gltf in &scene.gltf {
+            let data = GltfScene::load(&gltf.path).await?;
+            if gltf.camera {
+                gltf.place_camera(&data, &mut scene.camera);
+            }
+            scene.gltf_data.push(data);
+        }
+        if model && scene.gltf_data.iter().all(|data| data.camera.is_none()) {
+            scene.frame();
+        }
+        Ok(scene)
+    }
+
+    /// Scene of a single model file, with the materials from the file
+    fn model(path: &str, extension: &str) -> Result<Self> {
+        let mut scene = Scene::parse(b"(materials: [])")?;
+        if ["gltf", "glb"].contains(&extension) {
+            scene.gltf.push(SceneGltf {
+                path: path.to_string(),
+                pos: [0.0; 3],
+                rotation: [0.0; 3],
+                scale: 1.0,
+                material: None,
+                camera: true,
+            });
+        } else {
+            scene.meshes.push(SceneMesh {
+                path: path.to_string(),
+                pos: [0.0; 3],
+                rotation: [0.0; 3],
+                scale: 1.0,
+                material: None,
+            });
+        }
+        Ok(scene)
+    }
+
+    /// Point the camera at the middle of everything but planes, from in front
+    /// and a little above and far enough away for all of it to be in view
+    pub fn frame(&mut self) {
+        let geometry = self.geometry();
+        let Some(root) = geometry.bvh.nodes.first() else {
+            return;
+        };
+        let bounds = root.bounds();
+        let centre = bounds.centroid();
+        let radius = (bounds.max - bounds.min).magnitude() / 2.0;
+        let distance = radius / (self.camera.vfov.to_radians() / 2.0).sin();
+        let pos = centre + Vector3::new(0.0, 0.5, 1.0).normalize() * distance;
+        self.camera.pos = pos.into();
+        self.camera.target = centre.into();
+        self.camera.up = [0.0, 1.0, 0.0];
+    }
+
+    /// Parse a scene from the contents of a RON file, optional fields can be
+    /// written without `Some`
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let options = ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME);
+        let mut deserializer = ron::Deserializer::from_bytes_with_options(bytes, options)?;
+        let scene: Scene = serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+            let path = err.path().to_string();
+            let position = deserializer.span_error(ron::Error::Message(String::new()));
+            anyhow!(
+                "Invalid field `{path}` at line {}, column {}: {}",
+                position.position.line,
+                position.position.col,
+                err.into_inner()
+            )
+        })?;
+        deserializer.end()?;
+        scene.validate()?;
+        Ok(scene)
+    }
+
+    fn default_sky() -> [f32; 3] {
+        [1.0, 1.0, 1.0]
+    }
+
+    fn primitives(&self) -> ScenePrimitives<'_> {
+        ScenePrimitives {
+            spheres: &self.spheres,
+            planes: &self.planes,
+            discs: &self.discs,
+            boxes: &self.boxes,
+            quads: &self.quads,
+            meshes: &self.meshes,
+            mesh_data: &self.mesh_data,
+        }
+    }
+
+    /// Meshes placed directly in the scene then those of each model, with
+    /// their loaded data
+    fn loaded_meshes(&self) -> impl Iterator<Item = (&SceneMesh, &Mesh)> {
+        let models = self.models.iter();
+        self.meshes
+            .iter()
+            .zip(&self.mesh_data)
+            .chain(models.flat_map(|model| model.meshes.iter().zip(&model.mesh_data)))
+    }
+
+    /// Material table in the layout used by the GPU, the scene's materials
+    /// followed by those of each mesh then each glTF file not given one
+    pub fn gpu_materials(&self) -> Vec<Material> {
+        let mut materials: Vec<Material> =
+            self.materials.iter().copied().map(Material::from).collect();
+        for (mesh, data) in self.loaded_meshes() {
+            if mesh.material.is_none() {
+                materials.extend_from_slice(&data.materials);
+            }
+        }
+        // Each file's textures follow those of the files before it
+        let mut first_texture = 0;
+        for (gltf, data) in self.gltf.iter().zip(&self.gltf_data) {
+            if gltf.material.is_none() {
+                materials.extend(
+                    data.materials
+                        .iter()
+                        .map(|&material| match material.texture {
+                            Material::NO_TEXTURE => material,
+                            texture => material.with_texture(first_texture + texture),
+                        }),
+                );
+            }
+            first_texture += data.textures.len() as u32;
+        }
+        materials
+    }
+
+    /// Textures of every glTF file, in the layout used by the GPU
+    pub fn textures(&self) -> Vec<Texture> {
+        self.gltf_data
+            .iter()
+            .flat_map(|data| data.textures.iter().cloned())
+            .collect()
+    }
+
+    /// Primitives in the layout used by the GPU, those of models follow the
+    /// ones placed directly in the scene. Each mesh of a glTF file is a model
+    /// after the scene's, placed by an instance for each node. Panics on
+    /// instances validate would have rejected
+    pub fn geometry(&self) -> Geometry {
+        let mut geometry = Geometry::default();
+        let mut first_material = self.materials.len() as u32;
+        self.primitives().add_to(&mut geometry, &mut first_material);
+        for model in &self.models {
+            let start: [usize; Geometry::PRIMITIVE_KINDS] =
+                std::array::from_fn(|kind| geometry.count(kind as u32));
+            model
+                .primitives()
+                .add_to(&mut geometry, &mut first_material);
+            geometry.models.push(Model {
+                ranges: std::array::from_fn(|kind| start[kind]..geometry.count(kind as u32)),
+            });
+        }
+        geometry.instances = self
+            .instances
+            .iter()
+            .map(|instance| {
+                instance
+                    .build()
+                    .expect("instances that can't be built are rejected by validate")
+            })
+            .collect();
+
+        for (gltf, data) in self.gltf.iter().zip(&self.gltf_data) {
+            let first_model = geometry.models.len();
+            for mesh in &data.meshes {
+                let start: [usize; Geometry::PRIMITIVE_KINDS] =
+                    std::array::from_fn(|kind| geometry.count(kind as u32));
+                let first_vertex = geometry.vertices.len() as u32;
+                geometry.vertices.extend_from_slice(&mesh.vertices);
+                geometry
+                    .triangles
+                    .extend(mesh.triangles.iter().map(|triangle| {
+                        Triangle::new(
+                            triangle.vertices.map(|vertex| first_vertex + vertex),
+                            gltf.material.unwrap_or(first_material + triangle.material),
+                        )
+                    }));
+                geometry.models.push(Model {
+                    ranges: std::array::from_fn(|kind| start[kind]..geometry.count(kind as u32)),
+                });
+            }
+            // Nodes scaled to nothing, a common way of hiding them, are left out
+            geometry
+                .instances
+                .extend(data.nodes.iter().filter_map(|node| {
+                    let model = (first_model + node.mesh) as u32;
+                    Instance::new(model, gltf.transform() * node.transform, None).ok()
+                }));
+            if gltf.material.is_none() {
+                first_material += data.materials.len() as u32;
+            }
+        }
+        geometry.with_bvh()
+    }
+
+    /// Materials, lights and sky in the layout used by the GPU
+    pub fn shading(&self) -> Shading {
+        let mut lights: Vec<Light> = self.lights.iter().copied().map(Light::from).collect();
+        let sky = if let Some(environment) = &self.environment_map {
+            Sky::Environment(environment.clone())
+        } else if let Some(physical_sky) = &self.physical_sky {
+            let physical = physical_sky.build();
+            if physical_sky.sun {
+                lights.push(physical.sun());
+            }
+            Sky::Physical(physical)
+        } else {
+            Sky::Gradient(self.sky)
+        };
+        Shading::new(self.gpu_materials(), lights, sky).with_textures(self.textures())
+    }
+
+    /// Check references between parts of the scene
+    fn validate(&self) -> Result<()> {
+        // Without a view direction and an up across it the camera has no basis
+        let view = Vector3::from(self.camera.target) - Vector3::from(self.camera.pos);
+        if view.magnitude2() == 0.0 {
+            return Err(anyhow!(
+                "Invalid field `camera.target`: target must not be the camera position"
+            ));
+        }
+        let up = Vector3::from(self.camera.up);
+        // A zero up has no direction, making this NaN
+        let sin = up.normalize().cross(view.normalize()).magnitude();
+        if sin.is_nan() || sin <= 1e-6 {
+            return Err(anyhow!(
+                "Invalid field `camera.up`: up must not be zero or along the view direction"
+            ));
+        }
+
+        let material_count = self.materials.len();
+        self.primitives().validate("", material_count)?;
+        for (i, model) in self.models.iter().enumerate() {
+            model
+                .primitives()
+                .validate(&format!("models[{i}]."), material_count)?;
+        }
+        for (i, instance) in self.instances.iter().enumerate() {
+            if instance.model as usize >= self.models.len() {
+                return Err(anyhow!(
+                    "Invalid field `instances[{i}].model`: model {} does not exist, the scene has {} models",
+                    instance.model,
+                    self.models.len()
+                ));
+            }
+            if let Some(material) = instance.material {
+                if material as usize >= material_count {
+                    return Err(anyhow!(
+                        "Invalid field `instances[{i}].material`: material {material} does not exist, the scene has {material_count} materials"
+                    ));
+                }
+            }
+            if let Err(err) = instance.build() {
+                return Err(anyhow!("Invalid field `instances[{i}]`: {err}"));
+            }
+        }
+        for (i, gltf) in self.gltf.iter().enumerate() {
+            if let Some(material) = gltf.material {
+                if material as usize >= material_count {
+                    return Err(anyhow!(
+                        "Invalid field `gltf[{i}].material`: material {material} does not exist, the scene has {material_count} materials"
+                    ));
+                }
+            }
+            if gltf.scale <= 0.0 {
+                return Err(anyhow!(
+                    "Invalid field `gltf[{i}].scale`: scale must be positive"
+                ));
+            }
+        }
+        for (i, light) in self.lights.iter().enumerate() {
+            match *light {
+                SceneLight::Directional { direction, .. } if direction == [0.0; 3] => {
+                    return Err(anyhow!(
+                        "Invalid field `lights[{i}].direction`: direction must not be zero"
+                    ));
+                }
+                SceneLight::Spherical { radius, .. } if radius <= 0.0 => {
+                    return Err(anyhow!(
+                        "Invalid field `lights[{i}].radius`: radius must be positive"
+                    ));
+                }
+                _ => {}
+            }
+        }
+        if let Some(physical_sky) = &self.physical_sky {
+            if self.environment.is_some() {
+                return Err(anyhow!(
+                    "Invalid field `physical_sky`: a scene can't have both an environment map and a physical sky"
+                ));
+            }
+            if !(0.0..=90.0).contains(&physical_sky.elevation) {
+                return Err(anyhow!(
+                    "Invalid field `physical_sky.elevation`: elevation must be between 0 and 90 degrees"
+                ));
+            }
+            if !(1.7..=10.0).contains(&physical_sky.turbidity) {
+                return Err(anyhow!(
+                    "Invalid field `physical_sky.turbidity`: turbidity must be between 1.7 and 10"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
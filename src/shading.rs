@@ -0,0 +1,272 @@
+use bytemuck::Zeroable;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    context::GraphicsContext,
+    cpu,
+    environment::{Environment, SkyDistribution},
+    light::Light,
+    material::Material,
+    sky::{PhysicalSky, Sky},
+    texture::Texture,
+};
+
+/// Materials, lights and the environment map, which describe shading rather
+/// than geometry, sharing the last of the four bind groups wgpu guarantees
+pub struct ShadingWithBuffers {
+    pub shading: Shading,
+    pub layout: wgpu::BindGroupLayout,
+    pub materials_buffer: wgpu::Buffer,
+    pub lights_buffer: wgpu::Buffer,
+    pub sky_distribution_buffer: wgpu::Buffer,
+    pub textures_buffer: wgpu::Buffer,
+    pub environment_texture: wgpu::Texture,
+    pub sampler: wgpu::Sampler,
+    pub bind_group: wgpu::BindGroup,
+}
+
+/// Everything deciding how surfaces are lit and shaded
+#[derive(Clone, Debug)]
+pub struct Shading {
+    pub materials: Vec<Material>,
+    pub lights: Vec<Light>,
+    pub sky: Sky,
+    /// Importance sampling table of the sky, made by Shading::new
+    pub sky_distribution: SkyDistribution,
+    /// Textures materials refer to by index
+    pub textures: Vec<Texture>,
+}
+
+/// Start of the lights buffer, followed by the lights themselves
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightsHeader {
+    /// Colour of the gradient sky
+    sky: [f32; 3],
+    count: u32,
+    /// Which kind of sky surrounds the scene, from Sky::model
+    sky_model: u32,
+    /// Multiplies the environment map
+    intensity: f32,
+    /// Rotation of the environment map
+    rotation: f32,
+    _pad: [f32; 1],
+    physical: PhysicalSky,
+}
+
+impl Shading {
+    /// Number of storage buffers in the bind group
+    pub const BUFFERS: usize = 4;
+
+    /// Shading with the sky tabulated for importance sampling
+    pub fn new(materials: Vec<Material>, lights: Vec<Light>, sky: Sky) -> Self {
+        let sky_distribution = match &sky {
+            Sky::Gradient(colour) => {
+                let (width, height) = SkyDistribution::GRADIENT_SIZE;
+                SkyDistribution::tabulate(width, height, |_, y| {
+                    let theta = std::f32::consts::PI * (y as f32 + 0.5) / height as f32;
+                    cpu::sky_gradient(*colour, theta.cos()).into()
+                })
+            }
+            Sky::Environment(environment) => {
+                SkyDistribution::tabulate_image(environment.width, environment.height, |x, y| {
+                    let [r, g, b, _] = environment.pixels[(y * environment.width + x) as usize];
+                    [r, g, b]
+                })
+            }
+            Sky::Physical(physical) => {
+                let (width, height) = SkyDistribution::PHYSICAL_SIZE;
+                SkyDistribution::tabulate(width, height, |x, y| {
+                    let uv = cgmath::vec2(
+                        (x as f32 + 0.5) / width as f32,
+                        (y as f32 + 0.5) / height as f32,
+                    );
+                    cpu::physical_sky_colour(physical, cpu::environment_direction(0.0, uv)).into()
+                })
+            }
+        };
+
+        Self {
+            materials,
+            lights,
+            sky,
+            sky_distribution,
+            textures: Vec::new(),
+        }
+    }
+
+    /// Add the textures materials refer to
+    pub fn with_textures(mut self, textures: Vec<Texture>) -> Self {
+        self.textures = textures;
+        self
+    }
+
+    /// Contents of the materials buffer, like the lights one zeroed material
+    /// stands in when there are none
+    pub fn materials_bytes(&self) -> Vec<u8> {
+        if self.materials.is_empty() {
+            return bytemuck::bytes_of(&Material::zeroed()).to_vec();
+        }
+        bytemuck::cast_slice(&self.materials).to_vec()
+    }
+
+    /// Contents of the lights buffer, bindings can't be empty so a scene
+    /// without lights still has one zeroed light after the header
+    pub fn lights_bytes(&self) -> Vec<u8> {
+        let mut header = LightsHeader {
+            sky: [0.0; 3],
+            count: self.lights.len() as u32,
+            sky_model: self.sky.model(),
+            intensity: 0.0,
+            rotation: 0.0,
+            _pad: Default::default(),
+            physical: PhysicalSky::zeroed(),
+        };
+        match &self.sky {
+            Sky::Gradient(colour) => header.sky = *colour,
+            Sky::Environment(environment) => {
+                header.intensity = environment.intensity;
+                header.rotation = environment.rotation;
+            }
+            Sky::Physical(physical) => header.physical = *physical,
+        }
+
+        let mut bytes = bytemuck::bytes_of(&header).to_vec();
+        if self.lights.is_empty() {
+            bytes.extend_from_slice(bytemuck::bytes_of(&Light::default()));
+        } else {
+            bytes.extend_from_slice(bytemuck::cast_slice(&self.lights));
+        }
+        bytes
+    }
+
+    pub fn new_shading_buffers(
+        shading: Shading,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> ShadingWithBuffers {
+        // Create layout from entries, read only storage buffers for materials,
+        // lights, the sky distribution and textures, then the environment map,
+        // which being 32 bit float can't be filtered
+        let visibility = wgpu::ShaderStages::VERTEX_FRAGMENT | wgpu::ShaderStages::COMPUTE;
+        let mut entries = [0, 1, 4, 5]
+            .into_iter()
+            .map(|i| wgpu::BindGroupLayoutEntry {
+                binding: i,
+                visibility,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            })
+            .collect::<Vec<wgpu::BindGroupLayoutEntry>>();
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        });
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 3,
+            visibility,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+            count: None,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("shading_binding"),
+        });
+
+        // Create buffers with initial contents of the material table and lights
+        let materials_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("materials_buf"),
+            contents: &shading.materials_bytes(),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let lights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("lights_buf"),
+            contents: &shading.lights_bytes(),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let sky_distribution_buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("sky_distribution_buf"),
+                contents: &shading.sky_distribution.bytes(),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            });
+
+        let textures_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("textures_buf"),
+            contents: &Texture::buffer_bytes(&shading.textures),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // Create the environment map texture, or a black stand in without one
+        let environment_texture = match &shading.sky {
+            Sky::Environment(environment) => environment.create_texture(device, queue),
+            _ => Environment::create_empty_texture(device, queue),
+        };
+        let environment_view =
+            environment_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = GraphicsContext::create_sampler(device);
+
+        // Create bind group
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: materials_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: lights_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&environment_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: sky_distribution_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: textures_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("shading_group"),
+        });
+
+        ShadingWithBuffers {
+            shading,
+            layout,
+            materials_buffer,
+            lights_buffer,
+            sky_distribution_buffer,
+            textures_buffer,
+            environment_texture,
+            sampler,
+            bind_group,
+        }
+    }
+}
+
+impl Default for Shading {
+    fn default() -> Self {
+        Shading::new(Vec::new(), Vec::new(), Sky::default())
+    }
+}
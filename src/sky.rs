@@ -0,0 +1,144 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use crate::{environment::Environment, light::Light};
+
+/// What surrounds the scene, seen where rays escape and lighting it from
+/// infinitely far away
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sky {
+    /// White to blue gradient multiplied by a colour, black for scenes lit
+    /// only by lights
+    Gradient([f32; 3]),
+    /// Equirectangular image
+    Environment(Environment),
+    /// Analytic clear sky
+    Physical(PhysicalSky),
+}
+
+impl Sky {
+    /// Values of `lights.sky_model` in the shader
+    pub const GRADIENT: u32 = 0;
+    pub const ENVIRONMENT: u32 = 1;
+    pub const PHYSICAL: u32 = 2;
+
+    pub fn model(&self) -> u32 {
+        match self {
+            Sky::Gradient(_) => Sky::GRADIENT,
+            Sky::Environment(_) => Sky::ENVIRONMENT,
+            Sky::Physical(_) => Sky::PHYSICAL,
+        }
+    }
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Sky::Gradient([1.0, 1.0, 1.0])
+    }
+}
+
+/// Clear sky from "A Practical Analytic Model for Daylight", Preetham et al.
+/// 1999, in the layout used by the GPU. Luminance and chromaticity follow the
+/// Perez formula in the angle from the zenith and the angle from the sun
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PhysicalSky {
+    /// Perez coefficients A to E, each for luminance Y then chromaticity x
+    /// and y, the last component is unused
+    pub coefficients: [[f32; 4]; 5],
+    /// Y, x and y at the zenith divided by the Perez function there, so the
+    /// model is this times the Perez function
+    pub zenith: [f32; 3],
+    pub turbidity: f32,
+    /// Unit vector towards the sun
+    pub sun_direction: [f32; 3],
+    /// Converts luminance in kcd/m² to radiance
+    pub intensity: f32,
+}
+
+impl PhysicalSky {
+    /// Angular radius of the sun's disk in radians
+    pub const SUN_ANGULAR_RADIUS: f32 = 0.00465;
+    /// Radiance of a luminance of 1 kcd/m² at intensity one, a clear sky
+    /// straight up is about 0.2 at noon
+    pub const LUMINANCE_SCALE: f32 = 0.02;
+    /// Luminance of the sun outside the atmosphere in kcd/m²
+    pub const SUN_LUMINANCE: f32 = 2.0e6;
+    /// Smallest cosine of the angle from the zenith the model is evaluated at,
+    /// directions below it take the colour of the horizon
+    pub const HORIZON: f32 = 0.001;
+    /// Perez coefficients A to E for Y, x and y, linear in turbidity as
+    /// slope and offset
+    const PEREZ: [[[f32; 2]; 3]; 5] = [
+        [[0.1787, -1.4630], [-0.0193, -0.2592], [-0.0167, -0.2608]],
+        [[-0.3554, 0.4275], [-0.0665, 0.0008], [-0.0950, 0.0092]],
+        [[-0.0227, 5.3251], [-0.0004, 0.2125], [-0.0079, 0.2102]],
+        [[0.1206, -2.5771], [-0.0641, -0.8989], [-0.0441, -1.6537]],
+        [[-0.0670, 0.3703], [-0.0033, 0.0452], [-0.0109, 0.0529]],
+    ];
+    /// Wavelengths in micrometres the sun is attenuated at for red, green and blue
+    const WAVELENGTHS: [f32; 3] = [0.68, 0.55, 0.44];
+
+    /// Sky with the sun at the given elevation above the horizon and azimuth
+    /// around the y axis from -z towards +x, both in radians. Turbidity goes
+    /// from about 2 for a very clear sky to 10 for a hazy one
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32, intensity: f32) -> Self {
+        let t = turbidity;
+        let coefficients = PhysicalSky::PEREZ.map(|row| {
+            let [y, x, yc] = row.map(|[slope, offset]| slope * t + offset);
+            [y, x, yc, 0.0]
+        });
+
+        // Zenith values are fitted in the angle of the sun from the zenith
+        let theta = FRAC_PI_2 - elevation;
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let [theta2, theta3] = [theta * theta, theta * theta * theta];
+        let x = t * t * (0.00166 * theta3 - 0.00375 * theta2 + 0.00209 * theta)
+            + t * (-0.02903 * theta3 + 0.06377 * theta2 - 0.03202 * theta + 0.00394)
+            + (0.11693 * theta3 - 0.21196 * theta2 + 0.06052 * theta + 0.25886);
+        let y = t * t * (0.00275 * theta3 - 0.00610 * theta2 + 0.00317 * theta)
+            + t * (-0.04214 * theta3 + 0.08970 * theta2 - 0.04153 * theta + 0.00516)
+            + (0.15346 * theta3 - 0.26756 * theta2 + 0.06670 * theta + 0.26688);
+
+        let zenith = [luminance, x, y];
+        let zenith = std::array::from_fn(|i| {
+            let [a, b, c, d, e] = coefficients.map(|coefficient| coefficient[i]);
+            let perez =
+                (1.0 + a * b.exp()) * (1.0 + c * (d * theta).exp() + e * theta.cos().powi(2));
+            zenith[i] / perez
+        });
+
+        let (sin_azimuth, cos_azimuth) = azimuth.sin_cos();
+        let (sin_elevation, cos_elevation) = elevation.sin_cos();
+        Self {
+            coefficients,
+            zenith,
+            turbidity,
+            sun_direction: [
+                cos_elevation * sin_azimuth,
+                sin_elevation,
+                -cos_elevation * cos_azimuth,
+            ],
+            intensity: intensity * PhysicalSky::LUMINANCE_SCALE,
+        }
+    }
+
+    /// Light for the sun's disk, its radiance attenuated by Rayleigh and
+    /// aerosol scattering along the path through the atmosphere, from the
+    /// appendix of the paper
+    pub fn sun(&self) -> Light {
+        let cos_theta = self.sun_direction[1];
+        let theta = cos_theta.acos().to_degrees();
+        // Relative optical mass of air, one looking straight up
+        let mass = 1.0 / (cos_theta + 0.15 * (93.885 - theta).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let radiance = PhysicalSky::WAVELENGTHS.map(|lambda| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+            PhysicalSky::SUN_LUMINANCE * self.intensity * rayleigh * aerosol
+        });
+
+        let [x, y, z] = self.sun_direction;
+        Light::sun([-x, -y, -z], PhysicalSky::SUN_ANGULAR_RADIUS, radiance)
+    }
+}
@@ -1,68 +1,37 @@
-use wgpu::util::DeviceExt;
+use cgmath::Vector3;
 
-pub struct SpheresWithBuffers {
-    pub spheres: Spheres,
-    pub layout: wgpu::BindGroupLayout,
-    pub buffer: wgpu::Buffer,
-    pub bind_group: wgpu::BindGroup,
-}
-
-pub struct Spheres {
-    pub spheres: Vec<Sphere>,
-}
+use crate::bvh::Bounds;
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Sphere {
     pub pos: [f32; 3],
     pub radius: f32,
-    pub colour: [f32; 3],
-    pub reflection: f32,
+    /// Index into the material table
+    pub material: u32,
+    #[serde(skip)]
+    _pad: [f32; 3],
 }
 
 impl Sphere {
-    pub fn new_sphere_buffers(spheres: Spheres, device: &wgpu::Device) -> SpheresWithBuffers {
-        // Create layout from entries
-        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-            label: Some("spheres_binding"),
-        });
-
-        // Create buffer with intiial contents of default ImageDisplay
-        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("spheres_buf"),
-            contents: bytemuck::cast_slice(&spheres.spheres),
-            usage: wgpu::BufferUsages::STORAGE,
-        });
-
-        // Create bind group
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &buffer,
-                    offset: 0,
-                    size: None,
-                }),
-            }],
-            label: Some("spheres_group"),
-        });
+    pub fn new(pos: [f32; 3], radius: f32, material: u32) -> Self {
+        Self {
+            pos,
+            radius,
+            material,
+            _pad: Default::default(),
+        }
+    }
 
-        SpheresWithBuffers {
-            spheres,
-            layout,
-            buffer,
-            bind_group,
+    /// Negative radii, which flip the normals of hollow spheres, bound the
+    /// same as positive ones
+    pub fn bounds(&self) -> Bounds {
+        let pos = Vector3::from(self.pos);
+        let r = self.radius.abs();
+        Bounds {
+            min: pos - Vector3::new(r, r, r),
+            max: pos + Vector3::new(r, r, r),
         }
     }
 }
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{
+    material::Material,
+    mesh::{parse_floats, Mesh},
+    primitive::{MeshVertex, Triangle},
+};
+
+/// Size of the header of binary STL files, before the triangle count
+const BINARY_HEADER: usize = 80;
+/// Size of each triangle of binary STL files, a normal, three corners and an
+/// attribute count
+const BINARY_TRIANGLE: usize = 50;
+
+/// Parse an STL file, ASCII or binary. Corners at the same position are
+/// merged into shared vertices, without normals as facets are shaded flat,
+/// and every facet shares one material
+pub fn parse_stl(bytes: &[u8]) -> Result<Mesh> {
+    // Binary files can start with "solid" too, their size gives them away
+    let count = bytes
+        .get(BINARY_HEADER..BINARY_HEADER + 4)
+        .map(|count| u32::from_le_bytes(count.try_into().unwrap()) as usize);
+    // A count whose facets couldn't fit in memory can't be binary either
+    let binary = count
+        .and_then(|count| count.checked_mul(BINARY_TRIANGLE))
+        .is_some_and(|size| bytes.len() - BINARY_HEADER - 4 == size);
+    let facets = if binary {
+        bytes[BINARY_HEADER + 4..]
+            .chunks_exact(BINARY_TRIANGLE)
+            .map(|triangle| {
+                let float =
+                    |i: usize| f32::from_le_bytes(triangle[4 * i..4 * i + 4].try_into().unwrap());
+                // Skipping the normal
+                [0, 1, 2].map(|corner| [0, 1, 2].map(|axis| float(3 + 3 * corner + axis)))
+            })
+            .collect()
+    } else if bytes.starts_with(b"solid") {
+        parse_ascii(bytes)?
+    } else {
+        return Err(anyhow!("not an STL file"));
+    };
+
+    let mut mesh = Mesh {
+        materials: vec![Material::lambertian(Mesh::DEFAULT_ALBEDO)],
+        ..Default::default()
+    };
+    let mut vertex_indices: HashMap<[u32; 3], u32> = HashMap::new();
+    for facet in facets {
+        let vertices = facet.map(|pos| {
+            *vertex_indices
+                .entry(pos.map(f32::to_bits))
+                .or_insert_with(|| {
+                    mesh.vertices.push(MeshVertex::new(pos, [0.0; 3]));
+                    mesh.vertices.len() as u32 - 1
+                })
+        });
+        mesh.triangles.push(Triangle::new(vertices, 0));
+    }
+    Ok(mesh)
+}
+
+/// Corners of each facet of an ASCII STL file, facets with more than three
+/// are split into fans
+fn parse_ascii(bytes: &[u8]) -> Result<Vec<[[f32; 3]; 3]>> {
+    let text = std::str::from_utf8(bytes).context("STL file is not UTF-8")?;
+    let mut facets = Vec::new();
+    let mut corners: Vec<[f32; 3]> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let mut words = line.split_ascii_whitespace();
+        match words.next() {
+            Some("vertex") => corners.push(
+                parse_floats(&mut words).with_context(|| format!("Invalid line {}", number + 1))?,
+            ),
+            Some("endfacet") => {
+                if corners.len() < 3 {
+                    return Err(anyhow!(
+                        "Invalid line {}: a facet needs at least 3 vertices",
+                        number + 1
+                    ));
+                }
+                for i in 1..corners.len() - 1 {
+                    facets.push([corners[0], corners[i], corners[i + 1]]);
+                }
+                corners.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(facets)
+}
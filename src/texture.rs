@@ -0,0 +1,67 @@
+use anyhow::{Context, Result};
+
+/// Image multiplying the albedo of materials, looked up by the texture
+/// coordinates of mesh vertices
+#[derive(Clone, Debug, PartialEq)]
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    /// sRGB encoded colour in rows from the top, packed with red in the
+    /// lowest byte as WGSL `unpack4x8unorm` expects
+    pub texels: Vec<u32>,
+}
+
+/// Where a texture's texels start in the textures buffer, and its size
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TextureHeader {
+    offset: u32,
+    width: u32,
+    height: u32,
+    _pad: u32,
+}
+
+impl Texture {
+    /// Decode a PNG or JPEG image, or any other supported format
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let image = image::load_from_memory(bytes)
+            .context("Failed to decode texture")?
+            .into_rgba8();
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            texels: image
+                .pixels()
+                .map(|pixel| u32::from_le_bytes(pixel.0))
+                .collect(),
+        })
+    }
+
+    /// Contents of the textures buffer, a count padded to 16 bytes, where each
+    /// texture starts and its size, then the texels of every texture. Offsets
+    /// count u32s from the end of the count
+    pub fn buffer_bytes(textures: &[Texture]) -> Vec<u8> {
+        let mut headers = Vec::with_capacity(textures.len());
+        let mut offset = (textures.len() * 4) as u32;
+        for texture in textures {
+            headers.push(TextureHeader {
+                offset,
+                width: texture.width,
+                height: texture.height,
+                _pad: 0,
+            });
+            offset += texture.texels.len() as u32;
+        }
+
+        let mut bytes = bytemuck::bytes_of(&[textures.len() as u32, 0, 0, 0]).to_vec();
+        bytes.extend_from_slice(bytemuck::cast_slice(&headers));
+        for texture in textures {
+            bytes.extend_from_slice(bytemuck::cast_slice(&texture.texels));
+        }
+        // The shader's array of texels needs at least one
+        if textures.is_empty() {
+            bytes.extend_from_slice(&[0; 4]);
+        }
+        bytes
+    }
+}
@@ -1,6 +1,8 @@
 use winit::{
+    dpi::PhysicalSize,
     event::Event,
     event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
 };
 
 /// Wrapper around an eventloop and a window
@@ -18,13 +20,23 @@ impl Default for Window {
 impl Window {
     /// Create a new Window
     pub fn new() -> Self {
+        Self::from_builder(WindowBuilder::new())
+    }
+
+    /// Create a new Window with an initial inner size
+    pub fn with_size(width: u32, height: u32) -> Self {
+        Self::from_builder(WindowBuilder::new().with_inner_size(PhysicalSize::new(width, height)))
+    }
+
+    /// Create a new Window from a builder
+    fn from_builder(builder: WindowBuilder) -> Self {
         let event_loop = EventLoop::new();
 
-        let raw = winit::window::Window::new(&event_loop).expect("Failed to create Window");
+        let raw = builder.build(&event_loop).expect("Failed to create Window");
 
         #[cfg(target_arch = "wasm32")]
         {
-            use winit::{dpi::PhysicalSize, platform::web::WindowExtWebSys};
+            use winit::platform::web::WindowExtWebSys;
 
             web_sys::window()
                 .and_then(|win| win.document())
@@ -0,0 +1,180 @@
+//! The bounding volume hierarchy, whose traversal in cast_ray has to find the
+//! same closest hit as testing every primitive
+
+mod common;
+
+use cgmath::{InnerSpace, Vector3};
+use common::{add_random_primitives, random_point};
+use ray_tracer::{
+    bvh::Bvh, cpu, geometry::Geometry, primitive::Plane, shading::Shading, sphere::Sphere,
+};
+
+/// Every kind of primitive scattered through a cube of side 20
+fn random_geometry(state: &mut u32) -> Geometry {
+    let mut geometry = Geometry {
+        planes: vec![Plane::new([0.0, -12.0, 0.0], [0.0, 1.0, 0.0], 0)],
+        ..Default::default()
+    };
+    add_random_primitives(&mut geometry, state, 20, 20.0);
+    geometry.with_bvh()
+}
+
+#[test]
+fn traversal_matches_brute_force() {
+    let mut state = 1;
+    let shading = Shading::default();
+    for _ in 0..4 {
+        let geometry = random_geometry(&mut state);
+        let bindings = cpu::Bindings {
+            geometry: &geometry,
+            shading: &shading,
+        };
+        let mut hits = 0;
+        for _ in 0..2000 {
+            // Aimed into the middle, where the triangles are
+            let pos = random_point(&mut state, 30.0);
+            let target = random_point(&mut state, 10.0);
+            let ray = cpu::Ray {
+                pos,
+                dir: (target - pos).normalize(),
+            };
+            let expected = cpu::cast_ray_brute_force(&bindings, &ray);
+            let actual = cpu::cast_ray(&bindings, &ray);
+            assert_eq!(actual, expected, "{ray:?}");
+            hits += usize::from(expected.hit && expected.primitive != Geometry::PLANE);
+        }
+        // Enough rays hit something inside the hierarchy for this to mean much
+        assert!(hits > 400, "{hits}");
+    }
+}
+
+#[test]
+fn nodes_bound_everything_below_them() {
+    let geometry = random_geometry(&mut 7);
+    let bvh = &geometry.bvh;
+    let mut referenced = vec![0; bvh.primitives.len()];
+
+    // Walk from the root, every node should be reached exactly once
+    let mut visited = 0;
+    let mut stack = vec![(0, 1)];
+    while let Some((index, depth)) = stack.pop() {
+        visited += 1;
+        assert!(depth <= Bvh::MAX_DEPTH);
+        let node = bvh.nodes[index];
+        let bounds = node.bounds();
+        if node.count > 0 {
+            let start = node.start as usize;
+            for (i, &primitive) in bvh.primitives[start..start + node.count as usize]
+                .iter()
+                .enumerate()
+            {
+                referenced[start + i] += 1;
+                let primitive_bounds =
+                    geometry.primitive_bounds(primitive.kind, primitive.index as usize);
+                assert!(bounds.contains(&primitive_bounds));
+            }
+            continue;
+        }
+        for child in [index + 1, node.start as usize] {
+            assert!(bounds.contains(&bvh.nodes[child].bounds()), "{index}");
+            stack.push((child, depth + 1));
+        }
+    }
+    assert_eq!(visited, bvh.nodes.len());
+    assert!(referenced.iter().all(|&count| count == 1));
+
+    // Planes are left out, everything else is in exactly once
+    let mut primitives = bvh.primitives.clone();
+    primitives.sort_by_key(|p| (p.kind, p.index));
+    primitives.dedup();
+    let bounded = geometry.spheres.len()
+        + geometry.discs.len()
+        + geometry.boxes.len()
+        + geometry.quads.len()
+        + geometry.triangles.len();
+    assert_eq!(primitives.len(), bounded);
+    assert!(primitives.iter().all(|p| p.kind != Geometry::PLANE));
+}
+
+#[test]
+fn coincident_primitives_still_split() {
+    // Identical spheres can't be told apart by their centres
+    let geometry = Geometry {
+        spheres: vec![Sphere::new([0.0, 0.0, -5.0], 1.0, 0); 64],
+        ..Default::default()
+    }
+    .with_bvh();
+    let leaves = geometry.bvh.nodes.iter().filter(|node| node.count > 0);
+    assert!(leaves.clone().all(|node| node.count <= 4));
+    let count: u32 = leaves.map(|node| node.count).sum();
+    assert_eq!(count, 64);
+}
+
+/// Levels below and including the node
+fn depth(geometry: &Geometry, index: usize) -> usize {
+    let node = geometry.bvh.nodes[index];
+    if node.count > 0 {
+        return 1;
+    }
+    1 + depth(geometry, index + 1).max(depth(geometry, node.start as usize))
+}
+
+#[test]
+fn deepest_hierarchy_fits_the_traversal_stack() {
+    // Centres crowding towards the origin, so each split only peels off the
+    // furthest sphere and the hierarchy is as deep as it is allowed to be
+    let spheres = (0..100)
+        .map(|i| {
+            let x = 0.5f32.powi(i);
+            Sphere::new([x, 0.0, 0.0], x / 4.0, 0)
+        })
+        .collect();
+    let geometry = Geometry {
+        spheres,
+        ..Default::default()
+    }
+    .with_bvh();
+    assert_eq!(depth(&geometry, 0), Bvh::MAX_DEPTH);
+
+    // Starting at the crowded end every node is reached before anything is
+    // hit, leaving the further child at each level on the stack
+    let shading = Shading::default();
+    let bindings = cpu::Bindings {
+        geometry: &geometry,
+        shading: &shading,
+    };
+    let ray = cpu::Ray {
+        pos: [0.0; 3].into(),
+        dir: Vector3::unit_x(),
+    };
+    let expected = cpu::cast_ray_brute_force(&bindings, &ray);
+    assert!(expected.hit);
+    assert_eq!(cpu::cast_ray(&bindings, &ray), expected);
+}
+
+#[test]
+fn empty_hierarchy_hits_only_planes() {
+    let geometry = Geometry {
+        planes: vec![Plane::new([0.0, -1.0, 0.0], [0.0, 1.0, 0.0], 0)],
+        ..Default::default()
+    }
+    .with_bvh();
+    assert!(geometry.bvh.nodes.is_empty() && geometry.bvh.primitives.is_empty());
+
+    let shading = Shading::default();
+    let bindings = cpu::Bindings {
+        geometry: &geometry,
+        shading: &shading,
+    };
+    let down = cpu::Ray {
+        pos: [0.0; 3].into(),
+        dir: -Vector3::unit_y(),
+    };
+    let hit = cpu::cast_ray(&bindings, &down);
+    assert!(hit.hit && hit.primitive == Geometry::PLANE);
+    let up = cpu::Ray {
+        pos: [0.0; 3].into(),
+        dir: Vector3::unit_y(),
+    };
+    assert!(!cpu::cast_ray(&bindings, &up).hit);
+}
@@ -0,0 +1,84 @@
+//! Fixtures shared by the integration tests, each of which uses only some
+
+#![allow(dead_code)]
+
+use cgmath::{InnerSpace, Vector3};
+use ray_tracer::{
+    cpu,
+    geometry::Geometry,
+    primitive::{Cuboid, Disc, MeshVertex, Quad, Triangle},
+    sphere::Sphere,
+};
+
+pub fn ray(pos: [f32; 3], dir: [f32; 3]) -> cpu::Ray {
+    cpu::Ray {
+        pos: pos.into(),
+        dir: Vector3::from(dir).normalize(),
+    }
+}
+
+/// Point in a cube of the given side centred on the origin
+pub fn random_point(state: &mut u32, size: f32) -> Vector3<f32> {
+    Vector3::from([0; 3].map(|_| (cpu::rand(state) - 0.5) * size))
+}
+
+pub fn random_direction(state: &mut u32) -> Vector3<f32> {
+    loop {
+        let dir = random_point(state, 2.0);
+        if dir.magnitude2() > 0.01 {
+            return dir.normalize();
+        }
+    }
+}
+
+/// Add count of every kind of bounded primitive scattered through a cube of
+/// the given side, with triangles clumped like a mesh
+pub fn add_random_primitives(geometry: &mut Geometry, state: &mut u32, count: usize, size: f32) {
+    let scale = size / 20.0;
+    for _ in 0..count {
+        let radius = (0.2 + cpu::rand(state)) * scale;
+        let radius = if cpu::rand(state) < 0.2 {
+            -radius
+        } else {
+            radius
+        };
+        geometry
+            .spheres
+            .push(Sphere::new(random_point(state, size).into(), radius, 0));
+        geometry.discs.push(Disc::new(
+            random_point(state, size).into(),
+            random_direction(state).into(),
+            (0.2 + cpu::rand(state)) * scale,
+            0,
+        ));
+        let min = random_point(state, size);
+        let extent = random_point(state, 2.0 * scale).map(f32::abs);
+        geometry.boxes.push(Cuboid::new(
+            min.into(),
+            (min + extent).into(),
+            random_point(state, 360.0).into(),
+            0,
+        ));
+        geometry.quads.push(Quad::new(
+            random_point(state, size).into(),
+            random_point(state, 2.0 * scale).into(),
+            random_point(state, 2.0 * scale).into(),
+            0,
+        ));
+    }
+    for _ in 0..count / 2 {
+        let centre = random_point(state, 0.8 * size);
+        for _ in 0..20 {
+            let first = geometry.vertices.len() as u32;
+            for _ in 0..3 {
+                let pos = centre + random_point(state, 2.0 * scale);
+                geometry
+                    .vertices
+                    .push(MeshVertex::new(pos.into(), [0.0; 3]));
+            }
+            geometry
+                .triangles
+                .push(Triangle::new([first, first + 1, first + 2], 0));
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! The CPU twin of the shader, checked against hits and rays worked out by
+//! hand
+
+use cgmath::{vec2, ElementWise, InnerSpace, Vector3};
+use ray_tracer::{
+    camera::{Camera, Jitter},
+    cpu::{self, Ray, RayHit},
+    geometry::Geometry,
+    scene::Scene,
+    shading::Shading,
+    sky::Sky,
+    sphere::Sphere,
+};
+
+fn sphere(pos: [f32; 3], radius: f32) -> Sphere {
+    Sphere::new(pos, radius, 0)
+}
+
+/// Closest hit among the spheres, with nothing to shade them
+fn cast_ray(spheres: &[Sphere], ray: &Ray) -> RayHit {
+    let shading = Shading::new(vec![], vec![], Sky::Gradient([1.0; 3]));
+    let geometry = Geometry {
+        spheres: spheres.to_vec(),
+        ..Default::default()
+    }
+    .with_bvh();
+    let bindings = cpu::Bindings {
+        geometry: &geometry,
+        shading: &shading,
+    };
+    cpu::cast_ray(&bindings, ray)
+}
+
+fn assert_near(actual: Vector3<f32>, expected: [f32; 3]) {
+    let error = (actual - Vector3::from(expected)).magnitude();
+    assert!(error < 1e-5, "expected {expected:?} but got {actual:?}");
+}
+
+#[test]
+fn ray_hits_near_side_of_sphere() {
+    let ray = Ray {
+        pos: Vector3::new(0.0, 0.0, 0.0),
+        dir: Vector3::new(0.0, 0.0, -1.0),
+    };
+    let hit = cpu::hit_sphere(&sphere([0.0, 0.0, -5.0], 1.0), &ray);
+    assert!(hit.hit);
+    assert!((hit.distance - 4.0).abs() < 1e-5, "{hit:?}");
+    assert_near(hit.pos, [0.0, 0.0, -4.0]);
+    assert_near(hit.normal, [0.0, 0.0, 1.0]);
+    assert!(hit.front_face);
+
+    // From inside, the far side is hit with the normal still facing the ray
+    let hit = cpu::hit_sphere(&sphere([0.0, 0.0, -0.5], 1.0), &ray);
+    assert!((hit.distance - 1.5).abs() < 1e-5, "{hit:?}");
+    assert_near(hit.normal, [0.0, 0.0, 1.0]);
+    assert!(!hit.front_face);
+}
+
+#[test]
+fn ray_misses_spheres_beside_and_behind_it() {
+    let ray = Ray {
+        pos: Vector3::new(0.0, 0.0, 0.0),
+        dir: Vector3::new(0.0, 0.0, -1.0),
+    };
+    assert!(!cpu::hit_sphere(&sphere([2.0, 0.0, -5.0], 1.0), &ray).hit);
+    assert!(!cpu::hit_sphere(&sphere([0.0, 0.0, 5.0], 1.0), &ray).hit);
+    assert_eq!(cast_ray(&[], &ray), RayHit::miss());
+}
+
+#[test]
+fn closest_sphere_is_hit() {
+    let ray = Ray {
+        pos: Vector3::new(0.0, 0.0, 0.0),
+        dir: Vector3::new(0.0, 0.0, -1.0),
+    };
+    let spheres = [
+        Sphere::new([0.0, 0.0, -10.0], 1.0, 0),
+        Sphere::new([0.0, 0.0, -5.0], 1.0, 1),
+    ];
+    let hit = cast_ray(&spheres, &ray);
+    assert!((hit.distance - 4.0).abs() < 1e-5, "{hit:?}");
+    assert_eq!(hit.material, 1);
+}
+
+#[test]
+fn centre_ray_points_straight_ahead() {
+    let camera = Camera::with_dimensions([100.0, 50.0]);
+    let mut state = cpu::init_sample_state(&camera, [50, 25], 0);
+    // Pixel positions are offset by half a pixel, as in fs_main
+    let ray = cpu::calc_ray(&camera, vec2(49.5, 24.5), &mut state);
+    assert_near(ray.dir.normalize(), [0.0, 0.0, -1.0]);
+
+    // The top left corner is up and to the left, the viewport is twice as wide
+    // as it is high
+    let corner = cpu::calc_ray(&camera, vec2(-0.5, -0.5), &mut state);
+    let expected = Vector3::new(-2.0, 1.0, -1.0).normalize();
+    assert_near(corner.dir, expected.into());
+}
+
+#[test]
+fn cameras_without_a_basis_are_rejected() {
+    let errors = [
+        (
+            "pos: (1.0, 2.0, 3.0), target: (1.0, 2.0, 3.0)",
+            "camera.target",
+        ),
+        ("target: (0.0, 5.0, 0.0)", "camera.up"),
+        ("target: (0.0, -1.0, 0.0)", "camera.up"),
+        ("up: (0.0, 0.0, 0.0)", "camera.up"),
+    ];
+    for (camera, expected) in errors {
+        let source = format!("(materials: [], camera: ({camera}))");
+        let err = Scene::parse(source.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains(expected), "{err}");
+    }
+    let source = "(materials: [], camera: (target: (0.0, 1.0, -1.0)))";
+    assert!(Scene::parse(source.as_bytes()).is_ok());
+}
+
+#[test]
+fn lens_rays_meet_on_the_focus_plane() {
+    let mut camera = Camera::with_dimensions([100.0, 50.0]);
+    camera.aperture = 0.5;
+    camera.focus_dist = 4.0;
+    for i in 0..16 {
+        let mut state = cpu::init_sample_state(&camera, [50, 25], i);
+        let ray = cpu::calc_ray(&camera, vec2(49.5, 24.5), &mut state);
+        assert!(ray.pos.z.abs() < 1e-5 && ray.pos.x.hypot(ray.pos.y) <= 0.25);
+        // Every ray through the centre pixel crosses z = -4 on the axis
+        let t = -4.0 / ray.dir.z;
+        assert_near(ray.pos + t * ray.dir, [0.0, 0.0, -4.0]);
+    }
+}
+
+/// Sample counts which aren't squares still put one sample in every cell of
+/// the pixel, so no corner is left out
+#[test]
+fn stratified_jitter_fills_every_cell() {
+    const PIXELS: u32 = 256;
+    let mut camera = Camera::with_dimensions([1.0, 1.0]);
+    camera.jitter = Jitter::Stratified as u32;
+
+    for (samples, columns, rows) in [(8, 4, 2), (12, 4, 3), (32, 8, 4), (7, 7, 1), (16, 4, 4)] {
+        camera.samples = samples;
+        let cell_size = vec2(1.0 / columns as f32, 1.0 / rows as f32);
+        for pixel in 0..PIXELS {
+            for i in 0..samples {
+                let mut state = cpu::init_sample_state(&camera, [pixel, 0], i);
+                let offset = cpu::sample_offset(&camera, i, samples, &mut state);
+                // Sample i lands in cell i, counting along the rows
+                let corner = vec2((i % columns) as f32, (i / columns) as f32);
+                let within = offset + vec2(0.5, 0.5) - corner.mul_element_wise(cell_size);
+                assert!(
+                    within.x >= -1e-6
+                        && within.y >= -1e-6
+                        && within.x <= cell_size.x + 1e-6
+                        && within.y <= cell_size.y + 1e-6,
+                    "{samples} samples, sample {i} at {offset:?}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Environment map decoding, lookup and importance sampling, using the CPU
+//! twin of the shader
+
+use std::{
+    f32::consts::{PI, TAU},
+    io::Cursor,
+};
+
+use cgmath::{vec2, InnerSpace, Vector3, Zero};
+use image::{codecs::hdr::HdrEncoder, Rgb};
+use ray_tracer::{
+    camera::Camera,
+    cpu,
+    environment::{Environment, SkyDistribution},
+    geometry::Geometry,
+    material::Material,
+    renderer::Renderer,
+    shading::Shading,
+    sky::Sky,
+    sphere::Sphere,
+};
+
+/// Encode pixels as a Radiance `.hdr` file
+fn encode_hdr(width: usize, height: usize, pixels: &[[f32; 3]]) -> Vec<u8> {
+    let pixels: Vec<Rgb<f32>> = pixels.iter().map(|&pixel| Rgb(pixel)).collect();
+    let mut bytes = Vec::new();
+    HdrEncoder::new(Cursor::new(&mut bytes))
+        .encode(&pixels, width, height)
+        .unwrap();
+    bytes
+}
+
+/// 4x2 map with a different colour in every pixel, red encodes the column and
+/// green the row
+fn labelled(rotation: f32) -> Environment {
+    let pixels: Vec<[f32; 3]> = (0..2)
+        .flat_map(|y| (0..4).map(move |x| [x as f32 + 1.0, y as f32 + 1.0, 0.5]))
+        .collect();
+    Environment::from_bytes(&encode_hdr(4, 2, &pixels), 2.0, rotation).unwrap()
+}
+
+#[test]
+fn hdr_decodes_to_linear_radiance() {
+    let environment = labelled(0.0);
+    assert_eq!((environment.width, environment.height), (4, 2));
+    // RGBE keeps 8 bits of mantissa, exact for these values
+    assert_eq!(environment.pixels[6], [3.0, 2.0, 0.5, 1.0]);
+}
+
+/// Maps larger than the device's textures allow are averaged down to fit
+#[test]
+fn large_maps_are_downsampled() {
+    let environment = labelled(0.0);
+    assert_eq!(environment.downsampled(4), environment);
+
+    let small = environment.downsampled(3);
+    assert_eq!((small.width, small.height), (2, 1));
+    assert_eq!(small.pixels, [[1.5, 1.5, 0.5, 1.0], [3.5, 1.5, 0.5, 1.0]]);
+
+    // Odd sides repeat their last pixel
+    let single = environment.downsampled(1);
+    assert_eq!((single.width, single.height), (1, 1));
+    assert_eq!(single.pixels, [[2.5, 1.5, 0.5, 1.0]]);
+    let odd = Environment {
+        width: 3,
+        height: 1,
+        pixels: environment.pixels[..3].to_vec(),
+        ..environment
+    };
+    assert_eq!(
+        odd.downsampled(2).pixels,
+        [[1.5, 1.0, 0.5, 1.0], [3.0, 1.0, 0.5, 1.0]]
+    );
+}
+
+#[test]
+fn directions_map_to_equirectangular_pixels() {
+    let environment = labelled(0.0);
+    let cases = [
+        // -z is in the middle, just above and below the horizon
+        ([0.01, 0.1, -1.0], [3.0, 1.0]),
+        ([-0.01, -0.1, -1.0], [2.0, 2.0]),
+        // +x is a quarter turn to the right
+        ([1.0, 0.1, 0.01], [4.0, 1.0]),
+        // +z is at both edges
+        ([0.01, 0.1, 1.0], [4.0, 1.0]),
+        ([-0.01, 0.1, 1.0], [1.0, 1.0]),
+    ];
+    for (dir, [column, row]) in cases {
+        let dir = Vector3::from(dir).normalize();
+        let colour = cpu::environment_colour(&environment, dir) / 2.0;
+        assert_eq!(colour, Vector3::new(column, row, 0.5), "{dir:?}");
+    }
+}
+
+#[test]
+fn rotation_turns_map_around_y() {
+    let still = labelled(0.0);
+    let turned = labelled(std::f32::consts::FRAC_PI_2);
+
+    // A positive quarter turn, anticlockwise seen from above, brings what was
+    // at -z round to -x
+    let ahead = cpu::environment_colour(&still, Vector3::new(0.01, 0.1, -1.0).normalize());
+    let left = cpu::environment_colour(&turned, Vector3::new(-1.0, 0.1, -0.01).normalize());
+    assert_eq!(ahead, left);
+}
+
+/// A uniform environment is a furnace, a convex diffuse object in it reflects
+/// its albedo times the environment radiance. Sampling the environment and
+/// bouncing are weighted per sample, so only the average is exact
+#[test]
+fn uniform_environment_lights_like_furnace() {
+    let radiance = 1.5;
+    let intensity = 2.0;
+    let albedo = 0.6;
+    let bytes = encode_hdr(8, 4, &[[radiance; 3]; 32]);
+    let environment = Environment::from_bytes(&bytes, intensity, 0.3).unwrap();
+    let shading = Shading::new(
+        vec![Material::lambertian([albedo; 3])],
+        vec![],
+        Sky::Environment(environment),
+    );
+    let bindings = cpu::Bindings {
+        geometry: &Geometry {
+            spheres: vec![Sphere::new([0.0, 0.0, -3.0], 1.0, 0)],
+            ..Default::default()
+        }
+        .with_bvh(),
+        shading: &shading,
+    };
+
+    let mut camera = Camera::with_dimensions([16.0, 16.0]);
+    camera.vfov = 10.0;
+    camera.update_basis();
+    let mut accumulated = vec![Vector3::zero(); 16 * 16];
+    for frame in 0..16 {
+        camera.frame = frame;
+        cpu::render_frame(&camera, &bindings, &mut accumulated);
+    }
+
+    let expected = albedo * radiance * intensity;
+    let mean = accumulated.iter().sum::<Vector3<f32>>() / accumulated.len() as f32;
+    let error = (mean - Vector3::new(expected, expected, expected)).magnitude();
+    assert!(
+        error < 0.01 * expected,
+        "expected {expected} but got {mean:?}"
+    );
+}
+
+/// Black map with one bright pixel, a small sun in row 4 of 16
+fn sun(radiance: f32, rotation: f32) -> Environment {
+    let (width, height) = (32, 16);
+    let mut pixels = vec![[0.0, 0.0, 0.0, 1.0]; width * height];
+    pixels[4 * width + 10] = [radiance, radiance, radiance, 1.0];
+    Environment {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+        intensity: 1.0,
+        rotation,
+    }
+}
+
+fn sky_shading(environment: Environment) -> Shading {
+    Shading::new(vec![], vec![], Sky::Environment(environment))
+}
+
+#[test]
+fn sky_pdf_integrates_to_one() {
+    for shading in [
+        sky_shading(labelled(0.7)),
+        sky_shading(sun(100.0, 0.0)),
+        // The gradient sky is tabulated when there is no environment map
+        Shading::default(),
+    ] {
+        let bindings = cpu::Bindings {
+            geometry: &Geometry::default(),
+            shading: &shading,
+        };
+
+        // Midpoint rule over the sphere, fine enough to resolve every cell
+        let (columns, rows) = (512, 256);
+        let mut integral = 0.0;
+        for row in 0..rows {
+            let theta = PI * (row as f32 + 0.5) / rows as f32;
+            for column in 0..columns {
+                let phi = TAU * (column as f32 + 0.5) / columns as f32;
+                let dir = Vector3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let area = theta.sin() * PI * TAU / (rows * columns) as f32;
+                integral += cpu::sky_pdf(&bindings, dir) * area;
+            }
+        }
+        assert!((integral - 1.0).abs() < 0.01, "integral {integral}");
+    }
+}
+
+/// An 8K map is tabulated at a lower resolution, keeping the distribution
+/// buffer within the storage buffer size the renderer asks for, without
+/// losing the light of a single bright pixel
+#[test]
+fn large_maps_are_tabulated_within_buffer_limits() {
+    let (width, height) = (8192, 4096);
+    let sun = (4100, 1000);
+    let radiance = 1e6;
+    let uniform = SkyDistribution::tabulate_image(width, height, |_, _| [1.0; 3]);
+    let sunny = SkyDistribution::tabulate_image(width, height, |x, y| match (x, y) == sun {
+        true => [radiance; 3],
+        false => [1.0; 3],
+    });
+
+    assert_eq!((sunny.width, sunny.height), SkyDistribution::IMAGE_SIZE);
+    let limit = Renderer::limits().max_storage_buffer_binding_size as usize;
+    assert!(
+        sunny.bytes().len() <= limit,
+        "{} bytes",
+        sunny.bytes().len()
+    );
+
+    // Integral over uv of the luminance weighted by the sine of the angle
+    // from the zenith, at the centre of each cell
+    assert!((uniform.total - 2.0 / PI).abs() < 1e-4, "{}", uniform.total);
+    let row = sun.1 * sunny.height / height;
+    let theta = PI * (row as f32 + 0.5) / sunny.height as f32;
+    let expected = (radiance - 1.0) * theta.sin() / (width * height) as f32;
+    let extra = sunny.total - uniform.total;
+    assert!(
+        (extra - expected).abs() < 1e-3 * expected,
+        "{extra} {expected}"
+    );
+}
+
+#[test]
+fn sampled_pdf_matches_lookup() {
+    let shading = sky_shading(labelled(1.2));
+    let bindings = cpu::Bindings {
+        geometry: &Geometry::default(),
+        shading: &shading,
+    };
+
+    for i in 0..64 {
+        let u = vec2((i % 8) as f32 + 0.3, (i / 8) as f32 + 0.6) / 8.0;
+        let sample = cpu::sample_sky(&bindings, u);
+        assert!((sample.dir.magnitude() - 1.0).abs() < 1e-4);
+        let pdf = cpu::sky_pdf(&bindings, sample.dir);
+        assert!(
+            (sample.pdf - pdf).abs() < 1e-3 * pdf,
+            "{u:?} sampled {} but looked up {pdf}",
+            sample.pdf
+        );
+    }
+}
+
+#[test]
+fn black_sky_is_never_sampled() {
+    let shading = Shading::new(vec![], vec![], Sky::Gradient([0.0; 3]));
+    let bindings = cpu::Bindings {
+        geometry: &Geometry::default(),
+        shading: &shading,
+    };
+    assert_eq!(shading.sky_distribution.total, 0.0);
+    assert_eq!(cpu::sample_sky(&bindings, vec2(0.5, 0.5)).pdf, 0.0);
+    assert_eq!(cpu::sky_pdf(&bindings, Vector3::unit_y()), 0.0);
+}
+
+/// Bouncing alone would find the sun once in hundreds of samples, the cell's
+/// irradiance on a surface facing up is L * integral of cos over the cell
+#[test]
+fn small_sun_converges_with_few_samples() {
+    let (radiance, albedo) = (500.0, 0.5);
+    let shading = Shading::new(
+        vec![Material::lambertian([albedo; 3])],
+        vec![],
+        Sky::Environment(sun(radiance, 0.4)),
+    );
+    let bindings = cpu::Bindings {
+        geometry: &Geometry {
+            spheres: vec![Sphere::new([0.0, -1000.0, 0.0], 1000.0, 0)],
+            ..Default::default()
+        }
+        .with_bvh(),
+        shading: &shading,
+    };
+
+    let mut camera = Camera::with_dimensions([1.0, 1.0]);
+    camera.max_depth = 2;
+    camera.samples = 64;
+    let ray = cpu::Ray {
+        pos: Vector3::new(0.0, 0.5, 0.0),
+        dir: -Vector3::unit_y(),
+    };
+    let mut total = Vector3::zero();
+    for sample in 0..camera.samples {
+        let mut state = cpu::init_sample_state(&camera, [0, 0], sample);
+        total += cpu::iterative_ray_colour(&camera, &bindings, &ray, &mut state);
+    }
+    let colour = total / camera.samples as f32;
+
+    let (theta0, theta1) = (4.0 * PI / 16.0, 5.0 * PI / 16.0);
+    let irradiance = radiance * TAU / 32.0 * (theta1.sin().powi(2) - theta0.sin().powi(2)) / 2.0;
+    let expected = albedo / PI * irradiance;
+    let error = (colour - Vector3::new(expected, expected, expected)).magnitude();
+    assert!(
+        error < 0.03 * expected,
+        "expected {expected} but got {colour:?}"
+    );
+}
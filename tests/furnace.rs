@@ -0,0 +1,124 @@
+//! Furnace tests, scenes where the radiance reaching the camera is known
+//! exactly, rendered with the CPU twin of the shader
+
+use cgmath::{InnerSpace, Vector3, Zero};
+use ray_tracer::{
+    camera::Camera, cpu, geometry::Geometry, material::Material, shading::Shading, sphere::Sphere,
+};
+
+const SIZE: [f32; 2] = [16.0, 16.0];
+const FRAMES: u32 = 8;
+
+/// Render a few frames and return the linear running average
+fn render(camera: &Camera, spheres: &[Sphere], materials: &[Material]) -> Vec<Vector3<f32>> {
+    let shading = Shading {
+        materials: materials.to_vec(),
+        ..Default::default()
+    };
+    let geometry = Geometry {
+        spheres: spheres.to_vec(),
+        ..Default::default()
+    }
+    .with_bvh();
+    let bindings = cpu::Bindings {
+        geometry: &geometry,
+        shading: &shading,
+    };
+
+    let mut camera = *camera;
+    camera.update_basis();
+
+    let mut accumulated = vec![Vector3::zero(); (SIZE[0] * SIZE[1]) as usize];
+    for frame in 0..FRAMES {
+        camera.frame = frame;
+        cpu::render_frame(&camera, &bindings, &mut accumulated);
+    }
+    accumulated
+}
+
+fn assert_close(pixel: Vector3<f32>, expected: f32) {
+    for c in [pixel.x, pixel.y, pixel.z] {
+        assert!(
+            (c - expected).abs() < 1e-3,
+            "expected {expected} but got {pixel:?}"
+        );
+    }
+}
+
+/// Inside a closed sphere every bounce hits the wall again, so with albedo a
+/// and emission e the radiance after n bounces is e * (1 + a + ... + a^(n-1))
+#[test]
+fn closed_furnace_converges_to_geometric_series() {
+    let albedo = 0.5;
+    let mut wall = Material::lambertian([albedo; 3]);
+    wall.emission = [1.0; 3];
+
+    let mut camera = Camera::with_dimensions(SIZE);
+    camera.max_depth = 32;
+
+    let expected = (1.0 - albedo.powi(camera.max_depth)) / (1.0 - albedo);
+    for pixel in render(&camera, &[Sphere::new([0.0; 3], 10.0, 0)], &[wall]) {
+        assert_close(pixel, expected);
+    }
+}
+
+/// A convex diffuse object never sees itself, so surrounded by a uniform
+/// emitter it reflects exactly its albedo times the emission
+#[test]
+fn convex_object_in_furnace_reflects_albedo() {
+    let albedo = 0.7;
+    let emission = 2.0;
+    let materials = [
+        Material::emissive([emission; 3]),
+        Material::lambertian([albedo; 3]),
+    ];
+    let spheres = [
+        Sphere::new([0.0; 3], 100.0, 0),
+        Sphere::new([0.0, 0.0, -3.0], 1.0, 1),
+    ];
+
+    let mut camera = Camera::with_dimensions(SIZE);
+    camera.vfov = 10.0;
+    camera.max_depth = 8;
+
+    // The narrow view is filled by the object
+    for pixel in render(&camera, &spheres, &materials) {
+        assert_close(pixel, albedo * emission);
+    }
+}
+
+/// Light escaping to the sky is weighted by the throughput of the last bounce
+/// and looked up in the direction it left in, not the camera ray's direction
+#[test]
+fn mirror_reflects_sky_in_bounced_direction() {
+    let shading = Shading {
+        materials: vec![Material::metal([0.5; 3], 0.0)],
+        ..Default::default()
+    };
+    let bindings = cpu::Bindings {
+        geometry: &Geometry {
+            spheres: vec![Sphere::new([0.0, -3.0, 0.0], 1.0, 0)],
+            ..Default::default()
+        }
+        .with_bvh(),
+        shading: &shading,
+    };
+    let camera = Camera::with_dimensions(SIZE);
+
+    // Looking straight down onto the top of the mirror, which reflects straight up
+    let ray = cpu::Ray {
+        pos: Vector3::zero(),
+        dir: -Vector3::unit_y(),
+    };
+    let mut state = cpu::init_sample_state(&camera, [0, 0], 0);
+    let colour = cpu::iterative_ray_colour(&camera, &bindings, &ray, &mut state);
+
+    let up = cpu::sky_colour(
+        &bindings,
+        &cpu::Ray {
+            pos: Vector3::zero(),
+            dir: Vector3::unit_y(),
+        },
+    );
+    assert!((colour - 0.5 * up).magnitude() < 1e-4, "{colour:?}");
+}
@@ -0,0 +1,273 @@
+//! glTF 2.0 import of meshes, node transforms, materials, base colour textures
+//! and cameras, and their placement in scenes
+
+mod common;
+
+use base64::Engine;
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector2, Vector3, Vector4};
+use common::ray;
+use ray_tracer::{
+    cpu, geometry::Geometry, gltf_scene::GltfScene, material::Material, mesh::Mesh, scene::Scene,
+    texture::Texture,
+};
+
+fn transform_point(transform: Matrix4<f32>, point: [f32; 3]) -> Vector3<f32> {
+    (transform * Vector3::from(point).extend(1.0)).truncate()
+}
+
+#[test]
+fn glb_meshes_materials_and_textures_are_loaded() {
+    let room = pollster::block_on(GltfScene::load("scenes/models/room.glb")).unwrap();
+    let [floor, cube, lamp, plinth] = &room.meshes[..] else {
+        panic!("{} meshes", room.meshes.len());
+    };
+    assert_eq!((floor.vertices.len(), floor.triangles.len()), (4, 2));
+    assert_eq!((cube.vertices.len(), cube.triangles.len()), (24, 12));
+    assert_eq!(lamp.triangles.len(), 2);
+    // Triangles index the file's materials, the last being the default
+    assert!(floor.triangles.iter().all(|t| t.material == 0));
+    assert!(cube.triangles.iter().all(|t| t.material == 1));
+    assert!(lamp.triangles.iter().all(|t| t.material == 2));
+    assert!(plinth.triangles.iter().all(|t| t.material == 3));
+    assert!(room.meshes.iter().all(|mesh| mesh.materials.is_empty()));
+
+    // The floor's texture repeats four times across it
+    let uvs: Vec<_> = floor.vertices.iter().map(|v| [v.u, v.v]).collect();
+    assert_eq!(uvs, [[0.0, 4.0], [4.0, 4.0], [4.0, 0.0], [0.0, 0.0]]);
+    assert_eq!(floor.vertices[0].normal, [0.0, 1.0, 0.0]);
+
+    let [checker, brass, light, default] = room.materials[..] else {
+        panic!("{} materials", room.materials.len());
+    };
+    assert_eq!(checker, Material::lambertian([1.0; 3]).with_texture(0));
+    assert_eq!(brass, Material::metal([0.9, 0.7, 0.4], 0.2));
+    assert_eq!(light, Material::emissive([4.0, 3.6, 3.2]));
+    assert_eq!(default, Material::lambertian(Mesh::DEFAULT_ALBEDO));
+
+    let [texture] = &room.textures[..] else {
+        panic!("{} textures", room.textures.len());
+    };
+    assert_eq!((texture.width, texture.height), (4, 4));
+    assert_eq!(texture.texels[0].to_le_bytes(), [230, 200, 150, 255]);
+    assert_eq!(texture.texels[1].to_le_bytes(), [60, 90, 140, 255]);
+}
+
+#[test]
+fn glb_nodes_accumulate_their_parents_transforms() {
+    let room = pollster::block_on(GltfScene::load("scenes/models/room.glb")).unwrap();
+    let mut meshes: Vec<_> = room.nodes.iter().map(|node| node.mesh).collect();
+    meshes.sort();
+    assert_eq!(meshes, [0, 1, 1, 2, 3]);
+
+    // The right cube is turned and halved inside the cubes node, which is
+    // raised inside the root, which turns everything about y
+    let root = Matrix4::from_angle_y(cgmath::Deg(20.0));
+    let expected = root * Vector4::new(0.8, 0.5, 0.0, 1.0);
+    let right = room
+        .nodes
+        .iter()
+        .find(|node| node.mesh == 1 && transform_point(node.transform, [0.0; 3]).x > 0.0)
+        .unwrap();
+    let centre = transform_point(right.transform, [0.0; 3]);
+    assert!(
+        (centre - expected.truncate()).magnitude() < 1e-5,
+        "{centre:?}"
+    );
+    let corner = transform_point(right.transform, [1.0, 1.0, 1.0]);
+    assert!((corner.y - 1.0).abs() < 1e-5, "{corner:?}");
+    let reach = Vector2::new(corner.x - centre.x, corner.z - centre.z).magnitude();
+    assert!((reach - 0.5 * 2f32.sqrt()).abs() < 1e-5, "{corner:?}");
+
+    // The camera is outside the root, looking down -z tilted down
+    let camera = room.camera.unwrap();
+    assert!((camera.vfov - 40.0).abs() < 1e-4);
+    let pos = transform_point(camera.transform, [0.0; 3]);
+    assert!((pos - Vector3::new(0.0, 1.5, 4.0)).magnitude() < 1e-5);
+    let forward = (camera.transform * -Vector4::unit_z()).truncate();
+    assert!(forward.y < 0.0 && forward.z < 0.0, "{forward:?}");
+}
+
+#[test]
+fn gltf_json_with_data_uris_is_loaded() {
+    let positions: [[f32; 3]; 4] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0],
+    ];
+    let data = base64::engine::general_purpose::STANDARD.encode(bytemuck::cast_slice(&positions));
+    // A strip of two triangles without indices, normals or a material, placed
+    // by a node's matrix
+    let json = format!(
+        r#"{{
+            "asset": {{"version": "2.0"}},
+            "scenes": [{{"nodes": [0]}}],
+            "nodes": [{{"mesh": 0, "matrix": [1,0,0,0, 0,1,0,0, 0,0,1,0, 0,0,-2,1]}}],
+            "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "mode": 5}}]}}],
+            "accessors": [{{
+                "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]
+            }}],
+            "bufferViews": [{{"buffer": 0, "byteLength": 48}}],
+            "buffers": [{{"byteLength": 48, "uri": "data:application/octet-stream;base64,{data}"}}]
+        }}"#
+    );
+    let scene = pollster::block_on(GltfScene::from_bytes(json.as_bytes(), "a.gltf")).unwrap();
+
+    assert_eq!(
+        scene.materials,
+        [Material::lambertian(Mesh::DEFAULT_ALBEDO)]
+    );
+    assert!(scene.textures.is_empty() && scene.camera.is_none());
+    let mesh = &scene.meshes[0];
+    assert_eq!(mesh.vertices[3].pos, [1.0, 1.0, 0.0]);
+    assert_eq!(mesh.vertices[3].normal, [0.0; 3]);
+    // Both triangles of the strip face the same way
+    let corners: Vec<_> = mesh.triangles.iter().map(|t| t.vertices).collect();
+    assert_eq!(corners, [[0, 1, 2], [2, 1, 3]]);
+    assert!(mesh.triangles.iter().all(|t| t.material == 0));
+
+    let [node] = scene.nodes[..] else {
+        panic!("{} nodes", scene.nodes.len());
+    };
+    assert_eq!(node.mesh, 0);
+    assert_eq!(
+        transform_point(node.transform, [1.0, 1.0, 0.0]),
+        Vector3::new(1.0, 1.0, -2.0)
+    );
+
+    let err = pollster::block_on(GltfScene::from_bytes(b"{}", "a.gltf")).unwrap_err();
+    assert!(!err.to_string().is_empty());
+}
+
+/// Nodes scaled to zero are how many tools hide them, they are left out
+/// rather than failing to invert
+#[test]
+fn zero_scale_nodes_are_left_out() {
+    let positions: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+    let data = base64::engine::general_purpose::STANDARD.encode(bytemuck::cast_slice(&positions));
+    let json = format!(
+        r#"{{
+            "asset": {{"version": "2.0"}},
+            "scenes": [{{"nodes": [0, 1]}}],
+            "nodes": [{{"mesh": 0}}, {{"mesh": 0, "scale": [0, 0, 0]}}],
+            "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}}}]}}],
+            "accessors": [{{
+                "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]
+            }}],
+            "bufferViews": [{{"buffer": 0, "byteLength": 36}}],
+            "buffers": [{{"byteLength": 36, "uri": "data:application/octet-stream;base64,{data}"}}]
+        }}"#
+    );
+    let gltf = pollster::block_on(GltfScene::from_bytes(json.as_bytes(), "a.gltf")).unwrap();
+    assert_eq!(gltf.nodes.len(), 2);
+
+    let mut scene = Scene::parse(br#"(materials: [], gltf: [(path: "a.gltf")])"#).unwrap();
+    scene.gltf_data = vec![gltf];
+    let geometry = scene.geometry();
+    let [instance] = geometry.instances[..] else {
+        panic!("{} instances", geometry.instances.len());
+    };
+    assert_eq!(Matrix4::from(instance.transform), Matrix4::identity());
+}
+
+#[test]
+fn textures_repeat_and_are_decoded_from_srgb() {
+    let texture = Texture {
+        width: 2,
+        height: 1,
+        texels: vec![
+            u32::from_le_bytes([255, 0, 0, 255]),
+            u32::from_le_bytes([0, 188, 255, 255]),
+        ],
+    };
+    let left = cpu::texture_colour(&texture, Vector2::new(0.25, 0.5));
+    assert_eq!(left, Vector3::new(1.0, 0.0, 0.0));
+    let right = cpu::texture_colour(&texture, Vector2::new(-0.25, 3.5));
+    assert!(
+        (right - Vector3::new(0.0, 0.5, 1.0)).magnitude() < 0.01,
+        "{right:?}"
+    );
+
+    // Each texture's texels follow the headers of every texture
+    let bytes = Texture::buffer_bytes(&[texture.clone(), texture]);
+    let words: &[u32] = bytemuck::cast_slice(&bytes);
+    assert_eq!(words[..4], [2, 0, 0, 0]);
+    assert_eq!(words[4..7], [8, 2, 1]);
+    assert_eq!(words[8..11], [10, 2, 1]);
+    assert_eq!(words.len(), 4 + 8 + 4);
+    assert_eq!(Texture::buffer_bytes(&[]).len(), 20);
+}
+
+#[test]
+fn scene_gltf_files_are_placed_as_instances() {
+    let scene = pollster::block_on(Scene::load("scenes/gltf.ron")).unwrap();
+    let room = &scene.gltf_data[0];
+
+    // The file's camera replaces the scene's placement but not its settings
+    assert_eq!(scene.camera.pos, [0.0, 1.5, 4.0]);
+    assert!((scene.camera.vfov - 40.0).abs() < 1e-4);
+    assert_eq!(scene.camera.samples, 4);
+
+    let materials = scene.gpu_materials();
+    assert_eq!(
+        materials.len(),
+        scene.materials.len() + room.materials.len()
+    );
+    assert_eq!(materials[1].texture, 0);
+    assert_eq!(materials[2].kind, Material::METAL);
+
+    let geometry = scene.geometry();
+    assert_eq!(geometry.models.len(), room.meshes.len());
+    assert_eq!(geometry.instances.len(), room.nodes.len());
+    let triangles: usize = room.meshes.iter().map(|mesh| mesh.triangles.len()).sum();
+    assert_eq!(geometry.triangles.len(), triangles);
+    assert_eq!(
+        geometry.models[1].ranges[Geometry::TRIANGLE as usize],
+        2..14
+    );
+
+    // Straight down onto the floor between the glass sphere and the cubes,
+    // whose checker texture changes its albedo
+    let shading = scene.shading();
+    let bindings = cpu::Bindings {
+        geometry: &geometry,
+        shading: &shading,
+    };
+    let hit = cpu::cast_ray(&bindings, &ray([0.0, 1.0, 0.5], [0.0, -1.0, 0.0]));
+    assert!(hit.hit && hit.front_face, "{hit:?}");
+    assert_eq!(hit.material, 1);
+    assert!((hit.distance - 1.0).abs() < 1e-4, "{hit:?}");
+    let material = cpu::hit_material(&bindings, &hit);
+    let colours =
+        [[230, 200, 150], [60, 90, 140]].map(|c| c.map(|c| cpu::srgb_to_linear(c as f32 / 255.0)));
+    assert!(colours.contains(&material.albedo), "{material:?}");
+}
+
+#[test]
+fn scene_gltf_files_are_checked() {
+    let errors = [
+        (
+            r#"gltf: [(path: "a.glb", material: 1)]"#,
+            "gltf[0].material",
+        ),
+        (r#"gltf: [(path: "a.glb", scale: 0.0)]"#, "gltf[0].scale"),
+        (r#"gltf: [(pos: (0.0, 0.0, 0.0))]"#, "gltf[0]"),
+    ];
+    for (field, expected) in errors {
+        let source = format!("(materials: [Lambertian(albedo: (0.5, 0.5, 0.5))], {field})");
+        let err = Scene::parse(source.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains(expected), "{err}");
+    }
+
+    // A glTF file can be loaded as a scene by itself, seen through its camera
+    let scene = pollster::block_on(Scene::load("scenes/models/room.glb")).unwrap();
+    assert!(scene.materials.is_empty());
+    assert_eq!(scene.gpu_materials().len(), 4);
+    assert_eq!(scene.camera.pos, [0.0, 1.5, 4.0]);
+    assert_eq!(scene.shading().textures.len(), 1);
+
+    let err = pollster::block_on(Scene::load("scenes/models/missing.glb")).unwrap_err();
+    assert!(format!("{err:#}").contains("missing.glb"), "{err:#}");
+}
@@ -0,0 +1,260 @@
+//! Models placed by instances, whose hierarchies are walked with rays moved
+//! into each instance's space
+
+mod common;
+
+use cgmath::{InnerSpace, Matrix4, Vector3};
+use common::{add_random_primitives, random_point, ray};
+use ray_tracer::{
+    cpu,
+    geometry::{Geometry, Model},
+    instance::Instance,
+    primitive::Plane,
+    scene::Scene,
+    shading::Shading,
+    sphere::Sphere,
+};
+
+/// A sphere at the origin of radius one as the only model
+fn unit_sphere_model() -> Geometry {
+    Geometry {
+        spheres: vec![Sphere::new([0.0; 3], 1.0, 1)],
+        models: vec![Model {
+            ranges: [0..1, 0..0, 0..0, 0..0, 0..0, 0..0],
+        }],
+        ..Default::default()
+    }
+}
+
+/// World primitives, then two models of every kind of bounded primitive
+/// each placed many times with random turns and uneven scales
+fn random_geometry(state: &mut u32) -> Geometry {
+    let mut geometry = Geometry {
+        planes: vec![Plane::new([0.0, -12.0, 0.0], [0.0, 1.0, 0.0], 0)],
+        ..Default::default()
+    };
+    add_random_primitives(&mut geometry, state, 10, 20.0);
+    for _ in 0..2 {
+        let start: [usize; Geometry::PRIMITIVE_KINDS] =
+            std::array::from_fn(|kind| geometry.count(kind as u32));
+        add_random_primitives(&mut geometry, state, 20, 4.0);
+        geometry.models.push(Model {
+            ranges: std::array::from_fn(|kind| start[kind]..geometry.count(kind as u32)),
+        });
+    }
+    for i in 0..20 {
+        let rotation = random_point(state, 360.0);
+        let scale = random_point(state, 1.0).map(|x| x.abs() + 0.5);
+        let transform = Matrix4::from_translation(random_point(state, 16.0))
+            * Matrix4::from_angle_x(cgmath::Deg(rotation.x))
+            * Matrix4::from_angle_y(cgmath::Deg(rotation.y))
+            * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z);
+        let material = (i % 3 == 0).then_some(2);
+        geometry
+            .instances
+            .push(Instance::new(i % 2, transform, material).unwrap());
+    }
+    geometry.with_bvh()
+}
+
+#[test]
+fn traversal_matches_brute_force() {
+    let mut state = 3;
+    let shading = Shading::default();
+    for _ in 0..4 {
+        let geometry = random_geometry(&mut state);
+        let bindings = cpu::Bindings {
+            geometry: &geometry,
+            shading: &shading,
+        };
+        let mut instance_hits = 0;
+        for _ in 0..2000 {
+            let pos = random_point(&mut state, 30.0);
+            let target = random_point(&mut state, 10.0);
+            let ray = cpu::Ray {
+                pos,
+                dir: (target - pos).normalize(),
+            };
+            let expected = cpu::cast_ray_brute_force(&bindings, &ray);
+            let actual = cpu::cast_ray(&bindings, &ray);
+            assert_eq!(actual, expected, "{ray:?}");
+            instance_hits += usize::from(expected.hit && expected.instance != cpu::NO_INSTANCE);
+        }
+        // Enough rays reach into the instances for this to mean much
+        assert!(instance_hits > 150, "{instance_hits}");
+    }
+}
+
+#[test]
+fn models_are_shared_by_their_instances() {
+    let geometry = random_geometry(&mut 5);
+    let bvh = &geometry.bvh;
+    assert_eq!(bvh.roots.len(), 2);
+    assert!(bvh.roots.iter().all(|&root| root > 0));
+    for instance in &geometry.instances {
+        assert_eq!(instance.root, bvh.roots[instance.model as usize]);
+    }
+
+    // Each model's primitives are in its own hierarchy once, and the top
+    // level has the world's primitives and the instances
+    let bounded = geometry.spheres.len()
+        + geometry.discs.len()
+        + geometry.boxes.len()
+        + geometry.quads.len()
+        + geometry.triangles.len();
+    let instances = bvh
+        .primitives
+        .iter()
+        .filter(|p| p.kind == Geometry::INSTANCE)
+        .count();
+    assert_eq!(instances, geometry.instances.len());
+    assert_eq!(bvh.primitives.len(), bounded + instances);
+    assert_eq!(geometry.world_count(Geometry::SPHERE), 10);
+    assert_eq!(geometry.world_count(Geometry::PLANE), 1);
+}
+
+#[test]
+fn hits_are_moved_back_into_the_world() {
+    let mut geometry = unit_sphere_model();
+    // Stretched to twice as wide and moved away
+    let transform = Matrix4::from_translation(Vector3::new(0.0, 0.0, -5.0))
+        * Matrix4::from_nonuniform_scale(2.0, 1.0, 1.0);
+    geometry.instances = vec![Instance::new(0, transform, None).unwrap()];
+    let geometry = geometry.with_bvh();
+    let shading = Shading::default();
+    let bindings = cpu::Bindings {
+        geometry: &geometry,
+        shading: &shading,
+    };
+
+    let hit = cpu::cast_ray(&bindings, &ray([5.0, 0.0, -5.0], [-1.0, 0.0, 0.0]));
+    assert!(hit.hit && hit.front_face);
+    assert_eq!(
+        (hit.primitive, hit.index, hit.instance),
+        (Geometry::SPHERE, 0, 0)
+    );
+    assert_eq!(hit.material, 1);
+    assert!((hit.distance - 3.0).abs() < 1e-4, "{hit:?}");
+    assert!(
+        (hit.normal - Vector3::unit_x()).magnitude() < 1e-4,
+        "{hit:?}"
+    );
+
+    // On the slope of the ellipsoid x^2 / 4 + y^2 + z^2 = 1 the normal follows
+    // its gradient rather than the stretched sphere's normal
+    let hit = cpu::cast_ray(&bindings, &ray([1.0, 5.0, -5.0], [0.0, -1.0, 0.0]));
+    let y = 0.75f32.sqrt();
+    assert!(
+        (hit.pos - Vector3::new(1.0, y, -5.0)).magnitude() < 1e-4,
+        "{hit:?}"
+    );
+    assert!((hit.distance - (5.0 - y)).abs() < 1e-4, "{hit:?}");
+    let gradient = Vector3::new(0.25, y, 0.0).normalize();
+    assert!((hit.normal - gradient).magnitude() < 1e-4, "{hit:?}");
+
+    // Either side of the stretched edge, well beyond that of the sphere
+    assert!(cpu::cast_ray(&bindings, &ray([1.9, 0.0, 0.0], [0.0, 0.0, -1.0])).hit);
+    assert!(!cpu::cast_ray(&bindings, &ray([2.1, 0.0, 0.0], [0.0, 0.0, -1.0])).hit);
+}
+
+#[test]
+fn instance_material_overrides_the_model() {
+    let mut geometry = unit_sphere_model();
+    geometry.instances = vec![
+        Instance::new(
+            0,
+            Matrix4::from_translation(Vector3::new(-2.0, 0.0, -5.0)),
+            None,
+        )
+        .unwrap(),
+        Instance::new(
+            0,
+            Matrix4::from_translation(Vector3::new(2.0, 0.0, -5.0)),
+            Some(3),
+        )
+        .unwrap(),
+    ];
+    let geometry = geometry.with_bvh();
+    let shading = Shading::default();
+    let bindings = cpu::Bindings {
+        geometry: &geometry,
+        shading: &shading,
+    };
+
+    let left = cpu::cast_ray(&bindings, &ray([-2.0, 0.0, 0.0], [0.0, 0.0, -1.0]));
+    assert_eq!((left.instance, left.material), (0, 1));
+    let right = cpu::cast_ray(&bindings, &ray([2.0, 0.0, 0.0], [0.0, 0.0, -1.0]));
+    assert_eq!((right.instance, right.material), (1, 3));
+    // Only the instances are in the world, not the model itself
+    assert!(!cpu::cast_ray(&bindings, &ray([0.0; 3], [0.0, 0.0, -1.0])).hit);
+}
+
+#[test]
+fn scene_instances_are_parsed_and_checked() {
+    let scene = Scene::parse(
+        b"(
+            materials: [Lambertian(albedo: (0.5, 0.5, 0.5)), Emissive(emission: (1.0, 1.0, 1.0))],
+            spheres: [(pos: (0.0, 0.0, 0.0), radius: 1.0, material: 1)],
+            models: [
+                (spheres: [(pos: (0.0, 1.0, 0.0), radius: 0.5, material: 1)]),
+                (
+                    spheres: [(pos: (0.0, 1.0, 0.0), radius: 0.5, material: 0)],
+                    boxes: [(min: (0.0, 0.0, 0.0), max: (1.0, 1.0, 1.0), material: 0)],
+                ),
+            ],
+            instances: [
+                (model: 1, pos: (3.0, 0.0, 0.0), scale: (2.0, 1.0, 1.0), material: 1),
+                (model: 1, rotation: (0.0, 90.0, 0.0)),
+            ],
+        )",
+    )
+    .unwrap();
+    let geometry = scene.geometry();
+    assert_eq!(geometry.spheres.len(), 3);
+    assert_eq!(geometry.models[0].ranges[Geometry::SPHERE as usize], 1..2);
+    assert_eq!(geometry.models[1].ranges[Geometry::SPHERE as usize], 2..3);
+    assert_eq!(geometry.models[1].ranges[Geometry::BOX as usize], 0..1);
+    // Only the sphere placed directly in the world is sampled as a light
+    assert_eq!(geometry.world_count(Geometry::SPHERE), 1);
+    assert_eq!(
+        &geometry.buffer_bytes()[0][..8],
+        bytemuck::cast_slice::<u32, u8>(&[3, 1])
+    );
+
+    let instance = geometry.instances[0];
+    assert_eq!((instance.model, instance.material), (1, 1));
+    let corner = Matrix4::from(instance.transform) * Vector3::new(1.0, 1.0, 1.0).extend(1.0);
+    assert_eq!(corner.truncate(), Vector3::new(5.0, 1.0, 1.0));
+    assert_eq!(geometry.instances[1].material, Instance::NO_MATERIAL);
+
+    let errors = [
+        ("instances: [(model: 0)]", "instances[0].model"),
+        (
+            "models: [()], instances: [(model: 0, material: 1)]",
+            "instances[0].material",
+        ),
+        (
+            "models: [()], instances: [(model: 0, scale: (1.0, 0.0, 1.0))]",
+            "instances[0]`: transform can't be inverted",
+        ),
+        // Too small to invert in single precision without being zero
+        (
+            "models: [()], instances: [(model: 0), (model: 0, scale: (1e-30, 1e-30, 1e-30))]",
+            "instances[1]`: transform can't be inverted",
+        ),
+        (
+            "models: [(spheres: [(pos: (0.0, 0.0, 0.0), radius: 1.0, material: 2)])]",
+            "models[0].spheres[0].material",
+        ),
+        (
+            "models: [(), (discs: [(pos: (0.0, 0.0, 0.0), normal: (0.0, 1.0, 0.0), radius: 0.0, material: 0)])]",
+            "models[1].discs[0].radius",
+        ),
+        ("models: [(planes: [])]", "planes"),
+    ];
+    for (field, expected) in errors {
+        let source = format!("(materials: [Lambertian(albedo: (0.5, 0.5, 0.5))], {field})");
+        let err = Scene::parse(source.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains(expected), "{err}");
+    }
+}
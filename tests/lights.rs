@@ -0,0 +1,208 @@
+//! Direct lighting from explicit lights, checked against closed form values
+//! with the CPU twin of the shader
+
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Vector3, Zero};
+use ray_tracer::{
+    camera::Camera, cpu, geometry::Geometry, light::Light, material::Material, shading::Shading,
+    sky::Sky, sphere::Sphere,
+};
+
+const ALBEDO: f32 = 0.6;
+const EMISSION: f32 = 50.0;
+
+/// Radiance along a ray looking straight down onto a huge diffuse sphere with
+/// its top at the origin, only counting light reaching the camera directly
+/// after the first bounce
+fn radiance_below(lights: Vec<Light>) -> Vector3<f32> {
+    let shading = Shading::new(
+        vec![Material::lambertian([ALBEDO; 3])],
+        lights,
+        Sky::Gradient([0.0; 3]),
+    );
+    let bindings = cpu::Bindings {
+        geometry: &Geometry {
+            spheres: vec![Sphere::new([0.0, -1000.0, 0.0], 1000.0, 0)],
+            ..Default::default()
+        }
+        .with_bvh(),
+        shading: &shading,
+    };
+
+    let mut camera = Camera::with_dimensions([1.0, 1.0]);
+    camera.max_depth = 1;
+    let ray = cpu::Ray {
+        pos: Vector3::new(0.0, 1.0, 0.0),
+        dir: -Vector3::unit_y(),
+    };
+    let mut state = cpu::init_sample_state(&camera, [0, 0], 0);
+    cpu::iterative_ray_colour(&camera, &bindings, &ray, &mut state)
+}
+
+fn assert_close(colour: Vector3<f32>, expected: f32) {
+    let error = (colour - Vector3::new(expected, expected, expected)).magnitude();
+    assert!(
+        error < 1e-3 * expected,
+        "expected {expected} but got {colour:?}"
+    );
+}
+
+#[test]
+fn point_light_falls_off_with_distance_squared() {
+    let intensity = 8.0;
+    let height = 2.0;
+    let colour = radiance_below(vec![Light::point([0.0, height, 0.0], [intensity; 3])]);
+    assert_close(colour, ALBEDO / PI * intensity / (height * height));
+}
+
+#[test]
+fn directional_light_scales_with_cosine() {
+    let irradiance = 3.0;
+    let colour = radiance_below(vec![Light::directional([1.0, -1.0, 0.0], [irradiance; 3])]);
+    assert_close(colour, ALBEDO / PI * irradiance * (PI / 4.0).cos());
+}
+
+#[test]
+fn lights_below_the_surface_are_ignored() {
+    let colour = radiance_below(vec![
+        Light::point([0.0, -2.0, 0.0], [5.0; 3]),
+        Light::directional([0.0, 1.0, 0.0], [5.0; 3]),
+    ]);
+    assert_eq!(colour, Vector3::zero());
+}
+
+#[test]
+fn occluded_lights_cast_shadows() {
+    let shading = Shading::new(
+        vec![Material::lambertian([ALBEDO; 3])],
+        vec![Light::point([0.0, 3.0, 0.0], [5.0; 3])],
+        Sky::Gradient([0.0; 3]),
+    );
+    let bindings = cpu::Bindings {
+        geometry: &Geometry {
+            spheres: vec![
+                Sphere::new([0.0, -1000.0, 0.0], 1000.0, 0),
+                // Between the light and the point looked at
+                Sphere::new([0.0, 2.0, 0.0], 0.5, 0),
+            ],
+            ..Default::default()
+        }
+        .with_bvh(),
+        shading: &shading,
+    };
+
+    let mut camera = Camera::with_dimensions([1.0, 1.0]);
+    camera.max_depth = 1;
+    let ray = cpu::Ray {
+        pos: Vector3::new(1.0, 1.0, 0.0),
+        dir: Vector3::new(-1.0, -1.0, 0.0).normalize(),
+    };
+    let mut state = cpu::init_sample_state(&camera, [0, 0], 0);
+    let colour = cpu::iterative_ray_colour(&camera, &bindings, &ray, &mut state);
+    assert_eq!(colour, Vector3::zero());
+}
+
+#[test]
+fn spherical_light_is_seen_directly() {
+    let radiance = [4.0, 2.0, 1.0];
+    let light = Light::spherical([0.0, 0.0, -5.0], 1.0, radiance);
+    let shading = Shading::new(vec![], vec![light], Sky::Gradient([0.0; 3]));
+    let bindings = cpu::Bindings {
+        geometry: &Geometry::default(),
+        shading: &shading,
+    };
+
+    let camera = Camera::with_dimensions([1.0, 1.0]);
+    let ray = cpu::Ray {
+        pos: Vector3::zero(),
+        dir: -Vector3::unit_z(),
+    };
+    let mut state = cpu::init_sample_state(&camera, [0, 0], 0);
+    let colour = cpu::iterative_ray_colour(&camera, &bindings, &ray, &mut state);
+    assert_eq!(colour, Vector3::from(radiance));
+}
+
+/// Average radiance over many samples looking straight down onto the same
+/// diffuse floor, with an emissive sphere or spherical light overhead
+fn average_below(emitter: Option<Sphere>, lights: Vec<Light>, samples: u32) -> Vector3<f32> {
+    let mut spheres = vec![Sphere::new([0.0, -1000.0, 0.0], 1000.0, 0)];
+    spheres.extend(emitter);
+    let shading = Shading::new(
+        vec![
+            Material::lambertian([ALBEDO; 3]),
+            Material::emissive([EMISSION; 3]),
+        ],
+        lights,
+        Sky::Gradient([0.0; 3]),
+    );
+    let bindings = cpu::Bindings {
+        geometry: &Geometry {
+            spheres,
+            ..Default::default()
+        }
+        .with_bvh(),
+        shading: &shading,
+    };
+
+    // The floor never sees itself, so only the first two bounces carry light
+    let mut camera = Camera::with_dimensions([1.0, 1.0]);
+    camera.max_depth = 2;
+    camera.samples = samples;
+    let ray = cpu::Ray {
+        pos: Vector3::new(0.0, 0.5, 0.0),
+        dir: -Vector3::unit_y(),
+    };
+
+    let mut total = Vector3::zero();
+    for sample in 0..samples {
+        let mut state = cpu::init_sample_state(&camera, [0, 0], sample);
+        total += cpu::iterative_ray_colour(&camera, &bindings, &ray, &mut state);
+    }
+    total / samples as f32
+}
+
+/// A sphere of radiance L at distance d gives irradiance pi L r^2 / d^2 on a
+/// surface facing it, reflecting albedo L r^2 / d^2
+fn sphere_reflected(radius: f32, height: f32) -> f32 {
+    ALBEDO * EMISSION * radius * radius / (height * height)
+}
+
+fn assert_within(colour: Vector3<f32>, expected: f32, tolerance: f32) {
+    let error = (colour - Vector3::new(expected, expected, expected)).magnitude();
+    assert!(
+        error < tolerance * expected,
+        "expected {expected} but got {colour:?}"
+    );
+}
+
+/// Bouncing alone finds a light this small once in hundreds of samples
+#[test]
+fn small_spherical_light_converges_with_few_samples() {
+    let (radius, height) = (0.05, 2.0);
+    let light = Light::spherical([0.0, height, 0.0], radius, [EMISSION; 3]);
+    let colour = average_below(None, vec![light], 64);
+    assert_within(colour, sphere_reflected(radius, height), 0.02);
+}
+
+#[test]
+fn small_emissive_sphere_converges_with_few_samples() {
+    let (radius, height) = (0.05, 2.0);
+    let emitter = Sphere::new([0.0, height, 0.0], radius, 1);
+    let colour = average_below(Some(emitter), vec![], 64);
+    assert_within(colour, sphere_reflected(radius, height), 0.02);
+}
+
+/// Close to a large emitter both sampling strategies contribute, the weights
+/// have to sum to one for the estimate to be unbiased
+#[test]
+fn large_emitter_is_unbiased() {
+    let (radius, height) = (1.0, 2.0);
+    let emitter = Sphere::new([0.0, height, 0.0], radius, 1);
+    let colour = average_below(Some(emitter), vec![], 4096);
+    assert_within(colour, sphere_reflected(radius, height), 0.02);
+
+    let light = Light::spherical([0.0, height, 0.0], radius, [EMISSION; 3]);
+    let colour = average_below(None, vec![light], 4096);
+    assert_within(colour, sphere_reflected(radius, height), 0.02);
+}
@@ -0,0 +1,218 @@
+//! OBJ and MTL loading and triangle intersection, using the CPU twin of the
+//! shader
+
+mod common;
+
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector3};
+use common::ray;
+use ray_tracer::{
+    cpu,
+    geometry::Geometry,
+    material::Material,
+    mesh::{parse_mtl, Mesh, Obj},
+    primitive::{MeshVertex, Triangle},
+    scene::Scene,
+};
+
+#[test]
+fn obj_faces_are_triangulated_with_shared_vertices() {
+    let obj = Obj::parse(
+        b"# Square and a triangle sharing an edge
+mtllib a.mtl b.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vn 0 0 1
+usemtl red
+f 1//1 2//1 3//1 4//1
+usemtl missing
+f -4//-1 -2//-1 -1//-1
+",
+    )
+    .unwrap();
+    assert_eq!(obj.libraries, ["a.mtl", "b.mtl"]);
+
+    let red = Material::lambertian([0.8, 0.1, 0.1]);
+    let mesh = obj.into_mesh(&HashMap::from([("red".to_string(), red)]));
+    assert_eq!(mesh.vertices.len(), 4);
+    assert_eq!(
+        mesh.vertices[2],
+        MeshVertex::new([1.0, 1.0, 0.0], [0.0, 0.0, 1.0])
+    );
+    assert_eq!(
+        mesh.triangles,
+        [
+            Triangle::new([0, 1, 2], 0),
+            Triangle::new([0, 2, 3], 0),
+            Triangle::new([0, 2, 3], 1),
+        ]
+    );
+    assert_eq!(
+        mesh.materials,
+        [red, Material::lambertian(Mesh::DEFAULT_ALBEDO)]
+    );
+}
+
+#[test]
+fn obj_errors_name_the_line() {
+    let err = Obj::parse(b"v 0 0 0\nv 1 0 0\nf 1 2 3\n").unwrap_err();
+    let message = format!("{err:#}");
+    assert!(
+        message.contains("line 3") && message.contains("position index 3"),
+        "{message}"
+    );
+
+    let err = Obj::parse(b"v 0 0\n").unwrap_err();
+    assert!(format!("{err:#}").contains("line 1"), "{err:#}");
+}
+
+#[test]
+fn mtl_maps_onto_material_kinds() {
+    let materials = parse_mtl(
+        b"newmtl matte
+Kd 0.2 0.4 0.6
+Ks 0.1 0.1 0.1
+
+newmtl mirror
+Kd 0 0 0
+Ks 0.9 0.9 0.9
+Ns 1000000
+
+newmtl rough
+Ks 0.5 0.5 0.5
+Ns 0
+illum 3
+
+newmtl glass
+Ni 1.33
+d 0.2
+
+newmtl lamp
+Ke 5 4 3
+",
+    )
+    .unwrap();
+    assert_eq!(materials["matte"], Material::lambertian([0.2, 0.4, 0.6]));
+    assert_eq!(materials["glass"], Material::dielectric(1.33));
+    assert_eq!(materials["lamp"], Material::emissive([5.0, 4.0, 3.0]));
+
+    let mirror = materials["mirror"];
+    assert_eq!((mirror.kind, mirror.albedo), (Material::METAL, [0.9; 3]));
+    assert!(mirror.fuzz < 0.01);
+    assert_eq!(materials["rough"].fuzz, 1.0);
+}
+
+#[test]
+fn triangle_normals_are_interpolated() {
+    let vertices = [
+        MeshVertex::new([0.0, 0.0, -1.0], [-1.0, 0.0, 1.0]),
+        MeshVertex::new([2.0, 0.0, -1.0], [1.0, 0.0, 1.0]),
+        MeshVertex::new([0.0, 2.0, -1.0], [-1.0, 0.0, 1.0]),
+    ];
+    let triangle = Triangle::new([0, 1, 2], 4);
+
+    let hit = cpu::hit_triangle(
+        &triangle,
+        &vertices,
+        &ray([1.0, 0.5, 0.0], [0.0, 0.0, -1.0]),
+    );
+    assert!(hit.hit && hit.front_face);
+    assert_eq!((hit.primitive, hit.material), (Geometry::TRIANGLE, 4));
+    assert!((hit.distance - 1.0).abs() < 1e-5);
+    // Where the corners' normals lean sideways by equal amounts either way
+    assert!(
+        (hit.normal - Vector3::unit_z()).magnitude() < 1e-5,
+        "{hit:?}"
+    );
+
+    // From behind the normal faces the ray
+    let hit = cpu::hit_triangle(
+        &triangle,
+        &vertices,
+        &ray([1.0, 0.5, -2.0], [0.0, 0.0, 1.0]),
+    );
+    assert!(hit.hit && !hit.front_face);
+    assert!(
+        (hit.normal + Vector3::unit_z()).magnitude() < 1e-5,
+        "{hit:?}"
+    );
+
+    // Past the long edge
+    assert!(
+        !cpu::hit_triangle(
+            &triangle,
+            &vertices,
+            &ray([1.1, 1.1, 0.0], [0.0, 0.0, -1.0])
+        )
+        .hit
+    );
+}
+
+#[test]
+fn flat_triangles_use_their_face_normal() {
+    let vertices = [
+        MeshVertex::new([0.0, 0.0, 0.0], [0.0; 3]),
+        MeshVertex::new([0.0, 0.0, -1.0], [0.0; 3]),
+        MeshVertex::new([1.0, 0.0, 0.0], [0.0; 3]),
+    ];
+    // Anticlockwise seen from above
+    let triangle = Triangle::new([0, 2, 1], 0);
+    let hit = cpu::hit_triangle(
+        &triangle,
+        &vertices,
+        &ray([0.2, 1.0, -0.2], [0.0, -1.0, 0.0]),
+    );
+    assert!(hit.hit && hit.front_face);
+    assert!(
+        (hit.normal - Vector3::unit_y()).magnitude() < 1e-6,
+        "{hit:?}"
+    );
+}
+
+#[test]
+fn scene_meshes_are_placed_with_their_materials() {
+    let scene = pollster::block_on(Scene::load("scenes/mesh.ron")).unwrap();
+    let (torus, gem, cube) = (
+        &scene.mesh_data[0],
+        &scene.mesh_data[1],
+        &scene.mesh_data[2],
+    );
+    assert_eq!(torus.triangles.len(), 24 * 12 * 2);
+    assert_eq!(gem.triangles.len(), 12);
+    assert_eq!(cube.triangles.len(), 12);
+
+    // The torus and gem bring their own materials, the cube uses the scene's
+    let materials = scene.gpu_materials();
+    assert_eq!(materials.len(), scene.materials.len() + 2);
+    assert_eq!(materials[2].kind, Material::METAL);
+    assert_eq!(materials[3].kind, Material::DIELECTRIC);
+
+    let geometry = scene.geometry();
+    let count = torus.triangles.len() + gem.triangles.len() + cube.triangles.len();
+    assert_eq!(geometry.triangles.len(), count);
+    assert_eq!(geometry.triangles[0].material, 2);
+    assert_eq!(geometry.triangles[torus.triangles.len()].material, 3);
+    assert_eq!(geometry.triangles[count - 1].material, 1);
+
+    // The cube's vertices are scaled, turned about y and moved
+    let first_cube_vertex = torus.vertices.len() + gem.vertices.len();
+    let corner = Vector3::from(geometry.vertices[first_cube_vertex].pos);
+    let expected = Vector3::new(0.6, 0.0, -0.8);
+    assert!((corner.y - expected.y).abs() < 1e-5, "{corner:?}");
+    let horizontal = (corner - expected).magnitude();
+    assert!((horizontal - 0.35 * 2f32.sqrt()).abs() < 1e-5, "{corner:?}");
+}
+
+#[test]
+fn scene_mesh_errors_name_the_mesh() {
+    // Meshes keeping their own materials still count towards the index
+    let source = br#"(
+        materials: [Lambertian(albedo: (0.5, 0.5, 0.5))],
+        meshes: [(path: "a.obj"), (path: "b.obj", material: 0), (path: "c.obj", material: 1)],
+    )"#;
+    let err = Scene::parse(source).unwrap_err();
+    assert!(err.to_string().contains("meshes[2].material"), "{err}");
+}
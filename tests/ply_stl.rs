@@ -0,0 +1,300 @@
+//! PLY and STL loading, and vertex colours multiplying the albedo of the
+//! triangles they are interpolated across
+
+use cgmath::{InnerSpace, Vector3};
+use ray_tracer::{
+    cpu,
+    geometry::Geometry,
+    material::Material,
+    mesh::Mesh,
+    ply::parse_ply,
+    primitive::{MeshVertex, Triangle},
+    scene::Scene,
+    shading::Shading,
+    sky::Sky,
+    stl::parse_stl,
+};
+
+/// Square and a triangle sharing an edge, with colours, an extra vertex
+/// property and an element which are skipped
+const PLY_HEADER: &str = "ply
+format {format} 1.0
+comment Two faces
+element vertex 5
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+property float confidence
+element face 2
+property list uchar int vertex_indices
+element edge 1
+property int vertex1
+property int vertex2
+end_header
+";
+
+const PLY_VERTICES: [([f32; 3], [u8; 3]); 5] = [
+    ([0.0, 0.0, 0.0], [255, 0, 0]),
+    ([1.0, 0.0, 0.0], [0, 255, 0]),
+    ([1.0, 1.0, 0.0], [0, 0, 255]),
+    ([0.0, 1.0, 0.0], [255, 255, 255]),
+    ([2.0, 0.0, 0.0], [0, 0, 0]),
+];
+
+const PLY_FACES: [&[i32]; 2] = [&[0, 1, 2, 3], &[1, 4, 2]];
+
+fn ascii_ply() -> Vec<u8> {
+    let mut text = PLY_HEADER.replace("{format}", "ascii");
+    for (pos, colour) in PLY_VERTICES {
+        let [x, y, z] = pos;
+        let [r, g, b] = colour;
+        text += &format!("{x} {y} {z} {r} {g} {b} 0.5\n");
+    }
+    for face in PLY_FACES {
+        let indices: Vec<String> = face.iter().map(|i| i.to_string()).collect();
+        text += &format!("{} {}\n", face.len(), indices.join(" "));
+    }
+    text += "0 1\n";
+    text.into_bytes()
+}
+
+fn binary_ply(big_endian: bool) -> Vec<u8> {
+    let format = match big_endian {
+        true => "binary_big_endian",
+        false => "binary_little_endian",
+    };
+    let mut bytes = PLY_HEADER.replace("{format}", format).into_bytes();
+    let mut push = |mut word: Vec<u8>| {
+        if big_endian {
+            word.reverse();
+        }
+        bytes.extend(word);
+    };
+    for (pos, colour) in PLY_VERTICES {
+        for x in pos {
+            push(x.to_le_bytes().to_vec());
+        }
+        for c in colour {
+            push(vec![c]);
+        }
+        push(0.5f32.to_le_bytes().to_vec());
+    }
+    for face in PLY_FACES {
+        push(vec![face.len() as u8]);
+        for i in face {
+            push(i.to_le_bytes().to_vec());
+        }
+    }
+    push(0i32.to_le_bytes().to_vec());
+    push(1i32.to_le_bytes().to_vec());
+    bytes
+}
+
+#[test]
+fn ply_faces_are_triangulated_with_colours() {
+    let mesh = parse_ply(&ascii_ply()).unwrap();
+    assert_eq!(mesh.vertices.len(), 5);
+    assert_eq!(
+        mesh.triangles,
+        [
+            Triangle::new([0, 1, 2], 0),
+            Triangle::new([0, 2, 3], 0),
+            Triangle::new([1, 4, 2], 0),
+        ]
+    );
+    // Coloured vertices are shown as they are on a white material
+    assert_eq!(mesh.materials, [Material::lambertian([1.0; 3])]);
+
+    // Colours are decoded from sRGB, there are no normals
+    assert_eq!(
+        mesh.vertices[1],
+        MeshVertex::new([1.0, 0.0, 0.0], [0.0; 3]).with_colour([0.0, 1.0, 0.0])
+    );
+    assert_eq!(mesh.vertices[3].colour, [1.0; 3]);
+    assert_eq!(mesh.vertices[4].colour, [0.0; 3]);
+}
+
+#[test]
+fn binary_ply_matches_ascii() {
+    let ascii = parse_ply(&ascii_ply()).unwrap();
+    for big_endian in [false, true] {
+        let binary = parse_ply(&binary_ply(big_endian)).unwrap();
+        assert_eq!(binary, ascii, "big endian {big_endian}");
+    }
+}
+
+#[test]
+fn ply_normals_are_read_and_colours_are_optional() {
+    let mesh = parse_ply(
+        b"ply
+format ascii 1.0
+element vertex 3
+property double x
+property double y
+property double z
+property float nx
+property float ny
+property float nz
+element face 1
+property list uchar uint vertex_index
+end_header
+0 0 0 0 0 1
+1 0 0 0 0 1
+0 1 0 0 0 1
+3 0 1 2
+",
+    )
+    .unwrap();
+    assert_eq!(mesh.vertices[2].normal, [0.0, 0.0, 1.0]);
+    assert_eq!(mesh.vertices[2].colour, [1.0; 3]);
+    assert_eq!(mesh.materials, [Material::lambertian(Mesh::DEFAULT_ALBEDO)]);
+}
+
+#[test]
+fn ply_errors_are_reported() {
+    let errors: [(&[u8], &str); 8] = [
+        (b"obj\n", "end_header"),
+        (b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n0\n", "x, y and z"),
+        (b"ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\nend_header\n", "half"),
+        (
+            b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n3 0 1 2\n",
+            "vertex 1 does not exist",
+        ),
+        // Negative or fractional indices and lengths aren't taken as vertex 0
+        (
+            b"ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list char int vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n3 0 -1 2\n",
+            "Invalid face 0: -1 is not a whole number",
+        ),
+        (
+            b"ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list char int vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n3 0 1.5 2\n",
+            "Invalid face 0: 1.5 is not a whole number",
+        ),
+        (
+            b"ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list char int vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n-3 0 1 2\n",
+            "Invalid face 0 vertex_indices length: -3",
+        ),
+        (
+            b"ply\nformat binary_little_endian 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\nend_header\n\0\0\0\0",
+            "end of file",
+        ),
+    ];
+    for (bytes, expected) in errors {
+        let err = parse_ply(bytes).unwrap_err();
+        assert!(format!("{err:#}").contains(expected), "{err:#}");
+    }
+}
+
+/// Two facets sharing an edge, as ASCII then binary STL
+fn stl_facets() -> [[[f32; 3]; 3]; 2] {
+    [
+        [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+        [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+    ]
+}
+
+#[test]
+fn stl_corners_are_merged_into_shared_vertices() {
+    let mut ascii = String::from("solid square\n");
+    for facet in stl_facets() {
+        ascii += "  facet normal 0 0 1\n    outer loop\n";
+        for [x, y, z] in facet {
+            ascii += &format!("      vertex {x} {y} {z}\n");
+        }
+        ascii += "    endloop\n  endfacet\n";
+    }
+    ascii += "endsolid square\n";
+
+    // Binary files may start with solid too
+    let mut binary = b"solid but binary".to_vec();
+    binary.resize(80, b' ');
+    binary.extend(2u32.to_le_bytes());
+    for facet in stl_facets() {
+        binary.extend(bytemuck::cast_slice(&[0.0f32, 0.0, 1.0]));
+        binary.extend(bytemuck::cast_slice(&facet));
+        binary.extend([0, 0]);
+    }
+
+    for bytes in [ascii.as_bytes(), &binary] {
+        let mesh = parse_stl(bytes).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.vertices[3], MeshVertex::new([0.0, 1.0, 0.0], [0.0; 3]));
+        assert_eq!(
+            mesh.triangles,
+            [Triangle::new([0, 1, 2], 0), Triangle::new([0, 2, 3], 0)]
+        );
+        assert_eq!(mesh.materials, [Material::lambertian(Mesh::DEFAULT_ALBEDO)]);
+    }
+
+    let err = parse_stl(b"solid\nfacet normal 0 0 1\nvertex 0 0 0\nvertex 1 0\n").unwrap_err();
+    assert!(format!("{err:#}").contains("line 4"), "{err:#}");
+    let err = parse_stl(b"solid\nfacet\nvertex 0 0 0\nvertex 1 0 0\nendfacet\n").unwrap_err();
+    assert!(format!("{err:#}").contains("at least 3"), "{err:#}");
+    assert!(parse_stl(b"\0\0\0\0").is_err());
+}
+
+#[test]
+fn vertex_colours_multiply_the_albedo() {
+    let geometry = Geometry {
+        vertices: vec![
+            MeshVertex::new([0.0, 0.0, -1.0], [0.0; 3]).with_colour([1.0, 0.0, 0.0]),
+            MeshVertex::new([1.0, 0.0, -1.0], [0.0; 3]).with_colour([0.0, 1.0, 0.0]),
+            MeshVertex::new([0.0, 1.0, -1.0], [0.0; 3]).with_colour([0.0, 0.0, 1.0]),
+        ],
+        triangles: vec![Triangle::new([0, 1, 2], 0)],
+        ..Default::default()
+    }
+    .with_bvh();
+    let shading = Shading::new(
+        vec![Material::lambertian([0.5, 0.5, 0.5])],
+        Vec::new(),
+        Sky::default(),
+    );
+    let bindings = cpu::Bindings {
+        geometry: &geometry,
+        shading: &shading,
+    };
+
+    // A quarter of the way along each edge from the first corner
+    let ray = cpu::Ray {
+        pos: Vector3::new(0.25, 0.25, 0.0),
+        dir: -Vector3::unit_z(),
+    };
+    let hit = cpu::cast_ray(&bindings, &ray);
+    assert!(hit.hit);
+    assert!(
+        (hit.colour - Vector3::new(0.5, 0.25, 0.25)).magnitude() < 1e-5,
+        "{hit:?}"
+    );
+    let albedo = Vector3::from(cpu::hit_material(&bindings, &hit).albedo);
+    assert!(
+        (albedo - Vector3::new(0.25, 0.125, 0.125)).magnitude() < 1e-5,
+        "{albedo:?}"
+    );
+}
+
+#[test]
+fn model_files_load_as_framed_scenes() {
+    let scene = pollster::block_on(Scene::load("scenes/scanned.ron")).unwrap();
+    let [sphere, steps] = &scene.mesh_data[..] else {
+        panic!("{} meshes", scene.mesh_data.len());
+    };
+    assert_eq!(sphere.triangles.len(), 512);
+    assert!(sphere.vertices.iter().all(|v| v.normal != [0.0; 3]));
+    assert_eq!(steps.triangles.len(), 36);
+    assert_eq!(steps.vertices.len(), 24);
+    let geometry = scene.geometry();
+    assert_eq!(geometry.triangles.len(), 512 + 36);
+
+    // By itself the block is seen from in front, with all of it in view
+    let scene = pollster::block_on(Scene::load("scenes/models/steps.stl")).unwrap();
+    let target = Vector3::from(scene.camera.target);
+    assert!((target - Vector3::new(0.0, 0.6, 0.0)).magnitude() < 1e-5);
+    let view = Vector3::from(scene.camera.pos) - target;
+    assert!(view.z > 0.0 && view.y > 0.0, "{view:?}");
+    let half_diagonal = Vector3::new(2.0, 1.2, 2.0).magnitude() / 2.0;
+    let half_fov = (scene.camera.vfov / 2.0).to_radians();
+    assert!(half_diagonal / view.magnitude() <= half_fov.sin() + 1e-5);
+}
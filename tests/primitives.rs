@@ -0,0 +1,210 @@
+//! Intersections with the analytic primitives, using the CPU twin of the
+//! shader
+
+mod common;
+
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use cgmath::{InnerSpace, Vector3};
+use common::ray;
+use ray_tracer::{
+    cpu,
+    geometry::Geometry,
+    primitive::{Cuboid, Disc, Plane, Quad},
+    scene::Scene,
+    shading::Shading,
+    sphere::Sphere,
+};
+
+fn assert_near(actual: Vector3<f32>, expected: [f32; 3]) {
+    let error = (actual - Vector3::from(expected)).magnitude();
+    assert!(error < 1e-4, "expected {expected:?} but got {actual:?}");
+}
+
+#[test]
+fn plane_is_hit_from_either_side() {
+    let plane = Plane::new([0.0, 1.0, 0.0], [0.0, 2.0, 0.0], 3);
+
+    let hit = cpu::hit_plane(&plane, &ray([0.5, 3.0, 0.0], [0.0, -1.0, 0.0]));
+    assert!(hit.hit && hit.front_face);
+    assert_eq!((hit.distance, hit.material), (2.0, 3));
+    assert_near(hit.normal, [0.0, 1.0, 0.0]);
+
+    let hit = cpu::hit_plane(&plane, &ray([0.0, -1.0, 0.0], [1.0, 1.0, 0.0]));
+    assert!(hit.hit && !hit.front_face);
+    assert_near(hit.pos, [2.0, 1.0, 0.0]);
+    assert_near(hit.normal, [0.0, -1.0, 0.0]);
+
+    // Parallel and facing away
+    assert!(!cpu::hit_plane(&plane, &ray([0.0, 3.0, 0.0], [1.0, 0.0, 0.0])).hit);
+    assert!(!cpu::hit_plane(&plane, &ray([0.0, 3.0, 0.0], [0.0, 1.0, 0.0])).hit);
+}
+
+#[test]
+fn disc_is_cut_at_its_radius() {
+    let disc = Disc::new([0.0, 0.0, -2.0], [0.0, 0.0, 1.0], 0.5, 0);
+
+    let hit = cpu::hit_disc(&disc, &ray([0.4, 0.0, 0.0], [0.0, 0.0, -1.0]));
+    assert!(hit.hit && hit.front_face);
+    assert_near(hit.pos, [0.4, 0.0, -2.0]);
+    assert_near(hit.normal, [0.0, 0.0, 1.0]);
+
+    assert!(!cpu::hit_disc(&disc, &ray([0.4, 0.4, 0.0], [0.0, 0.0, -1.0])).hit);
+}
+
+#[test]
+fn box_faces_have_outward_normals() {
+    let cuboid = Cuboid::new([-1.0, 0.0, -1.0], [1.0, 1.0, 1.0], [0.0; 3], 2);
+    let cases = [
+        ([0.0, 5.0, 0.0], [0.0, -1.0, 0.0], 4.0, [0.0, 1.0, 0.0]),
+        ([0.2, 0.5, -4.0], [0.0, 0.0, 1.0], 3.0, [0.0, 0.0, -1.0]),
+        ([3.0, 0.5, 0.3], [-1.0, 0.0, 0.0], 2.0, [1.0, 0.0, 0.0]),
+    ];
+    for (pos, dir, distance, normal) in cases {
+        let hit = cpu::hit_box(&cuboid, &ray(pos, dir));
+        assert!(hit.hit && hit.front_face, "{pos:?}");
+        assert!((hit.distance - distance).abs() < 1e-4, "{pos:?} {hit:?}");
+        assert_near(hit.normal, normal);
+    }
+
+    // From inside the way out is found, facing back in
+    let hit = cpu::hit_box(&cuboid, &ray([0.0, 0.5, 0.0], [1.0, 0.0, 0.0]));
+    assert!(hit.hit && !hit.front_face);
+    assert_near(hit.pos, [1.0, 0.5, 0.0]);
+    assert_near(hit.normal, [-1.0, 0.0, 0.0]);
+
+    // Just passing by the top edge
+    assert!(!cpu::hit_box(&cuboid, &ray([0.0, 1.01, -4.0], [0.0, 0.0, 1.0])).hit);
+}
+
+#[test]
+fn rotated_box_turns_its_faces() {
+    // A unit cube turned 45 degrees about y presents an edge to +z
+    let cuboid = Cuboid::new([-0.5; 3], [0.5; 3], [0.0, 45.0, 0.0], 0);
+    let s = FRAC_1_SQRT_2;
+
+    let hit = cpu::hit_box(&cuboid, &ray([0.1, 0.0, 3.0], [0.0, 0.0, -1.0]));
+    assert!(hit.hit);
+    assert!((hit.distance - (3.0 - s + 0.1)).abs() < 1e-4, "{hit:?}");
+    assert_near(hit.normal, [s, 0.0, s]);
+
+    // Through the unrotated cube's corner but outside the turned one
+    assert!(!cpu::hit_box(&cuboid, &ray([0.45, 3.0, 0.45], [0.0, -1.0, 0.0])).hit);
+}
+
+#[test]
+fn quad_is_bounded_by_its_edges() {
+    // Slanted parallelogram in the plane z = -1
+    let quad = Quad::new([0.0, 0.0, -1.0], [2.0, 0.0, 0.0], [1.0, 1.0, 0.0], 1);
+
+    let hit = cpu::hit_quad(&quad, &ray([2.5, 0.9, 0.0], [0.0, 0.0, -1.0]));
+    assert!(hit.hit && hit.front_face);
+    assert_eq!(hit.material, 1);
+    assert_near(hit.pos, [2.5, 0.9, -1.0]);
+    assert_near(hit.normal, [0.0, 0.0, 1.0]);
+
+    // Inside the bounding rectangle but outside the slanted edges
+    assert!(!cpu::hit_quad(&quad, &ray([0.2, 0.9, 0.0], [0.0, 0.0, -1.0])).hit);
+    assert!(!cpu::hit_quad(&quad, &ray([2.9, 0.1, 0.0], [0.0, 0.0, -1.0])).hit);
+}
+
+#[test]
+fn cast_ray_finds_closest_of_every_kind() {
+    let mut geometry = Geometry {
+        spheres: vec![Sphere::new([0.0, 0.0, -10.0], 1.0, 0)],
+        planes: vec![Plane::new([0.0, 0.0, -20.0], [0.0, 0.0, 1.0], 0)],
+        discs: vec![
+            Disc::new([5.0, 0.0, 0.0], [0.0, 0.0, 1.0], 1.0, 0),
+            Disc::new([0.0, 0.0, -8.0], [0.0, 0.0, 1.0], 1.0, 0),
+        ],
+        boxes: vec![Cuboid::new(
+            [-0.5, -0.5, -7.0],
+            [0.5, 0.5, -6.0],
+            [0.0; 3],
+            0,
+        )],
+        quads: vec![Quad::new(
+            [-1.0, -1.0, -4.0],
+            [2.0, 0.0, 0.0],
+            [0.0, 2.0, 0.0],
+            0,
+        )],
+        ..Default::default()
+    };
+    let shading = Shading::default();
+
+    // Remove the closest primitive each time to reveal the next
+    let expected = [
+        (Geometry::QUAD, 0, 4.0),
+        (Geometry::BOX, 0, 6.0),
+        (Geometry::DISC, 1, 8.0),
+        (Geometry::SPHERE, 0, 9.0),
+        (Geometry::PLANE, 0, 20.0),
+    ];
+    let ray = ray([0.0; 3], [0.0, 0.0, -1.0]);
+    let cast = |geometry: &Geometry| {
+        let bindings = cpu::Bindings {
+            geometry: &geometry.clone().with_bvh(),
+            shading: &shading,
+        };
+        cpu::cast_ray(&bindings, &ray)
+    };
+    for (primitive, index, distance) in expected {
+        let hit = cast(&geometry);
+        assert!(hit.hit);
+        assert_eq!((hit.primitive, hit.index), (primitive, index));
+        assert!((hit.distance - distance).abs() < 1e-4, "{hit:?}");
+        match primitive {
+            Geometry::QUAD => geometry.quads.clear(),
+            Geometry::BOX => geometry.boxes.clear(),
+            Geometry::DISC => geometry.discs.clear(),
+            Geometry::SPHERE => geometry.spheres.clear(),
+            _ => geometry.planes.clear(),
+        }
+    }
+    assert!(!cast(&geometry).hit);
+}
+
+#[test]
+fn scene_primitives_are_parsed_and_checked() {
+    let scene = Scene::parse(
+        b"(
+            materials: [Lambertian(albedo: (0.5, 0.5, 0.5))],
+            planes: [(pos: (0.0, 0.0, 0.0), normal: (0.0, 2.0, 0.0), material: 0)],
+            discs: [(pos: (0.0, 1.0, 0.0), normal: (0.0, 1.0, 0.0), radius: 0.5, material: 0)],
+            boxes: [(min: (0.0, 0.0, 0.0), max: (1.0, 2.0, 3.0), rotation: (0.0, 90.0, 0.0), material: 0)],
+            quads: [(pos: (0.0, 0.0, 0.0), u: (1.0, 0.0, 0.0), v: (0.0, 1.0, 0.0), material: 0)],
+        )",
+    )
+    .unwrap();
+    let geometry = scene.geometry();
+    assert_eq!(geometry.planes[0].normal, [0.0, 1.0, 0.0]);
+    assert_eq!(geometry.discs[0].radius, 0.5);
+    assert_eq!(geometry.boxes[0].pos, [0.5, 1.0, 1.5]);
+    assert_eq!(geometry.boxes[0].half_size, [0.5, 1.0, 1.5]);
+    assert_eq!(geometry.quads[0].v, [0.0, 1.0, 0.0]);
+
+    let errors = [
+        (
+            "discs: [(pos: (0.0, 0.0, 0.0), normal: (0.0, 1.0, 0.0), radius: 0.0, material: 0)]",
+            "discs[0].radius",
+        ),
+        (
+            "boxes: [(min: (0.0, 0.0, 0.0), max: (1.0, 0.0, 1.0), material: 0)]",
+            "boxes[0].max",
+        ),
+        (
+            "quads: [(pos: (0.0, 0.0, 0.0), u: (1.0, 0.0, 0.0), v: (2.0, 0.0, 0.0), material: 0)]",
+            "quads[0].v",
+        ),
+        (
+            "planes: [(pos: (0.0, 0.0, 0.0), normal: (0.0, 1.0, 0.0), material: 1)]",
+            "planes[0].material",
+        ),
+    ];
+    for (field, expected) in errors {
+        let source = format!("(materials: [Lambertian(albedo: (0.5, 0.5, 0.5))], {field})");
+        let err = Scene::parse(source.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains(expected), "{err}");
+    }
+}
@@ -0,0 +1,93 @@
+//! Statistical tests of the random number generator, using the CPU twin of
+//! the shader functions
+
+use cgmath::InnerSpace;
+use ray_tracer::{
+    camera::Camera,
+    cpu::{init_rng, init_sample_state, rand, rand2, random_in_unit_sphere},
+};
+
+/// Pearson's chi-squared statistic of bin counts against a uniform distribution
+fn chi_squared(bins: &[u32]) -> f64 {
+    let total: u32 = bins.iter().sum();
+    let expected = total as f64 / bins.len() as f64;
+    bins.iter()
+        .map(|&count| (count as f64 - expected).powi(2) / expected)
+        .sum()
+}
+
+/// Chi-squared critical values at p = 0.001, for 99 and 255 degrees of freedom
+const CRITICAL_99: f64 = 148.2;
+const CRITICAL_255: f64 = 330.5;
+
+#[test]
+fn sequence_is_uniform() {
+    let mut state = init_rng([3, 7], 0, 0);
+    let mut bins = [0; 100];
+    for _ in 0..1_000_000 {
+        let x = rand(&mut state);
+        assert!((0.0..1.0).contains(&x));
+        bins[(x * 100.0) as usize] += 1;
+    }
+
+    let chi = chi_squared(&bins);
+    assert!(chi < CRITICAL_99, "chi-squared {chi}");
+}
+
+/// Consecutive draws are used together as 2D points, correlation between them
+/// shows up as clumping in a 2D histogram
+#[test]
+fn consecutive_pairs_are_uniform() {
+    let mut state = init_rng([0, 0], 1, 2);
+    let mut bins = [0; 256];
+    for _ in 0..500_000 {
+        let p = rand2(&mut state);
+        bins[(p.x * 16.0) as usize * 16 + (p.y * 16.0) as usize] += 1;
+    }
+
+    let chi = chi_squared(&bins);
+    assert!(chi < CRITICAL_255, "chi-squared {chi}");
+}
+
+/// Neighbouring pixels, frames and samples must not start with similar values
+#[test]
+fn first_draws_across_seeds_are_uniform() {
+    type Seed = fn(u32) -> u32;
+    let seeds: [(&str, Seed); 3] = [
+        ("pixel", |i| init_rng([i % 800, i / 800], 0, 0)),
+        ("frame", |i| init_rng([10, 20], i, 0)),
+        ("sample", |i| init_rng([10, 20], 5, i)),
+    ];
+    for (name, seed) in seeds {
+        let mut bins = [0; 100];
+        for i in 0..200_000 {
+            let mut state = seed(i);
+            bins[(rand(&mut state) * 100.0) as usize] += 1;
+        }
+
+        let chi = chi_squared(&bins);
+        assert!(chi < CRITICAL_99, "{name} chi-squared {chi}");
+    }
+}
+
+#[test]
+fn unit_sphere_is_uniform_in_volume() {
+    let mut state = init_sample_state(&Camera::with_dimensions([1.0, 1.0]), [1, 1], 1);
+    let mut shells = [0; 100];
+    let mut octants = [0; 8];
+    for _ in 0..400_000 {
+        let p = random_in_unit_sphere(&mut state);
+        let r = p.magnitude();
+        assert!(r <= 1.0 + 1e-5);
+
+        // Equal volume shells, the fraction of volume within r is r^3
+        shells[((r.powi(3) * 100.0) as usize).min(99)] += 1;
+        octants[(p.x > 0.0) as usize * 4 + (p.y > 0.0) as usize * 2 + (p.z > 0.0) as usize] += 1;
+    }
+
+    let chi = chi_squared(&shells);
+    assert!(chi < CRITICAL_99, "shells chi-squared {chi}");
+    // 7 degrees of freedom
+    let chi = chi_squared(&octants);
+    assert!(chi < 24.3, "octants chi-squared {chi}");
+}
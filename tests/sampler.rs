@@ -0,0 +1,96 @@
+//! Tests of the low discrepancy sampler against plain random sampling, using
+//! the CPU twin of the shader functions
+
+use cgmath::InnerSpace;
+use ray_tracer::{
+    camera::{Camera, Sampler},
+    cpu::{init_sample_state, random_unit_vector, sample_2d},
+};
+
+const PIXELS: u32 = 256;
+
+/// Root mean square error over many pixels of estimating the integral of f
+/// over the unit square with the given number of samples per pixel
+fn rms_error(sampler: Sampler, samples: u32, f: impl Fn(f32, f32) -> f32, expected: f32) -> f32 {
+    let mut camera = Camera::with_dimensions([1.0, 1.0]);
+    camera.sampler = sampler as u32;
+    camera.samples = samples;
+
+    let mut squared_error = 0.0;
+    for pixel in 0..PIXELS {
+        let mut sum = 0.0;
+        for i in 0..samples {
+            let mut state = init_sample_state(&camera, [pixel, 0], i);
+            let p = sample_2d(&mut state);
+            sum += f(p.x, p.y);
+        }
+        squared_error += (sum / samples as f32 - expected).powi(2);
+    }
+    (squared_error / PIXELS as f32).sqrt()
+}
+
+#[test]
+fn sobol_has_less_error_than_random() {
+    // Quarter disc, a discontinuity like the edge of an object in a pixel
+    let disc = |x: f32, y: f32| (x * x + y * y <= 1.0) as i32 as f32;
+    // Smooth falloff, like shading across a pixel
+    let smooth = |x: f32, y: f32| x * y.sqrt();
+
+    for samples in [16, 64] {
+        for (name, f, expected) in [
+            (
+                "disc",
+                &disc as &dyn Fn(f32, f32) -> f32,
+                std::f32::consts::FRAC_PI_4,
+            ),
+            ("smooth", &smooth, 1.0 / 3.0),
+        ] {
+            let random = rms_error(Sampler::Random, samples, f, expected);
+            let sobol = rms_error(Sampler::Sobol, samples, f, expected);
+            assert!(
+                sobol < 0.75 * random,
+                "{name} with {samples} samples, sobol {sobol} random {random}"
+            );
+        }
+    }
+}
+
+/// Each frame continues the sequence, so averaging frames keeps the benefit
+#[test]
+fn sobol_continues_across_frames() {
+    let mut camera = Camera::with_dimensions([1.0, 1.0]);
+    camera.sampler = Sampler::Sobol as u32;
+    camera.samples = 4;
+
+    let mut points = Vec::new();
+    for frame in 0..4 {
+        camera.frame = frame;
+        for i in 0..camera.samples {
+            points.push(sample_2d(&mut init_sample_state(&camera, [5, 9], i)));
+        }
+    }
+
+    // The first 16 points of a scrambled Sobol sequence are stratified, one
+    // in each cell of a 4 by 4 grid
+    let mut cells = [0; 16];
+    for p in points {
+        cells[(p.x * 4.0) as usize * 4 + (p.y * 4.0) as usize] += 1;
+    }
+    assert_eq!(cells, [1; 16]);
+}
+
+/// Directions built from the sequence still cover the sphere uniformly
+#[test]
+fn sobol_unit_vectors_are_unbiased() {
+    let mut camera = Camera::with_dimensions([1.0, 1.0]);
+    camera.sampler = Sampler::Sobol as u32;
+    camera.samples = 1024;
+
+    let mut mean = cgmath::Vector3::new(0.0, 0.0, 0.0);
+    for i in 0..camera.samples {
+        let direction = random_unit_vector(&mut init_sample_state(&camera, [2, 3], i));
+        assert!((direction.magnitude() - 1.0).abs() < 1e-4);
+        mean += direction / camera.samples as f32;
+    }
+    assert!(mean.magnitude() < 0.01, "mean direction {mean:?}");
+}
@@ -0,0 +1,195 @@
+//! Physical sky and sun, using the CPU twin of the shader
+
+use std::f32::consts::{PI, TAU};
+
+use cgmath::{InnerSpace, Vector3, Zero};
+use ray_tracer::{
+    camera::Camera,
+    cpu,
+    geometry::Geometry,
+    light::Light,
+    material::Material,
+    scene::Scene,
+    shading::Shading,
+    sky::{PhysicalSky, Sky},
+    sphere::Sphere,
+};
+
+fn luminance(colour: Vector3<f32>) -> f32 {
+    0.2126 * colour.x + 0.7152 * colour.y + 0.0722 * colour.z
+}
+
+/// Direction at the given elevation and azimuth in degrees, matching the sun
+fn direction(elevation: f32, azimuth: f32) -> Vector3<f32> {
+    let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+    Vector3::new(
+        elevation.cos() * azimuth.sin(),
+        elevation.sin(),
+        -elevation.cos() * azimuth.cos(),
+    )
+}
+
+#[test]
+fn sky_is_brightest_around_the_sun() {
+    let sky = PhysicalSky::new(30f32.to_radians(), 40f32.to_radians(), 3.0, 1.0);
+    assert!((Vector3::from(sky.sun_direction) - direction(30.0, 40.0)).magnitude() < 1e-6);
+
+    let near = luminance(cpu::physical_sky_colour(&sky, direction(35.0, 40.0)));
+    let away = luminance(cpu::physical_sky_colour(&sky, direction(35.0, 220.0)));
+    let up = luminance(cpu::physical_sky_colour(&sky, Vector3::unit_y()));
+    assert!(near > 2.0 * away, "near {near} away {away}");
+    assert!(near > up && up > 0.0, "near {near} up {up}");
+
+    // A clear sky is blue looking away from the sun
+    let colour = cpu::physical_sky_colour(&sky, direction(45.0, 220.0));
+    assert!(colour.z > colour.x, "{colour:?}");
+
+    // Below the horizon continues the horizon rather than going black
+    let horizon = luminance(cpu::physical_sky_colour(&sky, direction(0.0, 100.0)));
+    let below = luminance(cpu::physical_sky_colour(&sky, direction(-10.0, 100.0)));
+    assert!(below > 0.5 * horizon, "horizon {horizon} below {below}");
+}
+
+#[test]
+fn hazy_sky_is_less_blue() {
+    let blueness = |turbidity| {
+        let sky = PhysicalSky::new(45f32.to_radians(), 0.0, turbidity, 1.0);
+        let colour = cpu::physical_sky_colour(&sky, direction(45.0, 180.0));
+        colour.z / colour.x
+    };
+    assert!(blueness(2.0) > blueness(8.0));
+}
+
+#[test]
+fn sun_dims_and_reddens_towards_the_horizon() {
+    let sun = |elevation: f32| {
+        let light = PhysicalSky::new(elevation.to_radians(), 0.0, 3.0, 1.0).sun();
+        assert_eq!(light.kind, Light::SUN);
+        Vector3::from(light.emission)
+    };
+    let (noon, evening) = (sun(60.0), sun(5.0));
+    assert!(evening.z < noon.z && evening.x < noon.x);
+    assert!(
+        evening.x / evening.z > noon.x / noon.z,
+        "{noon:?} {evening:?}"
+    );
+}
+
+#[test]
+fn sun_is_seen_directly() {
+    let sky = PhysicalSky::new(20f32.to_radians(), 0.0, 3.0, 1.0);
+    let sun = sky.sun();
+    let shading = Shading::new(vec![], vec![sun], Sky::Physical(sky));
+    let bindings = cpu::Bindings {
+        geometry: &Geometry::default(),
+        shading: &shading,
+    };
+
+    let camera = Camera::with_dimensions([1.0, 1.0]);
+    let ray = cpu::Ray {
+        pos: Vector3::zero(),
+        dir: Vector3::from(sky.sun_direction),
+    };
+    let mut state = cpu::init_sample_state(&camera, [0, 0], 0);
+    let colour = cpu::iterative_ray_colour(&camera, &bindings, &ray, &mut state);
+    let expected = Vector3::from(sun.emission) + cpu::sky_colour(&bindings, &ray);
+    assert!((colour - expected).magnitude() < 1e-3 * expected.magnitude());
+}
+
+/// A fully visible cone of half angle a at angle theta from the normal gives
+/// irradiance L pi sin^2 a cos theta, bouncing alone would almost never find it
+#[test]
+fn sun_lights_floor_with_few_samples() {
+    let (radiance, albedo, elevation) = (40000.0, 0.5, 30f32.to_radians());
+    let sun = Light::sun(
+        (-direction(30.0, 0.0)).into(),
+        PhysicalSky::SUN_ANGULAR_RADIUS,
+        [radiance; 3],
+    );
+    let shading = Shading::new(
+        vec![Material::lambertian([albedo; 3])],
+        vec![sun],
+        Sky::Gradient([0.0; 3]),
+    );
+    let bindings = cpu::Bindings {
+        geometry: &Geometry {
+            spheres: vec![Sphere::new([0.0, -1000.0, 0.0], 1000.0, 0)],
+            ..Default::default()
+        }
+        .with_bvh(),
+        shading: &shading,
+    };
+
+    let mut camera = Camera::with_dimensions([1.0, 1.0]);
+    camera.max_depth = 2;
+    camera.samples = 16;
+    let ray = cpu::Ray {
+        pos: Vector3::new(0.0, 0.5, 0.0),
+        dir: -Vector3::unit_y(),
+    };
+    let mut total = Vector3::zero();
+    for sample in 0..camera.samples {
+        let mut state = cpu::init_sample_state(&camera, [0, 0], sample);
+        total += cpu::iterative_ray_colour(&camera, &bindings, &ray, &mut state);
+    }
+    let colour = total / camera.samples as f32;
+
+    let irradiance =
+        radiance * PI * PhysicalSky::SUN_ANGULAR_RADIUS.sin().powi(2) * elevation.sin();
+    let expected = albedo / PI * irradiance;
+    let error = (colour - Vector3::new(expected, expected, expected)).magnitude();
+    assert!(
+        error < 0.01 * expected,
+        "expected {expected} but got {colour:?}"
+    );
+}
+
+#[test]
+fn physical_sky_pdf_integrates_to_one() {
+    let sky = PhysicalSky::new(10f32.to_radians(), 1.0, 4.0, 1.0);
+    let shading = Shading::new(vec![], vec![], Sky::Physical(sky));
+    let bindings = cpu::Bindings {
+        geometry: &Geometry::default(),
+        shading: &shading,
+    };
+
+    let (columns, rows) = (512, 256);
+    let mut integral = 0.0;
+    for row in 0..rows {
+        let theta = PI * (row as f32 + 0.5) / rows as f32;
+        for column in 0..columns {
+            let phi = TAU * (column as f32 + 0.5) / columns as f32;
+            let dir = Vector3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            );
+            let area = theta.sin() * PI * TAU / (rows * columns) as f32;
+            integral += cpu::sky_pdf(&bindings, dir) * area;
+        }
+    }
+    assert!((integral - 1.0).abs() < 0.01, "integral {integral}");
+}
+
+#[test]
+fn scene_physical_sky_adds_sun() {
+    let scene = Scene::parse(
+        b"(materials: [], spheres: [], physical_sky: (elevation: 30.0, azimuth: 90.0))",
+    )
+    .unwrap();
+    let shading = scene.shading();
+    assert!(matches!(shading.sky, Sky::Physical(_)));
+    assert_eq!(shading.lights.len(), 1);
+    let sun = shading.lights[0];
+    assert_eq!(sun.kind, Light::SUN);
+    assert!((Vector3::from(sun.direction) + direction(30.0, 90.0)).magnitude() < 1e-6);
+
+    let scene =
+        Scene::parse(b"(materials: [], spheres: [], physical_sky: (elevation: 30.0, sun: false))")
+            .unwrap();
+    assert!(scene.shading().lights.is_empty());
+
+    let err =
+        Scene::parse(b"(materials: [], spheres: [], physical_sky: (elevation: 95.0))").unwrap_err();
+    assert!(err.to_string().contains("physical_sky.elevation"), "{err}");
+}